@@ -0,0 +1,20 @@
+mod shapes {
+    pub struct Rect {
+        pub w: u32,
+        pub h: u32,
+    }
+
+    impl Rect {
+        #[inline(never)]
+        pub fn area(&self) -> u32 {
+            self.w * self.h
+        }
+    }
+}
+
+fn main() {
+    let v: Vec<i32> = vec![1, 2, 3];
+    let s = String::from("hi there");
+    let r = shapes::Rect { w: 3, h: 4 };
+    println!("{:?} {} {}", v, s, r.area());
+}
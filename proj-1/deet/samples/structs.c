@@ -0,0 +1,34 @@
+#include <stdio.h>
+
+struct point {
+    int x;
+    int y;
+};
+
+struct node {
+    int val;
+    struct node *next;
+};
+
+int numbers[5] = {1, 2, 3, 4, 5};
+const char *greeting = "hello";
+
+int sum_points(struct point *points, int n) {
+    int total = 0;
+    for (int i = 0; i < n; i++) {
+        total += points[i].x + points[i].y;
+    }
+    return total;
+}
+
+int main() {
+    struct point points[3] = {{1, 2}, {3, 4}, {5, 6}};
+    struct node second = {20, NULL};
+    struct node first = {10, &second};
+    struct node *head = &first;
+    double ratio = 0.5;
+    char letter = 'z';
+    int total = sum_points(points, 3);
+    printf("%d %d %f %c %s\n", total, head->next->val, ratio, letter, greeting);
+    return 0;
+}
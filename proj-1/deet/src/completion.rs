@@ -0,0 +1,159 @@
+//! Tab completion of command lines: command names, then depending on the command, function and
+//! source file names for `break`, variables in scope for `print`, and paths for `run`.
+
+use crate::dwarf_data::DwarfData;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::path::Path;
+
+/// Commands offered when completing the first word of a line.
+const COMMANDS: &[&str] = &[
+    "advance", "alias", "backtrace", "break", "call", "catch", "checkpoint", "continue", "define",
+    "delete", "display", "dprintf", "handle", "info", "layout", "next", "print", "quit", "record", "replay",
+    "restart", "reverse-continue", "run", "set", "show", "source", "step", "tbreak", "trace",
+    "tty", "tui", "undisplay", "unset", "until", "watch",
+];
+
+pub struct DeetHelper {
+    functions: Vec<String>,
+    files: Vec<String>, // Base names, as typed in `break file.c:12`
+    globals: Vec<String>,
+    // Local variables and parameters of the function the inferior is stopped in
+    locals: Vec<String>,
+    // Aliases and user-defined commands
+    user_commands: Vec<String>,
+    filenames: FilenameCompleter,
+}
+
+impl DeetHelper {
+    pub fn new(debug_data: Option<&DwarfData>) -> DeetHelper {
+        let mut files: Vec<String> = debug_data
+            .map(|data| data.file_names())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|path| Some(Path::new(path).file_name()?.to_string_lossy().to_string()))
+            .collect();
+        files.sort();
+        files.dedup();
+        DeetHelper {
+            functions: debug_data.map(|data| data.function_names()).unwrap_or_default(),
+            files,
+            globals: debug_data.map(|data| data.global_variable_names()).unwrap_or_default(),
+            locals: Vec::new(),
+            user_commands: Vec::new(),
+            filenames: FilenameCompleter::new(),
+        }
+    }
+
+    /// Sets the variables in scope where the inferior is stopped, which `print` completes.
+    pub fn set_locals(&mut self, locals: Vec<String>) {
+        self.locals = locals;
+    }
+
+    pub fn set_user_commands(&mut self, names: Vec<String>) {
+        self.user_commands = names;
+    }
+
+    fn variables(&self) -> impl Iterator<Item = &String> {
+        self.locals.iter().chain(self.globals.iter())
+    }
+}
+
+/// Returns the candidates from `names` that start with `prefix`, sorted and without duplicates.
+fn matching<'a>(names: impl Iterator<Item = &'a String>, prefix: &str, suffix: &str) -> Vec<Pair> {
+    let mut names: Vec<&String> = names.filter(|name| name.starts_with(prefix)).collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .map(|name| Pair { display: name.clone(), replacement: format!("{}{}", name, suffix) })
+        .collect()
+}
+
+impl Completer for DeetHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        let word_start = before.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &before[word_start..];
+        let command = match before[..word_start].split_whitespace().next() {
+            Some(command) => command,
+            None => {
+                let names = COMMANDS.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+                return Ok((0, matching(names.iter().chain(self.user_commands.iter()), word, " ")));
+            }
+        };
+        match command {
+            "b" | "break" | "tbreak" | "until" | "advance" | "dprintf" => {
+                // Line numbers and addresses have nothing to complete
+                if word.contains(':') || word.starts_with('*') {
+                    return Ok((pos, Vec::new()));
+                }
+                let mut candidates = matching(self.functions.iter(), word, " ");
+                candidates.extend(matching(self.files.iter(), word, ":"));
+                Ok((word_start, candidates))
+            }
+            "p" | "print" | "call" | "watch" | "set" | "display" => {
+                // Complete the identifier under the cursor, e.g. `count` in `p *count`
+                let ident_start = before
+                    .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .map_or(0, |i| i + 1);
+                let ident = &before[ident_start..];
+                if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+                    return Ok((pos, Vec::new()));
+                }
+                let mut candidates = matching(self.variables(), ident, "");
+                if command == "call" {
+                    candidates.extend(matching(self.functions.iter(), ident, "("));
+                }
+                Ok((ident_start, candidates))
+            }
+            "r" | "run" | "record" | "source" => self.filenames.complete(line, pos, ctx),
+            _ => Ok((pos, Vec::new())),
+        }
+    }
+}
+
+impl Hinter for DeetHelper {
+    type Hint = String;
+}
+
+impl Highlighter for DeetHelper {}
+
+impl Validator for DeetHelper {}
+
+impl Helper for DeetHelper {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rustyline::history::DefaultHistory;
+
+    fn complete(helper: &DeetHelper, line: &str) -> (usize, Vec<String>) {
+        let history = DefaultHistory::new();
+        let (start, candidates) = helper.complete(line, line.len(), &Context::new(&history)).unwrap();
+        (start, candidates.into_iter().map(|pair| pair.replacement).collect())
+    }
+
+    #[test]
+    fn test_completion() {
+        let mut helper = DeetHelper::new(None);
+        helper.functions = vec!["add".to_string(), "main".to_string(), "make_big".to_string()];
+        helper.files = vec!["calls.c".to_string()];
+        helper.globals = vec!["counter".to_string()];
+        helper.set_locals(vec!["msg".to_string(), "count".to_string()]);
+        helper.set_user_commands(vec!["pp".to_string()]);
+        assert_eq!(complete(&helper, "bre"), (0, vec!["break ".to_string()]));
+        assert_eq!(complete(&helper, "p"), (0, vec!["pp ".to_string(), "print ".to_string()]));
+        assert_eq!(complete(&helper, "break ma"), (6, vec!["main ".to_string(), "make_big ".to_string()]));
+        assert_eq!(complete(&helper, "b cal"), (2, vec!["calls.c:".to_string()]));
+        assert_eq!(complete(&helper, "b calls.c:1").1, Vec::<String>::new());
+        assert_eq!(complete(&helper, "p *cou"), (3, vec!["count".to_string(), "counter".to_string()]));
+        assert_eq!(complete(&helper, "print msg + cou").1, vec!["count", "counter"]);
+        assert_eq!(complete(&helper, "call ad"), (5, vec!["add(".to_string()]));
+    }
+}
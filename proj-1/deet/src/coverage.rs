@@ -0,0 +1,153 @@
+//! Line coverage collected by `deet coverage`, which traps once at every address of the line
+//! tables, and its reports: an lcov tracefile and sources annotated with the lines that ran.
+
+use crate::dwarf_data::File;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+
+#[derive(Default)]
+pub struct Coverage {
+    // Whether each line with code ran, by source file path and line number
+    lines: BTreeMap<String, BTreeMap<usize, bool>>,
+    // (line, name, address) of the functions defined in each source file
+    functions: BTreeMap<String, Vec<(usize, String, usize)>>,
+    // The lines whose code starts at each address
+    addrs: HashMap<usize, Vec<(String, usize)>>,
+    hit_addrs: HashSet<usize>,
+}
+
+impl Coverage {
+    pub fn new(files: &[&File]) -> Coverage {
+        let mut coverage = Coverage::default();
+        for file in files {
+            // Names relative to the compilation directory would only be found from there
+            let path = file.path().to_string_lossy().to_string();
+            // Line 0 is code the compiler made up, and address 0 code the linker threw away
+            for line in file.lines.iter().filter(|line| line.number > 0 && line.address > 0) {
+                coverage.lines.entry(path.clone()).or_default().insert(line.number, false);
+                coverage.addrs.entry(line.address).or_default().push((path.clone(), line.number));
+            }
+            for func in file.functions.iter().filter(|func| func.text_length > 0 && func.line_number > 0) {
+                let function = (func.line_number, func.qualified_name.clone(), func.address);
+                coverage.functions.entry(path.clone()).or_default().push(function);
+            }
+        }
+        for functions in coverage.functions.values_mut() {
+            functions.sort();
+            functions.dedup();
+        }
+        coverage
+    }
+
+    /// Returns the addresses to trap at, in order.
+    pub fn addresses(&self) -> Vec<usize> {
+        let mut addrs: Vec<usize> = self.addrs.keys().copied().collect();
+        addrs.sort();
+        addrs
+    }
+
+    /// Marks the lines starting at `addr` as run.
+    pub fn hit(&mut self, addr: usize) {
+        self.hit_addrs.insert(addr);
+        for (file, number) in self.addrs.get(&addr).into_iter().flatten() {
+            if let Some(hit) = self.lines.get_mut(file).and_then(|lines| lines.get_mut(number)) {
+                *hit = true;
+            }
+        }
+    }
+
+    /// Returns the report in the lcov tracefile format (`geninfo(1)`). Each line only traps the
+    /// first time, so run lines have a count of 1.
+    pub fn lcov(&self, test_name: &str) -> String {
+        let mut info = String::new();
+        for (file, lines) in &self.lines {
+            writeln!(info, "TN:{}", test_name).unwrap();
+            writeln!(info, "SF:{}", file).unwrap();
+            let functions = self.functions.get(file).map(Vec::as_slice).unwrap_or_default();
+            for (line, name, _) in functions {
+                writeln!(info, "FN:{},{}", line, name).unwrap();
+            }
+            for (_, name, addr) in functions {
+                writeln!(info, "FNDA:{},{}", self.hit_addrs.contains(addr) as usize, name).unwrap();
+            }
+            let functions_hit = functions.iter().filter(|(_, _, addr)| self.hit_addrs.contains(addr)).count();
+            writeln!(info, "FNF:{}", functions.len()).unwrap();
+            writeln!(info, "FNH:{}", functions_hit).unwrap();
+            for (number, &hit) in lines {
+                writeln!(info, "DA:{},{}", number, hit as usize).unwrap();
+            }
+            writeln!(info, "LF:{}", lines.len()).unwrap();
+            writeln!(info, "LH:{}", lines.values().filter(|&&hit| hit).count()).unwrap();
+            writeln!(info, "end_of_record").unwrap();
+        }
+        info
+    }
+
+    /// Returns the lines of the summary: how many lines of each file ran, followed by its source
+    /// marked like gcov does: `#####` for lines that did not run, `-` for lines without code, or
+    /// why it could not be read.
+    pub fn summary(&self) -> Vec<String> {
+        let mut summary = Vec::new();
+        let (mut total_hit, mut total) = (0, 0);
+        for (file, lines) in &self.lines {
+            let hit = lines.values().filter(|&&hit| hit).count();
+            summary.push(format!("{}: {} of {} lines run ({})", file, hit, lines.len(), percent(hit, lines.len())));
+            total_hit += hit;
+            total += lines.len();
+            let source = match std::fs::read_to_string(file) {
+                Ok(source) => source,
+                Err(err) => {
+                    summary.push(format!("Could not read {}: {}", file, err));
+                    continue;
+                }
+            };
+            for (i, text) in source.lines().enumerate() {
+                let mark = match lines.get(&(i + 1)) {
+                    Some(true) => "+",
+                    Some(false) => "#####",
+                    None => "-",
+                };
+                summary.push(format!("{:>9}:{:>5}:{}", mark, i + 1, text));
+            }
+        }
+        summary.push(format!("Total: {} of {} lines run ({})", total_hit, total, percent(total_hit, total)));
+        summary
+    }
+}
+
+fn percent(count: usize, total: usize) -> String {
+    format!("{:.1}%", 100.0 * count as f64 / total.max(1) as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dwarf_data::Line;
+
+    #[test]
+    fn test_coverage() {
+        let line = |number, address| Line { file: "a.c".to_string(), number, address };
+        let file = File {
+            name: "a.c".to_string(),
+            comp_dir: "/src".to_string(),
+            lines: vec![line(3, 0x401000), line(4, 0x401008), line(4, 0x401010), line(6, 0x401018), line(0, 0x401020)],
+            ..Default::default()
+        };
+        let mut coverage = Coverage::new(&[&file]);
+        assert_eq!(coverage.addresses(), vec![0x401000, 0x401008, 0x401010, 0x401018]);
+        coverage.hit(0x401000);
+        coverage.hit(0x401010);
+        assert_eq!(
+            coverage.lcov("a"),
+            "TN:a\nSF:/src/a.c\nFNF:0\nFNH:0\nDA:3,1\nDA:4,1\nDA:6,0\nLF:3\nLH:2\nend_of_record\n"
+        );
+        assert_eq!(
+            coverage.summary(),
+            vec![
+                "/src/a.c: 2 of 3 lines run (66.7%)",
+                "Could not read /src/a.c: No such file or directory (os error 2)",
+                "Total: 2 of 3 lines run (66.7%)",
+            ]
+        );
+    }
+}
@@ -1,50 +1,154 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use crate::completion::DeetHelper;
+use crate::coverage::Coverage;
 use crate::debugger_command::DebuggerCommand;
-use crate::inferior::{Inferior, Status};
+use crate::inferior::{self, Inferior, RunEnvironment, Status};
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use rustyline::history::FileHistory;
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
+use crate::dwarf_data::{DwarfData, Error as DwarfError, Location, Variable, DEFAULT_DEBUG_FILE_DIRECTORY};
+use crate::expr;
+use crate::procfs;
+use crate::profiler::Profile;
+use crate::record::{LogMark, SyscallLog};
+use crate::settings::{self, Pager, Settings};
+use crate::shared_libraries::{self, SharedLibrary};
+use crate::syscalls;
+use crate::tui::{Tui, View};
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use regex::Regex;
 
 pub struct BreakPoint {
     pub addr: usize,
     pub origin_byte: u8,
+    pub condition: Option<String>,
+    pub internal: bool, // set by the debugger itself rather than the user
+    // Deleted once hit (tbreak), or for internal ones (until, advance) when the inferior next stops
+    pub temporary: bool,
+    // `"format", args...` printed when hit, after which the inferior is resumed (dprintf)
+    pub dprintf: Option<String>,
 }
 
+/// A breakpoint whose location can't be resolved yet, e.g. in a library that is not loaded.
+struct PendingBreakpoint {
+    location: String,
+    condition: Option<String>,
+    temporary: bool,
+    dprintf: Option<String>,
+}
+
+/// A software watchpoint: the expression is re-evaluated after every instruction while
+/// watchpoints are set, and execution stops when its value changes.
+pub struct WatchPoint {
+    pub expr: String,
+    pub value: Option<(Vec<u8>, String)>, // raw bytes for comparison, and the printed form
+    pub scope: Option<(usize, usize)>, // function address and CFA for watched locals
+}
+
+/// An expression printed every time the inferior stops, while it is in scope.
+pub struct Display {
+    pub number: usize,
+    pub expr: String,
+    pub function: Option<usize>, // address of the function whose locals the expression uses
+}
+
+/// A forked copy of the inferior, stopped at the point it was taken, which `restart` and
+/// `reverse-continue` go back to. Positions are counted in breakpoint hits since the start of the
+/// program, which are the same on every run of a deterministic program.
+pub struct Checkpoint {
+    pub process: Inferior,
+    pub pc: usize,
+    pub hits: usize,
+    pub at_hit: bool, // taken while stopped at breakpoint hit number `hits`
+    pub internal: bool, // taken automatically for reverse-continue
+    pub libraries: Vec<String>, // shared libraries loaded at the time
+    pub log_mark: Option<LogMark>, // position in the syscall log being recorded or replayed
+}
+
+/// Number of breakpoint hits between the checkpoints taken automatically for reverse-continue.
+const CHECKPOINT_INTERVAL: usize = 10;
+const MAX_INTERNAL_CHECKPOINTS: usize = 32;
+
 pub struct Debugger {
     target: String,
     history_path: String,
-    readline: Editor<(), FileHistory>,
+    readline: Editor<DeetHelper, FileHistory>,
     inferior: Option<Inferior>,
     debug_data: Option<DwarfData>,
     breakpoints: HashMap<usize, BreakPoint>,
+    watchpoints: Vec<WatchPoint>,
+    displays: Vec<Display>,
+    next_display: usize,
+    shared_libraries: Vec<SharedLibrary>,
+    // Breakpoint locations (and conditions) in code that has not been loaded yet
+    pending_breakpoints: Vec<PendingBreakpoint>,
+    debug_file_directories: Vec<String>,
+    index_cache: bool, // Whether the index of debug info is cached on disk
+    // Arguments of the program, kept from one `run` to the next, and where it runs
+    args: Vec<String>,
+    run_env: RunEnvironment,
+    checkpoints: Vec<Checkpoint>,
+    // Position of the inferior: the number of breakpoint hits so far, and whether it is still
+    // stopped at the last one
+    hits: usize,
+    at_hit: bool,
+    // System calls made during the last `record`ed run
+    recording: Option<Rc<RefCell<SyscallLog>>>,
+    // System call numbers of each `catch syscall`, where an empty list catches all of them
+    catchpoints: Vec<Vec<u64>>,
+    trace_syscalls: bool,
+    settings: Settings,
+    aliases: HashMap<String, String>,
+    // Commands created with `define`, by name
+    user_commands: HashMap<String, Vec<String>>,
+    // Lines from init files and user-defined commands, which run before more input is read
+    pending_lines: VecDeque<String>,
+    // ./.deetrc, which runs after ~/.deetrc if that puts it in the auto-load safe path
+    local_init_file: Option<PathBuf>,
+    tui: Option<Tui>,
 }
 
+/// Lines a user-defined command may expand to before it is assumed to be endlessly recursive.
+const MAX_PENDING_LINES: usize = 10000;
+
 impl Debugger {
     /// Initializes the debugger.
-    pub fn new(target: &str) -> Debugger {
-        // TODO (milestone 3): initialize the DwarfData
-
+    pub fn new(target: &str, index_cache: bool) -> Debugger {
         let history_path = format!("{}/.deet_history", std::env::var("HOME").unwrap());
-        let mut readline = Editor::<(), FileHistory>::new()
+        let mut readline = Editor::<DeetHelper, FileHistory>::new()
             .expect("failed to create readline");
         // Attempt to load history from ~/.deet_history if it exists
         let _ = readline.load_history(&history_path);
         // init debug info
-        let debug_data = match DwarfData::from_file(target) {
-            Ok(val) => Some(val),
+        let debug_file_directories = vec![DEFAULT_DEBUG_FILE_DIRECTORY.to_string()];
+        let debug_data = match DwarfData::from_file(target, &debug_file_directories, index_cache) {
+            Ok(val) => {
+                if !val.has_debug_info() {
+                    println!("(No debugging symbols found in {})", target);
+                }
+                Some(val)
+            }
             Err(DwarfError::ErrorOpeningFile) => {
                 println!("Could not open file {}", target);
                 std::process::exit(1);
             }
             Err(DwarfError::DwarfFormatError(err)) => {
-                println!("Could not debugging symbols from {}: {:?}", target, err);
-                std::process::exit(1);
+                println!("Could not read debugging symbols from {}: {}", target, err);
+                // Function names and breakpoints on them still work with the ELF symbol table
+                match DwarfData::from_symbol_table(target) {
+                    Ok(val) => Some(val),
+                    Err(_) => std::process::exit(1),
+                }
             }
         };
 
-        // for test
-        debug_data.as_ref().unwrap().print();
+        readline.set_helper(Some(DeetHelper::new(debug_data.as_ref())));
 
         Debugger {
             target: target.to_string(),
@@ -53,35 +157,112 @@ impl Debugger {
             inferior: None,
             debug_data,
             breakpoints: HashMap::new(),
+            watchpoints: Vec::new(),
+            displays: Vec::new(),
+            next_display: 1,
+            shared_libraries: Vec::new(),
+            pending_breakpoints: Vec::new(),
+            debug_file_directories,
+            index_cache,
+            args: Vec::new(),
+            run_env: RunEnvironment::default(),
+            checkpoints: Vec::new(),
+            hits: 0,
+            at_hit: false,
+            recording: None,
+            catchpoints: Vec::new(),
+            trace_syscalls: false,
+            settings: Settings::default(),
+            aliases: HashMap::new(),
+            user_commands: HashMap::new(),
+            pending_lines: settings::home_init_file()
+                .and_then(|path| std::fs::read_to_string(path).ok())
+                .map(|text| text.lines().map(str::to_string).collect())
+                .unwrap_or_default(),
+            local_init_file: settings::local_init_file(),
+            tui: None,
         }
     }
 
+    /// Runs `line` once the init files have been read, before reading commands from the user.
+    pub fn queue_command(&mut self, line: &str) {
+        self.pending_lines.push_back(line.to_string());
+    }
+
     pub fn run(&mut self) {
         loop {
-            match self.get_next_command() {
-                DebuggerCommand::Run(args) => {
-                    self.kill();
-
-                    if let Some(inferior) = Inferior::new(&self.target, &args) {
-                        // update breakpoints 
-                        self.breakpoints
-                            .values_mut()
-                            .filter(|bp| bp.origin_byte == 0)
-                            .for_each(|bp| {
-                                if let Ok(origin_byte) = inferior.write_byte(bp.addr, 0xcc) {
-                                    bp.origin_byte = origin_byte;
-                                }
-                                else {
-                                    println!("write byte: {:#x} error", 0xcc)
-                                }
-                            });
-
-                        self.inferior = Some(inferior);
-                        self.wakeup_wait();
-                    } 
-                    else {
-                        println!("Error starting subprocess");
+            let cmd = self.get_next_command();
+            match cmd {
+                DebuggerCommand::Run(words) => {
+                    if !words.is_empty() && !self.set_args(&words) {
+                        continue;
+                    }
+                    let args = self.args.clone();
+                    self.start(&args, None);
+                },
+                DebuggerCommand::Record(words) => {
+                    if !words.is_empty() && !self.set_args(&words) {
+                        continue;
+                    }
+                    let args = self.args.clone();
+                    let log = Rc::new(RefCell::new(SyscallLog::record(&args)));
+                    self.recording = Some(log.clone());
+                    self.start(&args, Some(log));
+                },
+                DebuggerCommand::Replay => {
+                    let log = match self.recording.as_ref() {
+                        Some(recording) => recording.borrow().replay(),
+                        None => {
+                            println!("No recording to replay; use \"record\" to make one.");
+                            continue;
+                        }
+                    };
+                    let args = log.args.clone();
+                    self.start(&args, Some(Rc::new(RefCell::new(log))));
+                },
+                DebuggerCommand::CatchSyscall(names) => {
+                    let numbers: Option<Vec<u64>> = names.iter().map(|name| syscalls::number(name)).collect();
+                    let numbers = match numbers {
+                        Some(numbers) => numbers,
+                        None => {
+                            let name = names.iter().find(|name| syscalls::number(name).is_none()).unwrap();
+                            println!("Unknown syscall name '{}'.", name);
+                            continue;
+                        }
+                    };
+                    let description = if numbers.is_empty() {
+                        "any syscall".to_string()
+                    } else {
+                        let names: Vec<String> = numbers.iter()
+                            .map(|&n| format!("'{}' [{}]", syscalls::display_name(n), n))
+                            .collect();
+                        format!("syscall {}", names.join(" "))
+                    };
+                    self.catchpoints.push(numbers);
+                    self.update_syscall_tracing();
+                    println!("set catchpoint {} ({})", self.catchpoints.len() - 1, description);
+                },
+                DebuggerCommand::TraceSyscalls(on) => {
+                    self.trace_syscalls = on;
+                    self.update_syscall_tracing();
+                },
+                DebuggerCommand::InfoRecord => match self.recording.as_ref() {
+                    Some(recording) => {
+                        let recording = recording.borrow();
+                        println!("Recorded {} system calls running {} {}", recording.records.len(),
+                            self.target, recording.args.join(" "));
+                        let mut pager = Pager::new(&self.settings);
+                        for (i, record) in recording.records.iter().enumerate() {
+                            let args: Vec<String> =
+                                record.args.iter().map(|arg| format!("{:#x}", arg)).collect();
+                            let line = format!("{:>6}  {}({}) = {}", i, syscalls::display_name(record.number),
+                                args.join(", "), record.result);
+                            if !pager.println(&line) {
+                                break;
+                            }
+                        }
                     }
+                    None => println!("No recording."),
                 },
                 DebuggerCommand::Continue => {
                     if self.inferior.is_none() {
@@ -97,73 +278,651 @@ impl Debugger {
                         continue;
                     }
 
-                    match self.inferior.as_ref().unwrap().backtrace(&self.debug_data) {
-                        Ok(_) => continue,
+                    match self.inferior.as_ref().unwrap().backtrace(&self.debug_data, &self.shared_libraries) {
+                        Ok(lines) => {
+                            let mut pager = Pager::new(&self.settings);
+                            for line in lines {
+                                if !pager.println(&line) {
+                                    break;
+                                }
+                            }
+                        },
                         Err(_) => println!("failed to backtrace")
                     }
                 },
-                DebuggerCommand::Break(arg) => {
-                    if arg.starts_with("*") {
-                        if let Some(addr) = parse_addr(&arg[1..]) {
-                            self.set_bp(addr);
-                        } 
-                        else {
-                            println!("parse addr error");
+                DebuggerCommand::Break(arg, condition, temporary) => {
+                    if let Some(condition) = condition.as_ref() {
+                        if let Err(err) = expr::parse(condition, &|_| false) {
+                            println!("invalid condition: {}", err);
+                            continue;
                         }
                     }
+                    let addrs = self.parse_location(&arg);
+                    // A function may be defined in a library loaded later, but not a file or line
+                    let is_function_name = !arg.starts_with(|c: char| c.is_ascii_digit())
+                        && arg.chars().all(|c| c.is_alphanumeric() || c == '_' || c == ':');
+                    if addrs.is_empty() && is_function_name && shared_libraries::interpreter(&self.target).is_some() {
+                        println!("Function \"{}\" not defined, breakpoint pending on future shared library load.", arg);
+                        let pending = PendingBreakpoint { location: arg, condition, temporary, dprintf: None };
+                        self.pending_breakpoints.push(pending);
+                        continue;
+                    }
+                    if addrs.is_empty() {
+                        println!("No location \"{}\" found.", arg);
+                        continue;
+                    }
+                    for addr in addrs {
+                        self.set_bp(addr, condition.clone(), temporary, None);
+                    }
+                    self.refresh_tui();
+                },
+                DebuggerCommand::Delete(pending_only) => {
+                    self.pending_breakpoints.clear();
+                    if !pending_only {
+                        self.delete_breakpoints();
+                    }
+                    self.refresh_tui();
+                },
+                DebuggerCommand::InfoBreakpoints => self.info_breakpoints(),
+                DebuggerCommand::Dprintf(location, format) => {
+                    if let Err(err) = expr::parse_format_args(&format) {
+                        println!("{}", err);
+                        continue;
+                    }
+                    let addrs = self.parse_location(&location);
+                    if addrs.is_empty() {
+                        println!("No location \"{}\" found.", location);
+                        continue;
+                    }
+                    for addr in addrs {
+                        self.set_bp(addr, None, false, Some(format.clone()));
+                    }
+                    self.refresh_tui();
+                },
+                DebuggerCommand::Print(src) => {
+                    match self.evaluate(&src) {
+                        Ok((_, formatted)) => println!("{} = {}", src, formatted),
+                        Err(expr::Error::OptimizedOut) => println!("{} = <optimized out>", src),
+                        Err(err) => println!("{}", err),
+                    }
+                },
+                DebuggerCommand::Call(src) => {
+                    // Like print, except that nothing is shown for functions returning void
+                    match self.evaluate(&src) {
+                        Ok((value, _)) if value.is_void() => {}
+                        Ok((_, formatted)) => println!("{} = {}", src, formatted),
+                        Err(err) => println!("{}", err),
+                    }
+                },
+                DebuggerCommand::Watch(src) => {
+                    if let Err(err) = expr::parse(&src, &|_| false) {
+                        println!("{}", err);
+                        continue;
+                    }
+                    // The initial value may not be available yet, e.g. before `run`
+                    let value = self.evaluate(&src).ok().map(|(value, text)| (value.bytes, text));
+                    let scope = self.eval_context().ok().and_then(|ctx| ctx.local_scope(&src));
+                    self.watchpoints.push(WatchPoint { expr: src.clone(), value, scope });
+                    println!("set watchpoint {}: {}", self.watchpoints.len() - 1, src);
+                },
+                DebuggerCommand::Until(Some(ref location)) | DebuggerCommand::Advance(ref location) => {
+                    if self.inferior.is_none() {
+                        println!("The program is not being run.");
+                        continue;
+                    }
+                    let addrs = self.parse_location(location);
+                    if addrs.is_empty() {
+                        println!("No location \"{}\" found.", location);
+                        continue;
+                    }
+                    self.run_until(&addrs, matches!(cmd, DebuggerCommand::Until(_)));
+                },
+                DebuggerCommand::Step | DebuggerCommand::Next | DebuggerCommand::Until(None) => {
+                    if self.inferior.is_none() {
+                        println!("no child start");
+                    }
                     else {
-                        if let Some(dbg_data) = self.debug_data.as_ref() {
-                            let source_file = self.target.to_owned() + ".c";
-                            if let Ok(line) =  arg.parse::<usize>() {
-                                if let Some(addr) = dbg_data
-                                .get_addr_for_line(Some(&source_file), line) {
-                                    self.set_bp(addr);
-                                }
-                                else {
-                                    println!("get addr for line error");
-                                }
-                            }
-                            else {
-                                if let Some(addr) = dbg_data
-                                .get_addr_for_function(Some(&source_file), &arg) {
-                                    self.set_bp(addr);
-                                }
-                                else {
-                                    println!("get addr for function");
-                                }
+                        let into = matches!(cmd, DebuggerCommand::Step);
+                        self.step_line(into, matches!(cmd, DebuggerCommand::Until(None)));
+                    }
+                },
+                DebuggerCommand::InfoFunctions(pattern) => {
+                    if let Some(regex) = search_regex(pattern.as_deref()) {
+                        self.page(self.info_functions(pattern.as_deref(), &regex));
+                    }
+                },
+                DebuggerCommand::InfoVariables(pattern) => {
+                    if let Some(regex) = search_regex(pattern.as_deref()) {
+                        self.page(self.info_variables(pattern.as_deref(), &regex));
+                    }
+                },
+                DebuggerCommand::InfoTypes(pattern) => {
+                    if let Some(regex) = search_regex(pattern.as_deref()) {
+                        let header = match pattern {
+                            Some(pattern) => format!("All types matching regular expression \"{}\":", pattern),
+                            None => "All defined types:".to_string(),
+                        };
+                        let types = self.debug_data.iter().flat_map(|data| data.search_types(&regex));
+                        self.page(std::iter::once(header).chain(types.map(|name| format!("\t{}", name))).collect());
+                    }
+                },
+                DebuggerCommand::InfoLine(location) => self.info_line(location.as_deref()),
+                DebuggerCommand::InfoAddress(name) => self.info_address(&name),
+                DebuggerCommand::InfoScope(location) => self.info_scope(&location),
+                DebuggerCommand::InfoSharedLibrary => {
+                    if self.shared_libraries.is_empty() {
+                        println!("No shared libraries loaded at this time.");
+                        continue;
+                    }
+                    println!("{:<20}{:<20}{:<12}Shared Object Library", "From", "To", "Syms Read");
+                    for lib in &self.shared_libraries {
+                        let syms = if lib.debug_data.is_some() { "Yes" } else { "No" };
+                        println!("{:<#20x}{:<#20x}{:<12}{}", lib.start, lib.end, syms, lib.name);
+                    }
+                },
+                DebuggerCommand::Checkpoint => {
+                    if self.inferior.is_none() {
+                        println!("The program is not being run.");
+                        continue;
+                    }
+                    if let Some(pid) = self.take_checkpoint(false) {
+                        let count = self.checkpoints.iter().filter(|c| !c.internal).count();
+                        println!("checkpoint {}: fork returned pid {}.", count, pid);
+                    }
+                },
+                DebuggerCommand::Restart(number) => {
+                    let index = self.checkpoints.iter()
+                        .enumerate()
+                        .filter(|(_, c)| !c.internal)
+                        .nth(number.wrapping_sub(1))
+                        .map(|(i, _)| i);
+                    match index {
+                        Some(index) => {
+                            if self.restore_checkpoint(index) {
+                                self.print_stop_location(self.checkpoints[index].pc);
                             }
                         }
+                        None => println!("Not found: checkpoint {}", number),
+                    }
+                },
+                DebuggerCommand::ReverseContinue => self.reverse_continue(),
+                DebuggerCommand::InfoCheckpoints => {
+                    let checkpoints: Vec<&Checkpoint> =
+                        self.checkpoints.iter().filter(|c| !c.internal).collect();
+                    if checkpoints.is_empty() {
+                        println!("No checkpoints.");
+                    }
+                    for (i, checkpoint) in checkpoints.iter().enumerate() {
+                        println!("{} process {} at {}", i + 1, checkpoint.process.pid(),
+                            self.describe_location(checkpoint.pc));
+                    }
+                },
+                DebuggerCommand::SetVar(src) => {
+                    if let Err(err) = self.evaluate(&src) {
+                        println!("{}", err);
+                    }
+                },
+                DebuggerCommand::SetOption(name, value) => self.set_option(&name, &value),
+                DebuggerCommand::UnsetEnvironment(name) => self.run_env.unset_var(name.as_deref()),
+                DebuggerCommand::ShowOption(name) => self.show_option(&name),
+                DebuggerCommand::Handle(name, keywords) => {
+                    let signal = match settings::parse_signal(&name) {
+                        Some(signal) => signal,
+                        None => {
+                            println!("Only signals 1-31 are valid as numeric signals.");
+                            continue;
+                        }
+                    };
+                    match self.settings.handle(signal, &keywords) {
+                        Ok(()) => {
+                            println!("{}", settings::signal_header());
+                            println!("{}", settings::format_signal(signal, self.settings.signal_action(signal)));
+                        }
+                        Err(err) => println!("{}", err),
+                    }
+                },
+                DebuggerCommand::InfoSignals(name) => {
+                    let signals: Vec<Signal> = match name.as_deref().map(settings::parse_signal) {
+                        Some(Some(signal)) => vec![signal],
+                        Some(None) => {
+                            println!("Only signals 1-31 are valid as numeric signals.");
+                            continue;
+                        }
+                        None => Signal::iterator().collect(),
+                    };
+                    let mut pager = Pager::new(&self.settings);
+                    pager.println(&settings::signal_header());
+                    for signal in signals {
+                        if !pager.println(&settings::format_signal(signal, self.settings.signal_action(signal))) {
+                            break;
+                        }
                     }
                 },
+                DebuggerCommand::Display(None) => self.print_displays(),
+                DebuggerCommand::Display(Some(src)) => {
+                    if let Err(err) = expr::parse(&src, &|_| false) {
+                        println!("{}", err);
+                        continue;
+                    }
+                    let function = self.eval_context().ok()
+                        .and_then(|ctx| ctx.local_scope(&src))
+                        .map(|(function, _)| function);
+                    let display = Display { number: self.next_display, expr: src, function };
+                    self.next_display += 1;
+                    if self.inferior.is_some() {
+                        self.print_display(&display);
+                    }
+                    self.displays.push(display);
+                }
+                DebuggerCommand::Undisplay(numbers) => {
+                    if numbers.is_empty() {
+                        self.displays.clear();
+                    }
+                    for number in numbers {
+                        match self.displays.iter().position(|display| display.number == number) {
+                            Some(index) => {
+                                self.displays.remove(index);
+                            }
+                            None => println!("No display number {}.", number),
+                        }
+                    }
+                }
+                DebuggerCommand::InfoDisplay => {
+                    if self.displays.is_empty() {
+                        println!("There are no auto-display expressions now.");
+                        continue;
+                    }
+                    println!("Auto-display expressions now in effect:");
+                    println!("Num Expression");
+                    for display in &self.displays {
+                        println!("{:<4}{}", format!("{}:", display.number), display.expr);
+                    }
+                }
+                DebuggerCommand::InfoProc
+                | DebuggerCommand::InfoProcMappings
+                | DebuggerCommand::InfoProcFds
+                | DebuggerCommand::InfoAuxv => {
+                    let inferior = match self.inferior.as_ref() {
+                        Some(inferior) => inferior,
+                        None => {
+                            println!("The program is not being run.");
+                            continue;
+                        }
+                    };
+                    match cmd {
+                        DebuggerCommand::InfoProc => self.info_proc(inferior.pid()),
+                        DebuggerCommand::InfoProcMappings => self.info_proc_mappings(inferior.pid()),
+                        DebuggerCommand::InfoProcFds => self.info_proc_fds(inferior.pid()),
+                        _ => self.info_auxv(inferior),
+                    }
+                }
+                DebuggerCommand::Tui(enable) => {
+                    if !enable {
+                        self.tui = None;
+                        continue;
+                    }
+                    if !std::io::stdout().is_terminal() {
+                        println!("Cannot enable the TUI when output is not a terminal");
+                        continue;
+                    }
+                    if self.tui.is_none() {
+                        self.tui = Some(Tui::new());
+                    }
+                    self.refresh_tui();
+                }
                 DebuggerCommand::Quit => {
                     self.kill();
+                    self.delete_checkpoints();
                     return;
                 }
             }
         }
     }
 
-    /// This function prompts the user to enter a command, and continues re-prompting until the user
-    /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
-    ///
-    /// You don't need to read, understand, or modify this function.
+    /// Starts the target with `args`, recording or replaying its system calls if `syscall_log`
+    /// is given.
+    fn start(&mut self, args: &[String], syscall_log: Option<Rc<RefCell<SyscallLog>>>) {
+        self.kill();
+        self.delete_checkpoints();
+        self.hits = 0;
+        self.at_hit = false;
+
+        let mut inferior = match Inferior::new(&self.target, args, &self.run_env) {
+            Ok(inferior) => inferior,
+            Err(err) => return println!("Error starting subprocess: {}", err),
+        };
+        if let Some(log) = syscall_log {
+            if let Err(err) = inferior.set_syscall_log(log) {
+                println!("Failed to trace system calls: {}", err);
+            }
+        }
+        inferior.set_syscall_tracing(&self.catchpoints, self.trace_syscalls);
+        self.relocate_executable(&inferior);
+        // insert breakpoints into the new process
+        self.shared_libraries.clear();
+        self.breakpoints.retain(|_, bp| !bp.internal);
+        let mut unmapped = Vec::new();
+        self.breakpoints
+            .values_mut()
+            .for_each(|bp| {
+                if let Ok(origin_byte) = inferior.write_byte(bp.addr, 0xcc) {
+                    bp.origin_byte = origin_byte;
+                }
+                else {
+                    // In a shared library that is not loaded yet
+                    unmapped.push(bp.addr);
+                }
+            });
+        for addr in unmapped {
+            let bp = self.breakpoints.remove(&addr).unwrap();
+            self.pending_breakpoints.push(PendingBreakpoint {
+                location: format!("*{:#x}", addr),
+                condition: bp.condition,
+                temporary: bp.temporary,
+                dprintf: bp.dprintf,
+            });
+        }
+
+        self.inferior = Some(inferior);
+        self.init_shared_libraries();
+        // The starting point for reverse execution
+        if self.settings.reverse {
+            self.take_checkpoint(true);
+        }
+        self.wakeup_wait();
+    }
+
+    /// Runs the program with `args` to the end, stopping it `hz` times a second to sample its
+    /// stack. Prints a flat profile, and writes the folded stacks to `folded_path`.
+    pub fn profile(&mut self, args: &[String], hz: u32, folded_path: &str) {
+        let inferior = match Inferior::new(&self.target, args, &self.run_env) {
+            Ok(inferior) => inferior,
+            Err(err) => return println!("Error starting subprocess: {}", err),
+        };
+        self.relocate_executable(&inferior);
+        self.inferior = Some(inferior);
+        self.init_shared_libraries();
+
+        let start = Instant::now();
+        let mut profile = Profile::default();
+        self.run_to_exit(Some(Duration::from_secs_f64(1.0 / hz as f64)), |debugger, _| {
+            profile.add(debugger.stack_functions());
+            true
+        });
+
+        let elapsed = start.elapsed().as_secs_f64();
+        println!(
+            "Flat profile ({} samples in {:.2}s, {:.1} Hz of {} Hz requested):",
+            profile.samples(), elapsed, profile.samples() as f64 / elapsed.max(f64::EPSILON), hz
+        );
+        for line in profile.flat() {
+            println!("{}", line);
+        }
+        let mut folded = profile.folded().join("\n");
+        folded.push('\n');
+        match std::fs::write(folded_path, folded) {
+            Ok(()) => println!("Folded stacks written to {}", folded_path),
+            Err(err) => println!("Could not write {}: {}", folded_path, err),
+        }
+    }
+
+    /// Runs the program with `args` to the end with a one-shot breakpoint at every address of
+    /// the line tables. Writes the lines that ran to `info_path` as an lcov tracefile, and prints
+    /// them next to the sources.
+    pub fn coverage(&mut self, args: &[String], info_path: &str) {
+        if !self.debug_data.as_ref().is_some_and(|debug_data| debug_data.has_debug_info()) {
+            return println!("No line tables to collect coverage with.");
+        }
+        let inferior = match Inferior::new(&self.target, args, &self.run_env) {
+            Ok(inferior) => inferior,
+            Err(err) => return println!("Error starting subprocess: {}", err),
+        };
+        self.relocate_executable(&inferior);
+        let mut coverage = Coverage::new(&self.debug_data.as_ref().unwrap().files());
+        for addr in coverage.addresses() {
+            if let Ok(origin_byte) = inferior.write_byte(addr, 0xcc) {
+                let bp = BreakPoint { addr, origin_byte, condition: None, internal: true, temporary: true, dprintf: None };
+                self.breakpoints.insert(addr, bp);
+            }
+        }
+        self.inferior = Some(inferior);
+        self.init_shared_libraries();
+
+        self.run_to_exit(None, |debugger, rip| {
+            // Each line only needs to be seen running once
+            let bp = debugger.breakpoints.remove(&rip).unwrap();
+            if let Err(err) = debugger.inferior.as_ref().unwrap().write_byte(rip, bp.origin_byte) {
+                println!("Error removing breakpoint at {:#x}: {}", rip, err);
+                return false;
+            }
+            coverage.hit(rip);
+            true
+        });
+        self.breakpoints.clear();
+
+        for line in coverage.summary() {
+            println!("{}", line);
+        }
+        let name = Path::new(&self.target).file_name().unwrap_or_default().to_string_lossy();
+        match std::fs::write(info_path, coverage.lcov(&name)) {
+            Ok(()) => println!("Coverage written to {}", info_path),
+            Err(err) => println!("Could not write {}: {}", info_path, err),
+        }
+    }
+
+    /// Runs the inferior to the end for `profile` and `coverage`, loading shared libraries as they
+    /// are mapped and passing the program's own signals on. `on_stop` is called with the address
+    /// the inferior stopped at when it hits a temporary breakpoint, and with `period`, when it is
+    /// stopped with SIGSTOP once every period. It returns false to give up.
+    fn run_to_exit(&mut self, period: Option<Duration>, mut on_stop: impl FnMut(&mut Debugger, usize) -> bool) {
+        // Stops are due at fixed times, so that the time spent in `on_stop` does not slow the rate
+        // down. Those missed meanwhile are skipped rather than made in a burst.
+        let mut deadline = period.map(|period| Instant::now() + period);
+        loop {
+            let inferior = self.inferior.as_ref().unwrap();
+            let status = match deadline {
+                Some(deadline) => inferior.wakeup_wait_until(&self.breakpoints, deadline),
+                None => inferior.wakeup_wait(&self.breakpoints),
+            };
+            match status {
+                Ok(Status::Stopped(Signal::SIGSTOP, rip)) if period.is_some() => {
+                    if !on_stop(self, rip) {
+                        break;
+                    }
+                    if let (Some(deadline), Some(period)) = (deadline.as_mut(), period) {
+                        let now = Instant::now();
+                        while *deadline <= now {
+                            *deadline += period;
+                        }
+                    }
+                },
+                Ok(Status::Stopped(Signal::SIGTRAP, rip)) if self.breakpoints.get(&rip).is_some_and(|bp| bp.temporary) => {
+                    if !on_stop(self, rip) {
+                        break;
+                    }
+                },
+                Ok(Status::Stopped(Signal::SIGTRAP, rip)) if self.breakpoints.contains_key(&rip) => {
+                    self.update_shared_libraries();
+                },
+                // Signals of the program's own are delivered as usual
+                Ok(Status::Stopped(signal, _)) => inferior.set_pending_signal(Some(signal)),
+                Ok(Status::Syscall(..)) => {},
+                status => {
+                    self.report_status(status);
+                    break;
+                }
+            }
+        }
+        // Still traced and stopped if waiting for it failed
+        self.kill();
+    }
+
+    /// Returns the functions on the stack of the stopped inferior, innermost first, including
+    /// inlined ones.
+    fn stack_functions(&self) -> Vec<String> {
+        let inferior = self.inferior.as_ref().unwrap();
+        let pcs = inferior.stack_frames(&self.debug_data, &self.shared_libraries).unwrap_or_default();
+        let mut functions = Vec::new();
+        for (i, pc) in pcs.into_iter().enumerate() {
+            let lookup = if i == 0 { pc } else { pc - 1 };
+            let (debug_data, base) = match shared_libraries::find_object(lookup, &self.debug_data, &self.shared_libraries) {
+                Some(object) => object,
+                None => {
+                    functions.push("??".to_string());
+                    continue;
+                }
+            };
+            let frames = debug_data.get_frames_from_addr(lookup - base);
+            if frames.is_empty() {
+                let symbol = debug_data.get_symbol_at(lookup - base);
+                functions.push(symbol.map_or("??".to_string(), |symbol| symbol.qualified_name.clone()));
+            }
+            functions.extend(frames.into_iter().map(|(function, _)| function));
+        }
+        functions
+    }
+
+    /// Returns the next command to run: from the init files or a user-defined command being run,
+    /// or else entered by the user, who is re-prompted until they enter a valid one. Aliases,
+    /// `define`, `alias` and `source` are handled here; everything else is parsed by
+    /// DebuggerCommand::from_tokens.
     fn get_next_command(&mut self) -> DebuggerCommand {
         loop {
-            // Print prompt and get next line of user input
-            match self.readline.readline("(deet) ") {
+            if self.pending_lines.is_empty() {
+                self.load_local_init_file();
+            }
+            let line = match self.next_line(None) {
+                Some(line) => line,
+                None => return DebuggerCommand::Quit,
+            };
+            let mut tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.is_empty() || tokens[0].starts_with('#') {
+                continue;
+            }
+            let expanded;
+            if let Some(command) = self.aliases.get(tokens[0]) {
+                expanded = format!("{} {}", command, tokens[1..].join(" "));
+                tokens = expanded.split_whitespace().collect();
+            }
+            match tokens[0] {
+                "define" if tokens.len() == 2 => {
+                    let name = tokens[1].to_string();
+                    self.define(name);
+                    continue;
+                }
+                "alias" => {
+                    match tokens[1..].join(" ").split_once('=') {
+                        Some((name, command)) if !name.trim().is_empty() && !command.trim().is_empty() => {
+                            self.aliases.insert(name.trim().to_string(), command.trim().to_string());
+                        }
+                        _ => println!("Usage: alias NAME = COMMAND"),
+                    }
+                    continue;
+                }
+                "source" if tokens.len() == 2 => {
+                    match std::fs::read_to_string(tokens[1]) {
+                        Ok(text) => {
+                            for line in text.lines().rev() {
+                                self.pending_lines.push_front(line.to_string());
+                            }
+                        }
+                        Err(err) => println!("{}: {}.", tokens[1], err),
+                    }
+                    continue;
+                }
+                name if self.user_commands.contains_key(name) => {
+                    let args = &tokens[1..];
+                    let body = self.user_commands[name].clone();
+                    if self.pending_lines.len() + body.len() > MAX_PENDING_LINES {
+                        println!("Max user call depth exceeded -- command aborted.");
+                        self.pending_lines.clear();
+                        continue;
+                    }
+                    for line in body.iter().rev() {
+                        self.pending_lines.push_front(substitute_args(line, args));
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
+                return cmd;
+            } else {
+                println!("Unrecognized command.");
+            }
+        }
+    }
+
+    /// Queues the commands of `./.deetrc` once those of `~/.deetrc` have run, if it is in the
+    /// auto-load safe path.
+    fn load_local_init_file(&mut self) {
+        let path = match self.local_init_file.take() {
+            Some(path) => path,
+            None => return,
+        };
+        if !self.settings.is_safe_to_load(&path) {
+            let dir = path.parent().unwrap_or(&path);
+            println!("warning: File \"{}\" auto-loading has been declined by your `auto-load safe-path'.", path.display());
+            println!("To enable execution of this file add");
+            println!("\tset auto-load safe-path {}", dir.display());
+            println!("line to your configuration file \"~/.deetrc\".");
+            return;
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                println!("Reading commands from {}", path.display());
+                self.pending_lines.extend(text.lines().map(str::to_string));
+            }
+            Err(err) => println!("{}: {}.", path.display(), err),
+        }
+    }
+
+    /// Reads the body of user-defined command `name`, up to a line reading `end`.
+    fn define(&mut self, name: String) {
+        if self.pending_lines.is_empty() {
+            println!("Type commands for definition of \"{}\".", name);
+            println!("End with a line saying just \"end\".");
+        }
+        let mut body = Vec::new();
+        // Nested blocks would need their own `end`
+        while let Some(line) = self.next_line(Some(">")) {
+            if line.trim() == "end" {
+                self.user_commands.insert(name, body);
+                return;
+            }
+            body.push(line.trim().to_string());
+        }
+    }
+
+    /// Returns the next line to run, taking pending lines first and prompting the user with
+    /// `prompt` (or the configured prompt) when there are none. Returns None at the end of input.
+    fn next_line(&mut self, prompt: Option<&str>) -> Option<String> {
+        if let Some(line) = self.pending_lines.pop_front() {
+            return Some(line);
+        }
+        let locals = self.scope_variable_names();
+        let mut commands: Vec<String> = self.aliases.keys().chain(self.user_commands.keys()).cloned().collect();
+        commands.sort();
+        if let Some(helper) = self.readline.helper_mut() {
+            helper.set_locals(locals);
+            helper.set_user_commands(commands);
+        }
+        loop {
+            let prompt = prompt.unwrap_or(&self.settings.prompt).to_string();
+            match self.readline.readline(&prompt) {
                 Err(ReadlineError::Interrupted) => {
                     // User pressed ctrl+c. We're going to ignore it
                     println!("Type \"quit\" to exit");
                 }
                 Err(ReadlineError::Eof) => {
                     // User pressed ctrl+d, which is the equivalent of "quit" for our purposes
-                    return DebuggerCommand::Quit;
+                    return None;
                 }
                 Err(err) => {
                     panic!("Unexpected I/O error: {:?}", err);
                 }
                 Ok(line) => {
-                    if line.trim().len() == 0 {
+                    if line.trim().is_empty() {
                         continue;
                     }
                     let _ = self.readline.add_history_entry(line.as_str());
@@ -173,95 +932,1341 @@ impl Debugger {
                             self.history_path, err
                         );
                     }
-                    let tokens: Vec<&str> = line.split_whitespace().collect();
-                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
-                        return cmd;
-                    } else {
-                        println!("Unrecognized command.");
-                    }
+                    return Some(line);
                 }
             }
         }
     }
 
-    fn wakeup_wait(&self) {
-        match self.inferior.as_ref().unwrap().wakeup_wait(&self.breakpoints) {
-            Ok(status) => {
-                match status {
-                    Status::Exited(ecode) => {
-                        println!("child exited (status {})", ecode);
-                    },
-                    Status::Signaled(signal) => {
-                        println!("child signaled (sigcode: {:?})", signal);
-                    },
-                    Status::Stopped(signal, rip) => {
-                        println!("child stopped (signal: {:?})", signal);
-                        // print debug info 
-                        let debug_ref = self.debug_data.as_ref().unwrap();
-                        let line = debug_ref
-                        .get_line_from_addr(rip)
-                        .expect("failed to get line info");
-            
-                        let func = debug_ref
-                        .get_function_from_addr(rip)
-                        .expect("failed to get func info");
-            
-                        println!("{} ({}:{})", func, line.file, line.number);
-                    }
-                }
+    /// Returns the names of the local variables and parameters of the function the inferior is
+    /// stopped in, including those of functions inlined at the current line.
+    fn scope_variable_names(&self) -> Vec<String> {
+        let (inferior, debug_data) = match (self.inferior.as_ref(), self.debug_data.as_ref()) {
+            (Some(inferior), Some(debug_data)) => (inferior, debug_data),
+            _ => return Vec::new(),
+        };
+        let rip = match inferior.get_regs() {
+            Ok(regs) => regs.rip as usize,
+            Err(_) => return Vec::new(),
+        };
+        let inlined = debug_data.get_inlined_at(rip).into_iter().flat_map(|f| f.variables.iter());
+        let function = debug_data.get_function_at(rip).into_iter().flat_map(|f| f.variables.iter());
+        inlined.chain(function).map(|var| var.name.clone()).collect()
+    }
+
+    /// Resumes the inferior and reports where it stopped. Breakpoints whose condition is false
+    /// are resumed from silently.
+    fn wakeup_wait(&mut self) {
+        let result = self.resume(true);
+        self.report_status(result);
+    }
+
+    /// Reports the result of resuming the inferior: where it stopped, or how it ended.
+    fn report_status(&mut self, result: Result<Status, nix::Error>) {
+        match result {
+            Ok(Status::Exited(ecode)) => {
+                println!("child exited (status {})", ecode);
+                self.inferior = None;
+                self.refresh_tui();
+            },
+            Ok(Status::Signaled(signal)) => {
+                println!("child signaled (sigcode: {:?})", signal);
+                self.inferior = None;
+                self.refresh_tui();
             },
-            Err(_) => {
-                panic!("Error wakeup subprocess");
+            Ok(Status::Stopped(signal, rip)) => {
+                println!("child stopped (signal: {:?})", signal);
+                self.print_stop_location(rip);
+            },
+            Ok(Status::Syscall(number, returning, rip)) => self.print_catchpoint(number, returning, rip),
+            Err(err) => {
+                println!("Error resuming subprocess: {}", err);
             }
         }
     }
 
-    fn kill(&mut self) {
-        if self.inferior.is_some() {
-            match self.inferior.as_mut().unwrap().kill() {
-                Ok(()) => println!("killed inferior (pid: {})", self.inferior.as_ref().unwrap().pid()),
-                Err(_) => println!("failed to kill child")
+    /// Resumes the inferior until it stops somewhere the user should see, and updates its
+    /// position. Watchpoints are only checked if `watch` is set.
+    fn resume(&mut self, watch: bool) -> Result<Status, nix::Error> {
+        loop {
+            let inferior = self.inferior.as_ref().unwrap();
+            let status = if self.watchpoints.is_empty() || !watch {
+                inferior.wakeup_wait(&self.breakpoints)?
             }
+            else {
+                self.watch_step()?
+            };
+
+            if let Status::Stopped(signal, _) = status {
+                if signal != Signal::SIGTRAP && !self.handle_signal(signal) {
+                    continue;
+                }
+            }
+            if let Status::Stopped(Signal::SIGTRAP, rip) = status {
+                if self.breakpoints.get(&rip).is_some_and(|bp| bp.internal && !bp.temporary) {
+                    // The dynamic linker has loaded or unloaded a library
+                    self.update_shared_libraries();
+                    continue;
+                }
+                if !self.breakpoint_condition_holds(rip) {
+                    continue;
+                }
+                if let Some(format) = self.breakpoints.get(&rip).and_then(|bp| bp.dprintf.clone()) {
+                    self.print_dprintf(&format);
+                    continue;
+                }
+            }
+            self.record_position(&status);
+            if let Status::Stopped(_, rip) = status {
+                self.remove_temporary_breakpoint(rip);
+            }
+            return Ok(status);
         }
     }
 
-    fn set_bp(&mut self, addr: usize) {
-        if self.inferior.is_some() {
+    /// Resumes the inferior until it reaches one of `addrs` or the current function returns,
+    /// using internal breakpoints that are removed again wherever it stops. With `same_frame`
+    /// (`until`), `addrs` are only stopped at in the current frame rather than in recursive calls.
+    fn run_until(&mut self, addrs: &[usize], same_frame: bool) {
+        let inferior = self.inferior.as_ref().unwrap();
+        let start_cfa = self.eval_context().ok().and_then(|ctx| ctx.cfa());
+        let return_addr = inferior.stack_frames(&self.debug_data, &self.shared_libraries).ok()
+            .and_then(|frames| frames.get(1).copied());
+        let mut one_shots = Vec::new();
+        for &addr in addrs.iter().chain(return_addr.iter()) {
             if self.breakpoints.contains_key(&addr) {
-                println!("{:#x} has already been set", addr);
+                continue;
             }
-            else {
-                if let Ok(origin_byte) = self.inferior.as_ref().unwrap()
-                    .write_byte(addr, 0xcc) {
-                    let bp = BreakPoint { addr, origin_byte };
+            match inferior.write_byte(addr, 0xcc) {
+                Ok(origin_byte) => {
+                    let bp = BreakPoint { addr, origin_byte, condition: None, internal: true, temporary: true, dprintf: None };
                     self.breakpoints.insert(addr, bp);
+                    one_shots.push(addr);
                 }
-                else {
-                    println!("set breakpoint at: {:#x} error", addr);
-                }
+                Err(err) => println!("Cannot insert breakpoint at {:#x}: {}", addr, err),
             }
         }
-        else {
-            self.breakpoints.entry(addr)
-            .or_insert(
-                BreakPoint {
-                    addr,
-                    origin_byte: 0
+
+        let mut reached = false;
+        let result = loop {
+            let result = self.resume(true);
+            if let Ok(Status::Stopped(Signal::SIGTRAP, rip)) = result {
+                if one_shots.contains(&rip) {
+                    // Deeper recursive calls pass through the same addresses
+                    let cfa = self.eval_context().ok().and_then(|ctx| ctx.cfa());
+                    reached = (addrs.contains(&rip) && (!same_frame || cfa >= start_cfa))
+                        || (Some(rip) == return_addr && cfa > start_cfa);
+                    if !reached {
+                        continue;
+                    }
                 }
-            );
+            }
+            break result;
+        };
+        for addr in one_shots {
+            if let Some(bp) = self.breakpoints.remove(&addr) {
+                if let Some(inferior) = self.inferior.as_ref() {
+                    // Fails harmlessly if the inferior has exited
+                    let _ = inferior.write_byte(addr, bp.origin_byte);
+                }
+            }
+        }
+        match result {
+            Ok(Status::Stopped(_, rip)) if reached => self.print_stop_location(rip),
+            result => self.report_status(result),
         }
+    }
 
-        println!("set breakpoint {} at {:#x}", self.breakpoints.len() - 1, addr);
+    /// Prints the output of a `dprintf` that the inferior has reached.
+    fn print_dprintf(&self, format: &str) {
+        let result = self.eval_context().and_then(|ctx| {
+            let (format, args) = expr::parse_format_args(format)?;
+            ctx.printf(&format, &args)
+        });
+        match result {
+            Ok(text) => {
+                print!("{}", text);
+                let _ = std::io::stdout().flush();
+            }
+            Err(err) => println!("dprintf: {}", err),
+        }
     }
-}
 
-fn parse_addr(addr: &str) -> Option<usize> {
-    let addr = if addr.to_lowercase().starts_with("0x") {
+    /// Prints `lines`, a page at a time.
+    fn page(&self, lines: Vec<String>) {
+        let mut pager = Pager::new(&self.settings);
+        for line in lines {
+            if !pager.println(&line) {
+                break;
+            }
+        }
+    }
+
+    /// Lists the functions matching `regex` by source file, with their declarations, followed
+    /// by the symbols without debug info in the program and its shared libraries.
+    fn info_functions(&self, pattern: Option<&str>, regex: &Regex) -> Vec<String> {
+        let mut lines = vec![match pattern {
+            Some(pattern) => format!("All functions matching regular expression \"{}\":", pattern),
+            None => "All defined functions:".to_string(),
+        }];
+        let mut current_file = None;
+        for (file, func) in self.debug_data.iter().flat_map(|data| data.search_functions(regex)) {
+            if current_file != Some(file) {
+                lines.push(format!("\nFile {}:", file));
+                current_file = Some(file);
+            }
+            lines.push(format!("{}:\t{}", func.line_number, func.signature()));
+        }
+        let objects = self.debug_data.iter().map(|data| (data, 0))
+            .chain(self.shared_libraries.iter().filter_map(|lib| Some((lib.debug_data.as_ref()?, lib.base))));
+        let mut symbols: Vec<(usize, &str)> = objects
+            .flat_map(|(data, base)| data.search_symbols(regex).into_iter().map(move |sym| (base + sym.address, sym.name.as_str())))
+            .collect();
+        symbols.sort();
+        if !symbols.is_empty() {
+            lines.push("\nNon-debugging symbols:".to_string());
+            lines.extend(symbols.iter().map(|(addr, name)| format!("{:#018x}  {}", addr, name)));
+        }
+        lines
+    }
+
+    /// Lists the global variables matching `regex` by source file.
+    fn info_variables(&self, pattern: Option<&str>, regex: &Regex) -> Vec<String> {
+        let mut lines = vec![match pattern {
+            Some(pattern) => format!("All variables matching regular expression \"{}\":", pattern),
+            None => "All defined variables:".to_string(),
+        }];
+        let mut current_file = None;
+        for (file, var) in self.debug_data.iter().flat_map(|data| data.search_variables(regex)) {
+            if current_file != Some(file) {
+                lines.push(format!("\nFile {}:", file));
+                current_file = Some(file);
+            }
+            lines.push(format!("{}:\t{};", var.line_number, declaration(&var.entity_type.name, &var.name)));
+        }
+        lines
+    }
+
+    /// Shows the address range of the code for the source line at `location`, or at the current
+    /// pc without one.
+    fn info_line(&self, location: Option<&str>) {
+        let addr = match location {
+            Some(location) => self.parse_location(location).first().copied(),
+            None => self.inferior.as_ref().and_then(|inferior| inferior.get_regs().ok()).map(|regs| regs.rip as usize),
+        };
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return println!("No line number information available."),
+        };
+        let object = shared_libraries::find_object(addr, &self.debug_data, &self.shared_libraries);
+        let describe = |addr: usize| match object.and_then(|(data, base)| Some((data.get_symbol_at(addr - base)?, base))) {
+            Some((sym, base)) => format!("{:#x} <{}>", addr, sym.describe(addr - base)),
+            None => format!("{:#x}", addr),
+        };
+        match object.and_then(|(data, base)| Some((data.get_line_range(addr - base)?, base))) {
+            Some(((line, end), base)) => println!(
+                "Line {} of \"{}\" starts at address {} and ends at {}.",
+                line.number,
+                line.file,
+                describe(line.address + base),
+                describe(end + base)
+            ),
+            None => println!("No line number information available for address {}", describe(addr)),
+        }
+    }
+
+    /// Lists the breakpoints set by the user, by address, followed by those still pending.
+    fn info_breakpoints(&self) {
+        let mut breakpoints: Vec<&BreakPoint> = self.breakpoints.values().filter(|bp| !bp.internal).collect();
+        breakpoints.sort_by_key(|bp| bp.addr);
+        if breakpoints.is_empty() && self.pending_breakpoints.is_empty() {
+            return println!("No breakpoints.");
+        }
+        println!("{:<12}{:<6}{:<20}What", "Type", "Disp", "Address");
+        for bp in breakpoints {
+            let kind = if bp.dprintf.is_some() { "dprintf" } else { "breakpoint" };
+            let disp = if bp.temporary { "del" } else { "keep" };
+            println!("{:<12}{:<6}{:<#20x}{}", kind, disp, bp.addr, self.describe_location(bp.addr));
+            if let Some(condition) = bp.condition.as_ref() {
+                println!("        stop only if {}", condition);
+            }
+        }
+        for pending in &self.pending_breakpoints {
+            let kind = if pending.dprintf.is_some() { "dprintf" } else { "breakpoint" };
+            let disp = if pending.temporary { "del" } else { "keep" };
+            println!("{:<12}{:<6}{:<20}{}", kind, disp, "<PENDING>", pending.location);
+            if let Some(condition) = pending.condition.as_ref() {
+                println!("        stop only if {}", condition);
+            }
+        }
+    }
+
+    /// Removes the breakpoints set by the user, putting back the original bytes.
+    fn delete_breakpoints(&mut self) {
+        let addrs: Vec<usize> = self.breakpoints.values().filter(|bp| !bp.internal).map(|bp| bp.addr).collect();
+        for addr in addrs {
+            let bp = self.breakpoints.remove(&addr).unwrap();
+            if let Some(inferior) = self.inferior.as_ref() {
+                let _ = inferior.write_byte(addr, bp.origin_byte);
+            }
+        }
+    }
+
+    /// Describes where the symbol `name` is stored: a local of the current function, a global,
+    /// or a function.
+    fn info_address(&self, name: &str) {
+        let rip = self.inferior.as_ref().and_then(|inferior| inferior.get_regs().ok()).map(|regs| regs.rip as usize);
+        let local = rip
+            .and_then(|rip| self.debug_data.as_ref()?.get_function_at(rip))
+            .and_then(|func| func.variables.iter().find(|var| var.name == name));
+        let global = self.debug_data.as_ref().and_then(|data| data.get_global_variable(name));
+        if let Some(var) = local.or(global) {
+            return println!("Symbol \"{}\" is {}.", name, describe_storage(var));
+        }
+        if let Some(func) = self.debug_data.as_ref().and_then(|data| data.get_function(name)) {
+            return println!("Symbol \"{}\" is a function at address {:#x}.", name, func.address);
+        }
+        let objects = self.debug_data.iter().map(|data| (data, 0))
+            .chain(self.shared_libraries.iter().filter_map(|lib| Some((lib.debug_data.as_ref()?, lib.base))));
+        for (data, base) in objects {
+            if let Some(addr) = data.get_symbol_addr(name) {
+                return println!("Symbol \"{}\" is at {:#x} in a file compiled without debugging.", name, base + addr);
+            }
+        }
+        println!("No symbol \"{}\" in current context.", name);
+    }
+
+    /// Lists the arguments and local variables of the function containing `location`, with where
+    /// each is stored.
+    fn info_scope(&self, location: &str) {
+        let func = self.parse_location(location).first()
+            .and_then(|&addr| self.debug_data.as_ref()?.get_function_at(addr));
+        let func = match func {
+            Some(func) => func,
+            None => return println!("No function contains specified address."),
+        };
+        if func.variables.is_empty() {
+            return println!("Scope for {}:\nSymbol {} contains no locals.", location, func.qualified_name);
+        }
+        println!("Scope for {}:", location);
+        for var in &func.variables {
+            println!("Symbol {} is {}.", var.name, describe_storage(var));
+        }
+    }
+
+    /// Prints the command line, working directory and executable of the inferior.
+    fn info_proc(&self, pid: Pid) {
+        println!("process {}", pid);
+        if let Some(args) = procfs::read_cmdline(pid) {
+            println!("cmdline = '{}'", args.join(" "));
+        }
+        for name in ["cwd", "exe"] {
+            if let Some(target) = procfs::read_link(pid, name) {
+                println!("{} = '{}'", name, target);
+            }
+        }
+    }
+
+    /// Prints the memory mappings of the inferior, with the ELF sections found in each mapping
+    /// of a file.
+    fn info_proc_mappings(&self, pid: Pid) {
+        let mappings = procfs::read_maps(pid);
+        let mut sections: HashMap<usize, Vec<String>> = HashMap::new();
+        let mut paths: Vec<&str> = mappings.iter().map(|m| m.path.as_str()).filter(|p| p.starts_with('/')).collect();
+        paths.dedup();
+        for path in paths {
+            let mapped: Vec<&procfs::Mapping> = mappings.iter().filter(|m| m.path == path).collect();
+            sections.extend(procfs::mapped_sections(path, &mapped));
+        }
+
+        let mut lines = vec![
+            format!("process {}", pid),
+            "Mapped address spaces:".to_string(),
+            String::new(),
+            format!("{:>20} {:>18} {:>10} {:>10}  {:<5}  objfile", "Start Addr", "End Addr", "Size", "Offset", "Perms"),
+        ];
+        for mapping in &mappings {
+            let mut line = format!(
+                "{:>#20x} {:>#18x} {:>#10x} {:>#10x}  {:<5}  {}",
+                mapping.start,
+                mapping.end,
+                mapping.end - mapping.start,
+                mapping.offset,
+                mapping.perms,
+                mapping.path
+            );
+            if let Some(names) = sections.get(&mapping.start) {
+                line.push_str(&format!(" ({})", names.join(" ")));
+            }
+            lines.push(line.trim_end().to_string());
+        }
+        self.page(lines);
+    }
+
+    /// Prints the open files of the inferior, with their cursors and flags.
+    fn info_proc_fds(&self, pid: Pid) {
+        let files = procfs::read_fds(pid);
+        let width = files.iter().map(|file| file.target.len()).max().unwrap_or(0).max("Target".len());
+        println!("process {}", pid);
+        println!("{:>4}  {:<width$}  {:>8}  Flags", "FD", "Target", "Pos", width = width);
+        for file in files {
+            println!(
+                "{:>4}  {:<width$}  {:>8}  {}",
+                file.fd,
+                file.target,
+                file.pos.map_or("?".to_string(), |pos| pos.to_string()),
+                file.flags.map_or("?".to_string(), procfs::describe_open_flags),
+                width = width
+            );
+        }
+    }
+
+    /// Prints the auxiliary vector the kernel passed to the inferior.
+    fn info_auxv(&self, inferior: &Inferior) {
+        let mut entries = shared_libraries::read_auxv(inferior);
+        entries.push((0, 0));
+        for (key, value) in entries {
+            let (name, description, format) = procfs::auxv_entry(key);
+            let value = match format {
+                procfs::AuxvFormat::Dec => value.to_string(),
+                procfs::AuxvFormat::Hex => format!("{:#x}", value),
+                procfs::AuxvFormat::Str => match shared_libraries::read_string(inferior, value as usize) {
+                    Some(string) => format!("{:#x} \"{}\"", value, string),
+                    None => format!("{:#x}", value),
+                },
+            };
+            println!("{:<4} {:<20} {:<30} {}", key, name, description, value);
+        }
+    }
+
+    /// Deletes the temporary breakpoint (`tbreak`) at `rip`, if there is one, once it has been hit.
+    fn remove_temporary_breakpoint(&mut self, rip: usize) {
+        if !self.at_hit || !self.breakpoints.get(&rip).is_some_and(|bp| bp.temporary && !bp.internal) {
+            return;
+        }
+        let bp = self.breakpoints.remove(&rip).unwrap();
+        if let Some(inferior) = self.inferior.as_ref() {
+            let _ = inferior.write_byte(rip, bp.origin_byte);
+        }
+    }
+
+    /// Applies the `handle` settings to `signal`, which the inferior just stopped with: arranges for
+    /// it to be delivered on resume if it is passed to the program, and returns whether to stop.
+    fn handle_signal(&self, signal: Signal) -> bool {
+        let action = self.settings.signal_action(signal);
+        if let Some(inferior) = self.inferior.as_ref() {
+            inferior.set_pending_signal(Some(signal).filter(|_| action.pass));
+        }
+        if !action.stop && action.print {
+            println!("child received signal {:?}", signal);
+        }
+        action.stop
+    }
+
+    /// Counts the breakpoint hit the inferior is stopped at, if it is, and takes one of the
+    /// periodic checkpoints used by reverse-continue.
+    fn record_position(&mut self, status: &Status) {
+        let rip = match status {
+            Status::Stopped(_, rip) => *rip,
+            _ => {
+                self.at_hit = false;
+                return;
+            }
+        };
+        self.at_hit = self.breakpoints.get(&rip).is_some_and(|bp| !bp.internal)
+            && self.breakpoint_condition_holds(rip);
+        if self.at_hit {
+            self.hits += 1;
+            if self.hits.is_multiple_of(CHECKPOINT_INTERVAL) && self.auto_checkpoints() {
+                self.take_checkpoint(true);
+            }
+        }
+    }
+
+    /// Whether to take automatic checkpoints, which costs a fork every few breakpoint hits: only
+    /// once the user asked for reverse execution or took a checkpoint themselves.
+    fn auto_checkpoints(&self) -> bool {
+        self.settings.reverse || self.checkpoints.iter().any(|c| !c.internal)
+    }
+
+    /// Forks the inferior where it is stopped. Returns the pid of the copy.
+    fn take_checkpoint(&mut self, internal: bool) -> Option<nix::unistd::Pid> {
+        let inferior = self.inferior.as_ref()?;
+        if internal && self.checkpoints.iter().any(|c| (c.hits, c.at_hit) == (self.hits, self.at_hit)) {
+            return None;
+        }
+        let pc = inferior.get_regs().ok()?.rip as usize;
+        let process = match inferior.checkpoint() {
+            Ok(process) => process,
+            Err(err) => {
+                println!("Failed to checkpoint: {}", err);
+                return None;
+            }
+        };
+        let pid = process.pid();
+        let libraries = self.shared_libraries.iter().map(|lib| lib.name.clone()).collect();
+        let log_mark = inferior.syscall_log().map(|log| log.borrow().mark());
+        self.checkpoints.push(Checkpoint {
+            process,
+            pc,
+            hits: self.hits,
+            at_hit: self.at_hit,
+            internal,
+            libraries,
+            log_mark,
+        });
+
+        // Thin out old automatic checkpoints, but keep the one at the start of the program
+        let internal: Vec<usize> = self.checkpoints.iter()
+            .enumerate()
+            .filter(|(_, c)| c.internal)
+            .map(|(i, _)| i)
+            .collect();
+        if internal.len() > MAX_INTERNAL_CHECKPOINTS {
+            let mut checkpoint = self.checkpoints.remove(internal[1]);
+            let _ = checkpoint.process.kill();
+        }
+        Some(pid)
+    }
+
+    /// Makes a copy of checkpoint `index` the inferior, leaving the checkpoint itself untouched
+    /// so that it can be restarted again. Returns whether it succeeded.
+    fn restore_checkpoint(&mut self, index: usize) -> bool {
+        let checkpoint = &self.checkpoints[index];
+        let process = match checkpoint.process.checkpoint() {
+            Ok(process) => process,
+            Err(err) => {
+                println!("Failed to restart checkpoint: {}", err);
+                return false;
+            }
+        };
+        if let Some(mut inferior) = self.inferior.take() {
+            let _ = inferior.kill();
+        }
+        // Breakpoints may have been set since the checkpoint was taken
+        for bp in self.breakpoints.values() {
+            if process.read_memory(bp.addr, 1).is_ok_and(|byte| byte[0] != 0xcc) {
+                let _ = process.write_byte(bp.addr, 0xcc);
+            }
+        }
+        if let (Some(log), Some(mark)) = (process.syscall_log(), checkpoint.log_mark) {
+            log.borrow_mut().rewind(mark);
+        }
+        self.hits = checkpoint.hits;
+        self.at_hit = checkpoint.at_hit;
+        self.inferior = Some(process);
+        self.shared_libraries.retain(|lib| checkpoint.libraries.contains(&lib.name));
+        self.update_syscall_tracing();
+        self.update_shared_libraries();
+        // Watched values are those of the restored state
+        for i in 0..self.watchpoints.len() {
+            let value = self.evaluate(&self.watchpoints[i].expr).ok();
+            self.watchpoints[i].value = value.map(|(value, text)| (value.bytes, text));
+        }
+        true
+    }
+
+    /// Goes back to the last breakpoint hit before the current position, by restarting the
+    /// latest checkpoint taken before it and continuing until it is reached again.
+    fn reverse_continue(&mut self) {
+        if self.inferior.is_none() {
+            println!("The program is not being run.");
+            return;
+        }
+        if self.checkpoints.is_empty() {
+            println!("No reverse-execution history; use \"set reverse on\" before running the program.");
+            return;
+        }
+        let target = if self.at_hit { self.hits - 1 } else { self.hits };
+        let before_target = |c: &Checkpoint| c.hits < target || (c.hits == target && c.at_hit);
+        let index = self.checkpoints.iter()
+            .enumerate()
+            .filter(|(_, c)| target > 0 && before_target(c))
+            .max_by_key(|(_, c)| (c.hits, c.at_hit))
+            .map(|(i, _)| i);
+        let index = match index {
+            Some(index) => index,
+            None => {
+                // Go back to the start of the program
+                let (index, _) = self.checkpoints.iter()
+                    .enumerate()
+                    .min_by_key(|(_, c)| (c.hits, c.at_hit))
+                    .unwrap();
+                if self.restore_checkpoint(index) {
+                    println!("\nNo more reverse-execution history.");
+                    self.print_stop_location(self.checkpoints[index].pc);
+                }
+                return;
+            }
+        };
+
+        if !self.restore_checkpoint(index) {
+            return;
+        }
+        if self.hits == target {
+            println!("child stopped (signal: {:?})", Signal::SIGTRAP);
+            self.print_stop_location(self.checkpoints[index].pc);
+            return;
+        }
+        while self.hits < target {
+            match self.resume(false) {
+                Ok(Status::Stopped(_, _)) | Ok(Status::Syscall(_, _, _)) => {}
+                Ok(_) | Err(_) => {
+                    println!("The program did not reach the same breakpoint again; it may not be deterministic.");
+                    self.kill();
+                    return;
+                }
+            }
+        }
+        let rip = self.inferior.as_ref().and_then(|inferior| inferior.get_regs().ok());
+        if let Some(regs) = rip {
+            println!("child stopped (signal: {:?})", Signal::SIGTRAP);
+            self.print_stop_location(regs.rip as usize);
+        }
+    }
+
+    fn update_syscall_tracing(&mut self) {
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.set_syscall_tracing(&self.catchpoints, self.trace_syscalls);
+        }
+    }
+
+    /// Reports a stop at a system call caught by a catchpoint.
+    fn print_catchpoint(&self, number: u64, returning: bool, rip: usize) {
+        let catchpoint = self.catchpoints.iter()
+            .position(|numbers| numbers.is_empty() || numbers.contains(&number))
+            .unwrap_or(0);
+        let event = if returning { "returned from" } else { "call to" };
+        println!("Catchpoint {} ({} syscall {})", catchpoint, event, syscalls::display_name(number));
+        self.print_stop_location(rip);
+    }
+
+    fn delete_checkpoints(&mut self) {
+        for mut checkpoint in self.checkpoints.drain(..) {
+            let _ = checkpoint.process.kill();
+        }
+    }
+
+    /// Single-steps the inferior until a breakpoint is reached, the value of a watchpoint
+    /// changes, or the inferior stops for another reason.
+    fn watch_step(&mut self) -> Result<Status, nix::Error> {
+        loop {
+            let status = self.inferior.as_ref().unwrap().step_instruction(&self.breakpoints)?;
+            let rip = match status {
+                Status::Stopped(Signal::SIGTRAP, rip) => rip,
+                other => return Ok(other),
+            };
+
+            let regs = self.inferior.as_ref().unwrap().get_regs()?;
+            let current_func = self.debug_data.as_ref()
+                .and_then(|dwarf| dwarf.get_function_at(rip))
+                .map(|func| func.address);
+            let current_cfa = self.eval_context().ok().and_then(|ctx| ctx.cfa());
+            let mut changed = false;
+            let mut i = 0;
+            while i < self.watchpoints.len() {
+                if let Some((func, cfa)) = self.watchpoints[i].scope {
+                    if regs.rsp as usize >= cfa {
+                        println!("\nwatchpoint {} deleted because the program has left the block in which its expression is valid.", i);
+                        self.watchpoints.remove(i);
+                        changed = true;
+                        continue;
+                    }
+                    // Locals of the watched frame are not visible from inside callees
+                    if current_func != Some(func) || current_cfa != Some(cfa) {
+                        i += 1;
+                        continue;
+                    }
+                }
+
+                let new_value = self
+                    .evaluate(&self.watchpoints[i].expr)
+                    .ok()
+                    .map(|(value, text)| (value.bytes, text));
+                let watchpoint = &mut self.watchpoints[i];
+                let old_bytes = watchpoint.value.as_ref().map(|(bytes, _)| bytes);
+                if let Some((new_bytes, new_text)) = new_value.as_ref() {
+                    if old_bytes != Some(new_bytes) {
+                        println!("\nwatchpoint {}: {}", i, watchpoint.expr);
+                        if let Some((_, old_text)) = watchpoint.value.as_ref() {
+                            println!("Old value = {}", old_text);
+                        }
+                        println!("New value = {}", new_text);
+                        changed = true;
+                    }
+                }
+                watchpoint.value = new_value;
+                i += 1;
+            }
+
+            if changed || self.breakpoints.contains_key(&rip) {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Returns whether execution should stop at `rip`: false only if there is a breakpoint with
+    /// a condition there, and the condition evaluates to zero.
+    fn breakpoint_condition_holds(&self, rip: usize) -> bool {
+        let condition = match self.breakpoints.get(&rip).and_then(|bp| bp.condition.as_ref()) {
+            Some(condition) => condition,
+            None => return true,
+        };
+        let inferior = self.inferior.as_ref().unwrap();
+        let result = expr::Context::new(inferior, self.debug_data.as_ref().unwrap())
+            .and_then(|ctx| {
+                let value = ctx.evaluate(condition)?;
+                ctx.is_true(&value)
+            });
+        match result {
+            Ok(holds) => holds,
+            Err(err) => {
+                println!("Error in testing condition for breakpoint at {:#x}: {}", rip, err);
+                true
+            }
+        }
+    }
+
+    fn print_stop_location(&self, rip: usize) {
+        println!("{}", self.describe_location(rip));
+        if self.settings.auto_list {
+            self.print_source_line(rip);
+        }
+        self.print_displays();
+        self.refresh_tui();
+    }
+
+    /// Prints the `display` expressions that are in scope where the inferior is stopped.
+    fn print_displays(&self) {
+        if self.inferior.is_none() {
+            return;
+        }
+        for display in &self.displays {
+            self.print_display(display);
+        }
+    }
+
+    /// Prints `display`, unless it uses locals of a function other than the current one or
+    /// names a symbol that does not exist here.
+    fn print_display(&self, display: &Display) {
+        if let Some(function) = display.function {
+            let rip = self.inferior.as_ref().and_then(|inferior| inferior.get_regs().ok()).map(|regs| regs.rip as usize);
+            let current = rip.and_then(|rip| self.debug_data.as_ref()?.get_function_at(rip)).map(|func| func.address);
+            if current != Some(function) {
+                return;
+            }
+        }
+        match self.evaluate(&display.expr) {
+            Ok((_, formatted)) => println!("{}: {} = {}", display.number, display.expr, formatted),
+            Err(expr::Error::UnknownSymbol(_)) => {}
+            Err(expr::Error::OptimizedOut) => println!("{}: {} = <optimized out>", display.number, display.expr),
+            Err(err) => println!("{}: {} = <error: {}>", display.number, display.expr, err),
+        }
+    }
+
+    /// Redraws the panes of the text interface, if it is on, for where the inferior is now.
+    fn refresh_tui(&self) {
+        let tui = match self.tui.as_ref() {
+            Some(tui) => tui,
+            None => return,
+        };
+        let mut view = View::default();
+        let regs = self.inferior.as_ref().and_then(|inferior| inferior.get_regs().ok());
+        let line_at = |addr: usize| {
+            shared_libraries::find_object(addr, &self.debug_data, &self.shared_libraries)
+                .and_then(|(debug_ref, base)| debug_ref.get_line_from_addr(addr - base))
+        };
+        if let Some(regs) = regs {
+            let rip = regs.rip as usize;
+            if let Some(line) = line_at(rip) {
+                view.file = Some(line.file);
+                view.line = Some(line.number);
+            }
+            view.location = Some(self.describe_location(rip));
+            view.stack = self.inferior.as_ref().unwrap()
+                .backtrace(&self.debug_data, &self.shared_libraries)
+                .unwrap_or_default();
+        } else {
+            // Before the program runs, show the file with main in it
+            let main = self.debug_data.as_ref().and_then(|data| data.get_function("main"));
+            view.file = main.and_then(|main| line_at(main.address)).map(|line| line.file);
+        }
+        view.registers = regs;
+        view.breakpoint_lines = self.breakpoints.values()
+            .filter(|bp| !bp.internal)
+            .filter_map(|bp| line_at(bp.addr))
+            .filter(|line| Some(&line.file) == view.file.as_ref())
+            .map(|line| line.number)
+            .collect();
+        tui.draw(&view);
+    }
+
+    /// Prints the source line containing `rip`, as `number<TAB>text`.
+    fn print_source_line(&self, rip: usize) {
+        let line = shared_libraries::find_object(rip, &self.debug_data, &self.shared_libraries)
+            .and_then(|(debug_ref, base)| debug_ref.get_line_from_addr(rip - base));
+        let line = match line {
+            Some(line) => line,
+            None => return,
+        };
+        match std::fs::read_to_string(&line.file) {
+            Ok(text) => {
+                if let Some(source) = text.lines().nth(line.number.saturating_sub(1)) {
+                    println!("{}\t{}", line.number, source);
+                }
+            }
+            Err(err) => println!("{}\tin {}: {}", line.number, line.file, err),
+        }
+    }
+
+    fn describe_location(&self, rip: usize) -> String {
+        let (debug_ref, base) =
+            match shared_libraries::find_object(rip, &self.debug_data, &self.shared_libraries) {
+                Some(object) => object,
+                None => return format!("{:#x} in ??", rip),
+            };
+        // The innermost frame, which is the inlined function if rip is inside one
+        match debug_ref.get_frames_from_addr(rip - base).into_iter().next() {
+            Some((func, Some(line))) => format!("{} ({}:{})", func, line.file, line.number),
+            Some((func, None)) => format!("{} ({:#x})", func, rip),
+            None => match debug_ref.get_symbol_at(rip - base) {
+                Some(sym) => format!("{} ({:#x})", sym.describe(rip - base), rip),
+                None => format!("{:#x} in ??", rip),
+            },
+        }
+    }
+
+    /// Steps the inferior until it reaches the beginning of a different source line. `step`
+    /// (`into`) stops in called and inlined functions that have debug info, while `next` runs
+    /// over calls and stays out of inlined code. `until` (`past`) is like `next`, but keeps going
+    /// until a line after the current one, so that it gets out of loops.
+    fn step_line(&mut self, into: bool, past: bool) {
+        let result = self.step_line_status(into, past);
+        if let Ok(status) = result.as_ref() {
+            if let Status::Stopped(signal, _) = status {
+                if *signal != Signal::SIGTRAP {
+                    self.handle_signal(*signal);
+                }
+            }
+            self.record_position(status);
+            if let Status::Stopped(_, rip) = status {
+                self.remove_temporary_breakpoint(*rip);
+            }
+        }
+        match result {
+            Ok(Status::Exited(ecode)) => {
+                println!("child exited (status {})", ecode);
+                self.inferior = None;
+            },
+            Ok(Status::Signaled(signal)) => {
+                println!("child signaled (sigcode: {:?})", signal);
+                self.inferior = None;
+            },
+            Ok(Status::Stopped(Signal::SIGTRAP, rip)) => self.print_stop_location(rip),
+            Ok(Status::Stopped(signal, rip)) => {
+                println!("child stopped (signal: {:?})", signal);
+                self.print_stop_location(rip);
+            },
+            Ok(Status::Syscall(number, returning, rip)) => self.print_catchpoint(number, returning, rip),
+            Err(err) => println!("failed to step: {}", err),
+        }
+    }
+
+    fn step_line_status(&mut self, into: bool, past: bool) -> Result<Status, nix::Error> {
+        let dwarf = match self.debug_data.as_ref() {
+            Some(dwarf) => dwarf,
+            None => return Err(nix::Error::EINVAL),
+        };
+        let inferior = self.inferior.as_ref().unwrap();
+        let regs = inferior.get_regs()?;
+        let frame_of = |pc: usize| {
+            let frames = dwarf.get_frames_from_addr(pc);
+            let line = frames.first().and_then(|(_, line)| line.as_ref()).map(|line| line.number);
+            (frames.len(), line)
+        };
+        let (start_depth, start_line) = frame_of(regs.rip as usize);
+        let start_cfa = self.eval_context().ok().and_then(|ctx| ctx.cfa());
+        if start_line.is_none() {
+            // Nothing to step through line by line: run until the function returns
+            let frames = inferior.stack_frames(&self.debug_data, &self.shared_libraries)?;
+            return match frames.get(1) {
+                Some(&return_addr) => Self::run_to(inferior, &mut self.breakpoints, return_addr),
+                None => inferior.wakeup_wait(&self.breakpoints),
+            };
+        }
+
+        loop {
+            let inferior = self.inferior.as_ref().unwrap();
+            let before = inferior.get_regs()?;
+            let status = inferior.step_instruction(&self.breakpoints)?;
+            let pc = match status {
+                Status::Stopped(Signal::SIGTRAP, pc) => pc,
+                other => return Ok(other),
+            };
+            if self.breakpoints.get(&pc).is_some_and(|bp| !bp.internal) {
+                return Ok(status);
+            }
+
+            // A call pushes the address of the instruction following it
+            let regs = inferior.get_regs()?;
+            let pushed = inferior.read_memory(regs.rsp as usize, 8)
+                .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                .unwrap_or(0);
+            let called = regs.rsp + 8 == before.rsp
+                && pushed > before.rip
+                && pushed <= before.rip + 16
+                && pc as u64 != pushed;
+            if called {
+                match dwarf.get_function_at(pc) {
+                    Some(func) if into && func.address == pc => {
+                        let addr = dwarf.skip_prologue(pc);
+                        if addr == pc {
+                            return Ok(status);
+                        }
+                        return Self::run_to(inferior, &mut self.breakpoints, addr);
+                    }
+                    _ => {
+                        // Run until the callee returns, then keep stepping in this frame
+                        match Self::run_to(inferior, &mut self.breakpoints, pushed as usize)? {
+                            Status::Stopped(Signal::SIGTRAP, pc) if pc as u64 == pushed => {}
+                            other => return Ok(other),
+                        }
+                    }
+                }
+            }
+
+            let pc = self.inferior.as_ref().unwrap().get_regs()?.rip as usize;
+            if !called && dwarf.get_function_at(pc).is_none() {
+                // Returned into code without debug info, e.g. from main into libc
+                return self.inferior.as_ref().unwrap().wakeup_wait(&self.breakpoints);
+            }
+            if !dwarf.is_line_start(pc) {
+                continue;
+            }
+            let (depth, line) = frame_of(pc);
+            if line.is_none() {
+                continue;
+            }
+            let cfa = self.eval_context().ok().and_then(|ctx| ctx.cfa());
+            if cfa != start_cfa {
+                // Returned to the caller
+                return Ok(Status::Stopped(Signal::SIGTRAP, pc));
+            }
+            if !into && depth > start_depth {
+                continue;
+            }
+            if past && depth == start_depth && line <= start_line {
+                // Jumped back to the start of a loop
+                continue;
+            }
+            if (depth, line) != (start_depth, start_line) {
+                return Ok(Status::Stopped(Signal::SIGTRAP, pc));
+            }
+        }
+    }
+
+    /// Resumes the inferior until it reaches `addr`, using a temporary breakpoint there unless
+    /// one has been set by the user already.
+    fn run_to(
+        inferior: &Inferior,
+        breakpoints: &mut HashMap<usize, BreakPoint>,
+        addr: usize,
+    ) -> Result<Status, nix::Error> {
+        let temporary = !breakpoints.contains_key(&addr);
+        if temporary {
+            let origin_byte = inferior.write_byte(addr, 0xcc)?;
+            breakpoints.insert(addr, BreakPoint { addr, origin_byte, condition: None, internal: true, temporary: true, dprintf: None });
+        }
+        let status = inferior.wakeup_wait(breakpoints);
+        if temporary {
+            if let Some(bp) = breakpoints.remove(&addr) {
+                if matches!(status, Ok(Status::Stopped(_, _)) | Ok(Status::Syscall(_, _, _))) {
+                    inferior.write_byte(addr, bp.origin_byte)?;
+                }
+            }
+        }
+        status
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) {
+        match name {
+            "args" => {
+                let words: Vec<String> = value.split_whitespace().map(str::to_string).collect();
+                self.set_args(&words);
+            },
+            "environment" | "env" => {
+                let (name, value) = match value.split_once('=') {
+                    Some((name, value)) => (name.trim(), value.trim()),
+                    None => value.split_once(' ').unwrap_or((value, "")),
+                };
+                if name.is_empty() {
+                    return println!("Argument required (environment variable to set).");
+                }
+                self.run_env.set_var(name, value.trim());
+            },
+            "cwd" => self.run_env.cwd = Some(value.to_string()).filter(|cwd| !cwd.is_empty()),
+            "inferior-tty" => self.run_env.tty = Some(value.to_string()).filter(|tty| !tty.is_empty()),
+            "debug-file-directory" => {
+                // A colon-separated list, like $PATH
+                self.debug_file_directories = value.split(':').map(str::to_string).collect();
+                self.reload_symbols();
+            },
+            "index-cache" => match value {
+                "on" | "enabled on" => self.index_cache = true,
+                "off" | "enabled off" => {
+                    // Symbols read from the cache are read again without it
+                    self.index_cache = false;
+                    self.reload_symbols();
+                }
+                _ => println!("\"on\" or \"off\" expected, got \"{}\".", value),
+            },
+            _ => {
+                if let Err(err) = self.settings.set(name, value) {
+                    println!("{}", err);
+                }
+            },
+        }
+    }
+
+    /// Reads the symbols of the target again, e.g. after the debug file directories changed.
+    /// Shared libraries are reloaded on the next run.
+    fn reload_symbols(&mut self) {
+        match DwarfData::from_file(&self.target, &self.debug_file_directories, self.index_cache) {
+            Ok(debug_data) => self.debug_data = Some(debug_data),
+            Err(err) => println!("Could not reload symbols from {}: {}", self.target, err),
+        }
+    }
+
+    /// Sets the arguments and redirections of the program for the next runs. Returns false if
+    /// `words` could not be parsed.
+    fn set_args(&mut self, words: &[String]) -> bool {
+        match inferior::parse_run_args(words) {
+            Ok((args, redirections)) => {
+                self.args = args;
+                self.run_env.redirections = redirections;
+                true
+            }
+            Err(err) => {
+                println!("{}", err);
+                false
+            }
+        }
+    }
+
+    fn show_option(&self, name: &str) {
+        match name {
+            "args" => {
+                let words: Vec<String> = self.args.iter().cloned()
+                    .chain(self.run_env.redirections.iter().map(|redirection| redirection.to_string()))
+                    .collect();
+                println!("Argument list to give program being debugged when it is started is \"{}\".", words.join(" "));
+            },
+            "environment" | "env" => {
+                let lines = self.run_env.environment.iter().map(|(name, value)| format!("{}={}", name, value));
+                self.page(lines.collect());
+            },
+            _ if name.starts_with("environment ") || name.starts_with("env ") => {
+                let var = name.split_once(' ').unwrap().1.trim();
+                match self.run_env.get_var(var) {
+                    Some(value) => println!("{} = {}", var, value),
+                    None => println!("Environment variable \"{}\" not defined.", var),
+                }
+            },
+            "cwd" => match &self.run_env.cwd {
+                Some(cwd) => println!("Current working directory that will be used when starting the inferior is \"{}\".", cwd),
+                None => println!("You have not set the inferior's current working directory.\n\
+                    The inferior will inherit deet's current working directory."),
+            },
+            "inferior-tty" => println!("Terminal for future runs of program being debugged is \"{}\".",
+                self.run_env.tty.as_deref().unwrap_or("")),
+            "debug-file-directory" => {
+                println!("The directory where separate debug symbols are searched for is \"{}\".",
+                    self.debug_file_directories.join(":"));
+            },
+            "index-cache" => println!("The index cache is {}.", if self.index_cache { "on" } else { "off" }),
+            _ => match self.settings.show(name) {
+                Some(description) => println!("{}", description),
+                None => println!("No option named \"{}\".", name),
+            },
+        }
+    }
+
+    /// Moves the debug info of a position-independent executable to where `inferior` loaded it,
+    /// along with the breakpoints set at its link-time addresses before it first ran.
+    fn relocate_executable(&mut self, inferior: &Inferior) {
+        let bias = shared_libraries::executable_bias(inferior, &self.target);
+        let debug_data = match self.debug_data.as_mut() {
+            Some(debug_data) => debug_data,
+            None => return,
+        };
+        let delta = bias.wrapping_sub(debug_data.load_bias());
+        if delta == 0 {
+            return;
+        }
+        debug_data.set_load_bias(bias);
+        self.breakpoints = std::mem::take(&mut self.breakpoints)
+            .into_values()
+            .map(|mut bp| {
+                bp.addr = bp.addr.wrapping_add(delta);
+                (bp.addr, bp)
+            })
+            .collect();
+    }
+
+    /// Loads the dynamic linker's symbols and sets an internal breakpoint on `_dl_debug_state`,
+    /// which it calls whenever the list of loaded shared libraries changes.
+    fn init_shared_libraries(&mut self) {
+        let inferior = self.inferior.as_ref().unwrap();
+        let (interp, base) = match (
+            shared_libraries::interpreter(&self.target),
+            shared_libraries::interpreter_base(inferior),
+        ) {
+            (Some(interp), Some(base)) => (interp, base),
+            _ => return, // statically linked
+        };
+        let lib = match SharedLibrary::load(&interp, base, &self.debug_file_directories, self.index_cache) {
+            Some(lib) => lib,
+            None => return,
+        };
+        let dl_debug_state = lib.debug_data.as_ref()
+            .and_then(|dwarf| dwarf.get_symbol_addr("_dl_debug_state"));
+        if let Some(addr) = dl_debug_state.map(|addr| base + addr) {
+            if let Ok(origin_byte) = inferior.write_byte(addr, 0xcc) {
+                let bp = BreakPoint { addr, origin_byte, condition: None, internal: true, temporary: false, dprintf: None };
+                self.breakpoints.insert(addr, bp);
+            }
+        }
+        self.shared_libraries.push(lib);
+    }
+
+    /// Re-reads the dynamic linker's list of loaded objects, loading the symbols of new
+    /// libraries and inserting the pending breakpoints that can now be resolved.
+    fn update_shared_libraries(&mut self) {
+        let inferior = self.inferior.as_ref().unwrap();
+        let objects = match shared_libraries::read_link_map(inferior, &self.target) {
+            Some(objects) => objects,
+            None => return,
+        };
+        self.shared_libraries.retain(|lib| objects.iter().any(|(name, _)| *name == lib.name));
+        for (name, base) in objects {
+            // The executable itself has an empty name
+            if name.is_empty() || self.shared_libraries.iter().any(|lib| lib.name == name) {
+                continue;
+            }
+            if let Some(lib) = SharedLibrary::load(&name, base, &self.debug_file_directories, self.index_cache) {
+                self.shared_libraries.push(lib);
+            }
+        }
+
+        for pending in std::mem::take(&mut self.pending_breakpoints) {
+            let addrs = self.parse_location(&pending.location);
+            let inferior = self.inferior.as_ref().unwrap();
+            // Addresses from a previous run may not be mapped yet
+            if addrs.is_empty() || addrs.iter().any(|&addr| inferior.read_memory(addr, 1).is_err()) {
+                self.pending_breakpoints.push(pending);
+                continue;
+            }
+            for addr in addrs {
+                self.set_bp(addr, pending.condition.clone(), pending.temporary, pending.dprintf.clone());
+            }
+        }
+    }
+
+    /// Evaluates `src` in the current frame of the stopped inferior. Returns the value along with
+    /// its printable representation.
+    fn evaluate(&self, src: &str) -> Result<(expr::Value, String), expr::Error> {
+        let ctx = self.eval_context()?;
+        let value = ctx.evaluate(src)?;
+        let formatted = ctx.format(&value);
+        Ok((value, formatted))
+    }
+
+    fn eval_context(&self) -> Result<expr::Context<'_>, expr::Error> {
+        let inferior = self.inferior.as_ref()
+            .ok_or_else(|| expr::Error::Invalid("The program is not being run.".to_string()))?;
+        let debug_data = self.debug_data.as_ref()
+            .ok_or_else(|| expr::Error::Invalid("No symbol table is loaded.".to_string()))?;
+        let mut ctx = expr::Context::new(inferior, debug_data)?;
+        ctx.format = self.settings.format;
+        Ok(ctx)
+    }
+
+    fn kill(&mut self) {
+        if let Some(inferior) = self.inferior.as_mut() {
+            match inferior.kill() {
+                Ok(()) => println!("killed inferior (pid: {})", inferior.pid()),
+                Err(_) => println!("failed to kill child")
+            }
+            self.inferior = None;
+        }
+    }
+
+    /// Resolves a breakpoint location: `*addr`, a line number, or a function name, optionally
+    /// qualified by a source file (`file.c:12`, `file.c:func`). A function
+    /// that was inlined resolves to every place its code was inlined into, along with its
+    /// out-of-line copy if there is one.
+    fn parse_location(&self, arg: &str) -> Vec<usize> {
+        if let Some(addr) = arg.strip_prefix('*') {
+            let addr = parse_addr(addr);
+            if addr.is_none() {
+                println!("parse addr error");
+            }
+            return addr.into_iter().collect();
+        }
+
+        let dbg_data = match self.debug_data.as_ref() {
+            Some(dbg_data) => dbg_data,
+            None => return Vec::new(),
+        };
+        if let Some((file, location)) = arg.split_once(':').filter(|(file, _)| file.contains('.')) {
+            let addr = match location.parse::<usize>() {
+                Ok(line) => dbg_data.get_addr_for_line(Some(file), line),
+                Err(_) => dbg_data
+                    .get_addr_for_function(Some(file), location)
+                    .map(|addr| dbg_data.skip_prologue(addr)),
+            };
+            return addr.into_iter().collect();
+        }
+        let source_file = self.target.to_owned() + ".c";
+        if let Ok(line) = arg.parse::<usize>() {
+            let addr = dbg_data.get_addr_for_line(Some(&source_file), line)
+                .or_else(|| dbg_data.get_addr_for_line(None, line));
+            if addr.is_none() {
+                println!("get addr for line error");
+            }
+            addr.into_iter().collect()
+        }
+        else {
+            let mut addrs: Vec<usize> = dbg_data.get_addr_for_function(Some(&source_file), arg)
+                .or_else(|| dbg_data.get_addr_for_function(None, arg))
+                // Stop after the prologue, so that arguments and locals can be printed
+                .map(|addr| dbg_data.skip_prologue(addr))
+                .into_iter()
+                .collect();
+            addrs.extend(dbg_data.get_inlined_addrs(arg));
+            // Functions without debug info, e.g. in a stripped binary
+            addrs.extend(dbg_data.get_symbol_addr(arg).filter(|_| addrs.is_empty()));
+            if addrs.is_empty() {
+                for lib in &self.shared_libraries {
+                    let lib_data = match lib.debug_data.as_ref() {
+                        Some(lib_data) => lib_data,
+                        None => continue,
+                    };
+                    let addr = lib_data.get_addr_for_function(None, arg)
+                        .map(|addr| lib_data.skip_prologue(addr))
+                        .or_else(|| lib_data.get_symbol_addr(arg));
+                    if let Some(addr) = addr {
+                        addrs.push(lib.base + addr);
+                        break;
+                    }
+                }
+            }
+            addrs
+        }
+    }
+
+    fn set_bp(&mut self, addr: usize, condition: Option<String>, temporary: bool, dprintf: Option<String>) {
+        let kind = match (temporary, dprintf.is_some()) {
+            (_, true) => "dprintf",
+            (true, false) => "temporary breakpoint",
+            (false, false) => "breakpoint",
+        };
+        if let Some(inferior) = self.inferior.as_ref() {
+            if let Some(bp) = self.breakpoints.get_mut(&addr) {
+                println!("{:#x} has already been set", addr);
+                bp.condition = condition;
+                bp.dprintf = dprintf;
+                // The library load hook must stay in place
+                if !bp.internal {
+                    bp.temporary = temporary;
+                }
+                return;
+            }
+            else if let Ok(origin_byte) = inferior.write_byte(addr, 0xcc) {
+                let bp = BreakPoint { addr, origin_byte, condition, internal: false, temporary, dprintf };
+                self.breakpoints.insert(addr, bp);
+            }
+            else {
+                println!("set breakpoint at: {:#x} error", addr);
+                return;
+            }
+        }
+        else {
+            let bp = self.breakpoints.entry(addr)
+            .or_insert(
+                BreakPoint {
+                    addr,
+                    origin_byte: 0,
+                    condition: None,
+                    internal: false,
+                    temporary,
+                    dprintf: None,
+                }
+            );
+            bp.condition = condition;
+            bp.dprintf = dprintf;
+            bp.temporary = temporary;
+        }
+
+        let count = self.breakpoints.values().filter(|bp| !bp.internal).count();
+        println!("set {} {} at {:#x}", kind, count - 1, addr);
+    }
+}
+
+/// Returns the C declaration of a variable called `name` of the type named `type_name`, e.g.
+/// `char buf[16]` for `char [16]`.
+fn declaration(type_name: &str, name: &str) -> String {
+    match type_name.find('[') {
+        Some(dims) => format!("{} {}{}", type_name[..dims].trim_end(), name, &type_name[dims..]),
+        None if type_name.ends_with('*') => format!("{}{}", type_name, name),
+        None => format!("{} {}", type_name, name),
+    }
+}
+
+/// Compiles the regular expression given to `info functions` and the like, which matches
+/// everything when there is none.
+fn search_regex(pattern: Option<&str>) -> Option<Regex> {
+    match Regex::new(pattern.unwrap_or("")) {
+        Ok(regex) => Some(regex),
+        Err(err) => {
+            println!("Invalid regexp: {}", err);
+            None
+        }
+    }
+}
+
+fn parse_addr(addr: &str) -> Option<usize> {
+    let addr = if addr.to_lowercase().starts_with("0x") {
         &addr[2..]
     }
     else {
-        &addr
+        addr
     };
 
     usize::from_str_radix(addr, 16).ok()
-}
\ No newline at end of file
+}
+
+/// Replaces `$argc` and `$arg0`, `$arg1`... in a line of a user-defined command with the
+/// arguments it was invoked with.
+fn substitute_args(line: &str, args: &[&str]) -> String {
+    let mut line = line.replace("$argc", &args.len().to_string());
+    // Higher numbers first, so that `$arg1` doesn't replace the start of `$arg10`
+    for (i, arg) in args.iter().enumerate().rev() {
+        line = line.replace(&format!("$arg{}", i), arg);
+    }
+    line
+}
+
+/// Describes where a variable is stored, as printed by `info address` and `info scope`.
+fn describe_storage(var: &Variable) -> String {
+    let kind = if var.is_parameter { "an argument" } else { "a variable" };
+    match &var.location {
+        Location::Address(addr) => format!("static storage at address {:#x}", addr),
+        Location::FramePointerOffset(offset) => format!("{} at frame base offset {}", kind, offset),
+        Location::Value(_) => "constant".to_string(),
+        Location::OptimizedOut => "optimized out".to_string(),
+        Location::Expression(..) | Location::List(..) => {
+            format!("{} with complex DWARF expression locating its address in memory", kind)
+        }
+    }
+}
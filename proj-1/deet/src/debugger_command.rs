@@ -3,11 +3,51 @@ pub enum DebuggerCommand {
     Run(Vec<String>),
     Continue,
     Backtrace,
-    Break(String),
+    Break(String, Option<String>, bool),
+    Delete(bool), // only the pending breakpoints
+    InfoBreakpoints,
+    Until(Option<String>),
+    Advance(String),
+    Dprintf(String, String),
+    Print(String),
+    Call(String),
+    Watch(String),
+    Step,
+    Next,
+    InfoSharedLibrary,
+    Checkpoint,
+    Restart(usize),
+    ReverseContinue,
+    InfoCheckpoints,
+    Record(Vec<String>),
+    Replay,
+    InfoRecord,
+    CatchSyscall(Vec<String>),
+    TraceSyscalls(bool),
+    SetVar(String),
+    SetOption(String, String),
+    UnsetEnvironment(Option<String>),
+    ShowOption(String),
+    Handle(String, Vec<String>),
+    InfoSignals(Option<String>),
+    Tui(bool),
+    Display(Option<String>),
+    Undisplay(Vec<usize>),
+    InfoDisplay,
+    InfoFunctions(Option<String>),
+    InfoVariables(Option<String>),
+    InfoTypes(Option<String>),
+    InfoLine(Option<String>),
+    InfoAddress(String),
+    InfoScope(String),
+    InfoProc,
+    InfoProcMappings,
+    InfoProcFds,
+    InfoAuxv,
 }
 
 impl DebuggerCommand {
-    pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
+    pub fn from_tokens(tokens: &[&str]) -> Option<DebuggerCommand> {
         match tokens[0] {
             "q" | "quit" => Some(DebuggerCommand::Quit),
             "r" | "run" => {
@@ -16,14 +56,120 @@ impl DebuggerCommand {
                     args.iter().map(|s| s.to_string()).collect(),
                 ))
             },
+            "record" => Some(DebuggerCommand::Record(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "replay" => Some(DebuggerCommand::Replay),
+            "catch" if tokens.get(1) == Some(&"syscall") => Some(DebuggerCommand::CatchSyscall(
+                tokens[2..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "trace" if tokens.get(1) == Some(&"syscalls") => match tokens.get(2) {
+                None | Some(&"on") => Some(DebuggerCommand::TraceSyscalls(true)),
+                Some(&"off") => Some(DebuggerCommand::TraceSyscalls(false)),
+                Some(_) => None,
+            },
             "c" | "cont" | "continue" => Some(DebuggerCommand::Continue),
             "bt" | "back" | "backtrace" => Some(DebuggerCommand::Backtrace),
-            "b" | "break" => {
-                let arg = tokens[1].to_string();
-                Some(DebuggerCommand::Break(arg))
+            "b" | "break" | "tbreak" => {
+                let arg = tokens.get(1)?.to_string();
+                // break <location> if <condition>
+                let condition = match tokens.get(2) {
+                    Some(&"if") if tokens.len() > 3 => Some(tokens[3..].join(" ")),
+                    Some(_) => return None,
+                    None => None,
+                };
+                Some(DebuggerCommand::Break(arg, condition, tokens[0] == "tbreak"))
+            },
+            "d" | "delete" => match tokens.get(1) {
+                None => Some(DebuggerCommand::Delete(false)),
+                Some(&"pending") if tokens.len() == 2 => Some(DebuggerCommand::Delete(true)),
+                Some(_) => None,
+            },
+            // dprintf <location>,"format",args...
+            "dprintf" => {
+                let args = tokens[1..].join(" ");
+                let (location, format) = args.split_once(',')?;
+                Some(DebuggerCommand::Dprintf(location.trim().to_string(), format.trim().to_string()))
+            },
+            "u" | "until" if tokens.len() <= 2 => Some(DebuggerCommand::Until(tokens.get(1).map(|s| s.to_string()))),
+            "advance" if tokens.len() == 2 => Some(DebuggerCommand::Advance(tokens[1].to_string())),
+            "p" | "print" if tokens.len() > 1 => Some(DebuggerCommand::Print(tokens[1..].join(" "))),
+            "call" if tokens.len() > 1 => Some(DebuggerCommand::Call(tokens[1..].join(" "))),
+            "display" => Some(DebuggerCommand::Display(Some(tokens[1..].join(" ")).filter(|src| !src.is_empty()))),
+            "undisplay" => Some(DebuggerCommand::Undisplay(
+                tokens[1..].iter().map(|n| n.parse().ok()).collect::<Option<_>>()?,
+            )),
+            "watch" if tokens.len() > 1 => Some(DebuggerCommand::Watch(tokens[1..].join(" "))),
+            "s" | "step" => Some(DebuggerCommand::Step),
+            "n" | "next" => Some(DebuggerCommand::Next),
+            "checkpoint" => Some(DebuggerCommand::Checkpoint),
+            "restart" => Some(DebuggerCommand::Restart(tokens.get(1)?.parse().ok()?)),
+            "rc" | "reverse-continue" => Some(DebuggerCommand::ReverseContinue),
+            "set" if matches!(tokens.get(1), Some(&"var") | Some(&"variable")) && tokens.len() > 2 => {
+                Some(DebuggerCommand::SetVar(tokens[2..].join(" ")))
+            },
+            // `set x = 3` and `set *(int *)0x601040 = 7` are assignments rather than options
+            "set" if tokens.len() > 1 && is_assignment(&tokens[1..]) => {
+                Some(DebuggerCommand::SetVar(tokens[1..].join(" ")))
+            },
+            // These options may be cleared by giving no value
+            "set" if matches!(tokens.get(1), Some(&"args") | Some(&"cwd") | Some(&"inferior-tty")) => {
+                Some(DebuggerCommand::SetOption(tokens[1].to_string(), tokens[2..].join(" ")))
+            },
+            "unset" if matches!(tokens.get(1), Some(&"environment") | Some(&"env")) && tokens.len() <= 3 => {
+                Some(DebuggerCommand::UnsetEnvironment(tokens.get(2).map(|name| name.to_string())))
+            },
+            "tty" if tokens.len() == 2 => Some(DebuggerCommand::SetOption("inferior-tty".to_string(), tokens[1].to_string())),
+            "set" if tokens.len() > 2 => {
+                Some(DebuggerCommand::SetOption(tokens[1].to_string(), tokens[2..].join(" ")))
+            },
+            "show" if tokens.len() > 1 => Some(DebuggerCommand::ShowOption(tokens[1..].join(" "))),
+            "handle" if tokens.len() > 1 => Some(DebuggerCommand::Handle(
+                tokens[1].to_string(),
+                tokens[2..].iter().map(|s| s.to_string()).collect(),
+            )),
+            // There is a single layout, with source, registers and stack
+            "layout" if tokens.len() == 2 => Some(DebuggerCommand::Tui(true)),
+            "tui" => match *tokens.get(1)? {
+                "enable" => Some(DebuggerCommand::Tui(true)),
+                "disable" => Some(DebuggerCommand::Tui(false)),
+                _ => None,
+            },
+            "i" | "info" => match *tokens.get(1)? {
+                "sharedlibrary" | "sharedlib" | "dll" => Some(DebuggerCommand::InfoSharedLibrary),
+                "b" | "break" | "breakpoints" => Some(DebuggerCommand::InfoBreakpoints),
+                "checkpoints" => Some(DebuggerCommand::InfoCheckpoints),
+                "record" => Some(DebuggerCommand::InfoRecord),
+                "display" => Some(DebuggerCommand::InfoDisplay),
+                "functions" => Some(DebuggerCommand::InfoFunctions(rest(&tokens[2..]))),
+                "variables" => Some(DebuggerCommand::InfoVariables(rest(&tokens[2..]))),
+                "types" => Some(DebuggerCommand::InfoTypes(rest(&tokens[2..]))),
+                "line" => Some(DebuggerCommand::InfoLine(rest(&tokens[2..]))),
+                "address" if tokens.len() == 3 => Some(DebuggerCommand::InfoAddress(tokens[2].to_string())),
+                "scope" => Some(DebuggerCommand::InfoScope(rest(&tokens[2..])?)),
+                "proc" => match tokens.get(2) {
+                    None => Some(DebuggerCommand::InfoProc),
+                    Some(&"mappings") => Some(DebuggerCommand::InfoProcMappings),
+                    Some(&"fds") | Some(&"files") => Some(DebuggerCommand::InfoProcFds),
+                    _ => None,
+                },
+                "auxv" => Some(DebuggerCommand::InfoAuxv),
+                "signals" | "handle" => Some(DebuggerCommand::InfoSignals(tokens.get(2).map(|s| s.to_string()))),
+                _ => None,
             },
             // Default case:
             _ => None,
         }
     }
 }
+
+/// Joins the remaining arguments of a command, if there are any.
+fn rest(tokens: &[&str]) -> Option<String> {
+    Some(tokens.join(" ")).filter(|rest| !rest.is_empty())
+}
+
+fn is_assignment(tokens: &[&str]) -> bool {
+    !tokens[0].starts_with(char::is_alphabetic)
+        || tokens.get(1) == Some(&"=")
+        || tokens[0].contains('=')
+}
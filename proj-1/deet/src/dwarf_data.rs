@@ -1,9 +1,20 @@
-use crate::gimli_wrapper;
+use crate::gimli_wrapper::{self, CallFrameInfo, DwarfReader, UnwindRow};
 use addr2line::Context;
-use object::Object;
+use object::{Object, ObjectSymbol, SymbolKind};
+use regex::Regex;
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::{fmt, fs};
 
+/// Where separate debug files are looked for unless configured otherwise.
+pub const DEFAULT_DEBUG_FILE_DIRECTORY: &str = "/usr/lib/debug";
+
+/// First line of the files in the index cache. Bump the version whenever `Index` changes.
+const INDEX_CACHE_HEADER: &str = "deet index 1";
+
 #[derive(Debug)]
 pub enum Error {
     ErrorOpeningFile,
@@ -11,13 +22,192 @@ pub enum Error {
 }
 
 pub struct DwarfData {
-    files: Vec<File>,
-    addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    dwarf: gimli::Dwarf<DwarfReader>,
+    index: Index,
+    units: Vec<OnceCell<Unit>>, // Parsed on first use, in the order of `index.units`
+    frame_info: CallFrameInfo,
+    symbols: Vec<Symbol>,
+    addr2line: Context<DwarfReader>,
+    // Where a position-independent executable was loaded. Addresses given to and returned by
+    // DwarfData include it, while the debug info itself is read at link-time addresses.
+    load_bias: usize,
+}
+
+/// What is known about the debug info before any unit is parsed: enough to find the unit that
+/// defines a name or contains an address. Building it only reads the top-level DIEs, and it is
+/// cached on disk by build ID so that loading the same binary again reads nothing at all.
+#[derive(Debug, Default, PartialEq)]
+pub struct Index {
+    pub units: Vec<(usize, String)>, // DIE offset and source file of each unit, sorted by offset
+    pub ranges: Vec<(usize, usize, usize)>, // [begin, end) code addresses of a unit, sorted
+    pub functions: BTreeMap<String, Vec<usize>>, // Name to DIE offsets, for functions with code
+    pub declarations: BTreeMap<String, Vec<usize>>, // Name to DIE offsets, for functions without
+    pub variables: BTreeMap<String, Vec<usize>>, // Name to DIE offsets of global variables
+    pub types: BTreeMap<String, Vec<usize>>, // Name to DIE offsets of type definitions
+}
+
+impl Index {
+    /// Reads the index of `object` from the cache, or builds it and saves it there. A cached
+    /// index that does not fit the debug info is rebuilt. Without `use_cache`, the index is
+    /// always built and never saved.
+    fn load(object: &object::File, dwarf: &gimli::Dwarf<DwarfReader>, use_cache: bool) -> Result<Index, Error> {
+        let path = build_id(object)
+            .filter(|_| use_cache)
+            .and_then(|build_id| index_cache_path(&build_id));
+        if let Some(index) = path.as_ref().and_then(|path| Index::read(path)) {
+            if index.fits(dwarf) {
+                return Ok(index);
+            }
+        }
+        let index = gimli_wrapper::scan_units(dwarf)?;
+        if let Some(path) = path.filter(|_| !index.units.is_empty()) {
+            // The cache is only an optimization, so failing to write it is not an error
+            let _ = index.write(&path);
+        }
+        Ok(index)
+    }
+
+    /// Serializes the index, one tab-separated entry per line with the name last.
+    fn to_text(&self) -> String {
+        let mut text = format!("{}\n", INDEX_CACHE_HEADER);
+        for (offset, name) in &self.units {
+            text.push_str(&format!("unit\t{:x}\t{}\n", offset, name));
+        }
+        for (begin, end, unit) in &self.ranges {
+            text.push_str(&format!("range\t{:x}\t{:x}\t{}\n", begin, end, unit));
+        }
+        let tables = [
+            ("function", &self.functions),
+            ("declaration", &self.declarations),
+            ("variable", &self.variables),
+            ("type", &self.types),
+        ];
+        for (kind, table) in tables {
+            for (name, offsets) in table {
+                let offsets: Vec<String> = offsets.iter().map(|offset| format!("{:x}", offset)).collect();
+                text.push_str(&format!("{}\t{}\t{}\n", kind, offsets.join(","), name));
+            }
+        }
+        text
+    }
+
+    fn from_text(text: &str) -> Option<Index> {
+        let mut lines = text.lines();
+        if lines.next()? != INDEX_CACHE_HEADER {
+            return None;
+        }
+        let hex = |field: &str| usize::from_str_radix(field, 16).ok();
+        let mut index = Index::default();
+        for line in lines {
+            let mut fields = line.splitn(3, '\t');
+            let (kind, first, rest) = (fields.next()?, fields.next()?, fields.next()?);
+            let table = match kind {
+                "unit" => {
+                    index.units.push((hex(first)?, rest.to_string()));
+                    continue;
+                }
+                "range" => {
+                    let (end, unit) = rest.split_once('\t')?;
+                    index.ranges.push((hex(first)?, hex(end)?, unit.parse().ok()?));
+                    continue;
+                }
+                "function" => &mut index.functions,
+                "declaration" => &mut index.declarations,
+                "variable" => &mut index.variables,
+                "type" => &mut index.types,
+                _ => return None,
+            };
+            let offsets: Option<Vec<usize>> = first.split(',').map(hex).collect();
+            table.insert(rest.to_string(), offsets?);
+        }
+        Some(index)
+    }
+
+    /// Checks an index read from the cache against `dwarf`, since the file may be corrupt or come
+    /// from another binary with the same build ID: it must list the same units, and all its
+    /// offsets must be in .debug_info.
+    fn fits(&self, dwarf: &gimli::Dwarf<DwarfReader>) -> bool {
+        let mut unit_offsets = Vec::new();
+        let mut headers = dwarf.units();
+        while let Ok(Some(header)) = headers.next() {
+            if let gimli::UnitSectionOffset::DebugInfoOffset(offset) = header.offset() {
+                unit_offsets.push(offset.0);
+            }
+        }
+        let size = gimli::Reader::len(gimli::Section::reader(&dwarf.debug_info));
+        let tables = [&self.functions, &self.declarations, &self.variables, &self.types];
+        self.units.iter().map(|(offset, _)| *offset).eq(unit_offsets)
+            && self.ranges.iter().all(|&(begin, end, unit)| begin <= end && unit < self.units.len())
+            && tables.iter().flat_map(|table| table.values().flatten()).all(|&offset| offset < size)
+    }
+
+    fn read(path: &Path) -> Option<Index> {
+        Index::from_text(&fs::read_to_string(path).ok()?)
+    }
+
+    fn write(&self, path: &Path) -> std::io::Result<()> {
+        fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+        // Write to a temporary file first so that another deet never reads half an index
+        let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&partial, self.to_text())?;
+        fs::rename(&partial, path)
+    }
+}
+
+/// A compilation unit parsed in full.
+#[derive(Default)]
+struct Unit {
+    file: File,
+    types: HashMap<usize, Type>,
+    lines: Vec<(usize, usize)>, // (address, index into file.lines), sorted by address
+}
+
+impl Unit {
+    fn new(file: File, types: HashMap<usize, Type>) -> Unit {
+        let mut lines: Vec<(usize, usize)> =
+            file.lines.iter().enumerate().map(|(i, line)| (line.address, i)).collect();
+        lines.sort();
+        Unit { file, types, lines }
+    }
+
+    /// Moves every address in the unit by `delta` (wrapping, so that it can move down).
+    fn relocate(&mut self, delta: usize) {
+        let file = &mut self.file;
+        for func in &mut file.functions {
+            func.address = func.address.wrapping_add(delta);
+            func.frame_base.iter_mut().for_each(|location| location.relocate(delta));
+            func.variables.iter_mut().for_each(|var| var.location.relocate(delta));
+        }
+        for inlined in &mut file.inlined_functions {
+            for (begin, end) in &mut inlined.ranges {
+                *begin = begin.wrapping_add(delta);
+                *end = end.wrapping_add(delta);
+            }
+            inlined.entry_address = inlined.entry_address.wrapping_add(delta);
+            inlined.variables.iter_mut().for_each(|var| var.location.relocate(delta));
+        }
+        file.global_variables.iter_mut().for_each(|var| var.location.relocate(delta));
+        for line in &mut file.lines {
+            line.address = line.address.wrapping_add(delta);
+        }
+        for (address, _) in &mut self.lines {
+            *address = address.wrapping_add(delta);
+        }
+    }
 }
 
 impl fmt::Debug for DwarfData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "DwarfData {{files: {:?}}}", self.files)
+        write!(f, "DwarfData {{units: {:?}}}", self.index.units)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ErrorOpeningFile => write!(f, "could not open file"),
+            Error::DwarfFormatError(err) => write!(f, "{}", err),
+        }
     }
 }
 
@@ -28,62 +218,226 @@ impl From<gimli_wrapper::Error> for Error {
 }
 
 impl DwarfData {
-    pub fn from_file(path: &str) -> Result<DwarfData, Error> {
+    /// Loads the debug info of the executable or shared object at `path`. When the file itself
+    /// has been stripped of its DWARF sections, they are read from a separate debug file found
+    /// through its build ID or `.gnu_debuglink` in `debug_file_directories`. With `index_cache`,
+    /// the index of the debug info is read from and saved to the cache on disk.
+    pub fn from_file(path: &str, debug_file_directories: &[String], index_cache: bool) -> Result<DwarfData, Error> {
+        Self::load(path, debug_file_directories, true, index_cache)
+    }
+
+    /// Loads only the ELF symbol table and call frame information of `path`, for binaries whose
+    /// DWARF sections are missing or can't be parsed.
+    pub fn from_symbol_table(path: &str) -> Result<DwarfData, Error> {
+        Self::load(path, &[], false, false)
+    }
+
+    fn load(
+        path: &str,
+        debug_file_directories: &[String],
+        read_dwarf: bool,
+        index_cache: bool,
+    ) -> Result<DwarfData, Error> {
         let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
         let mmap = unsafe { memmap2::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
         let object = object::File::parse(&*mmap)
-            .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+            .map_err(|e| gimli_wrapper::Error::ObjectError(e.to_string()))?;
         let endian = if object.is_little_endian() {
             gimli::RunTimeEndian::Little
         } else {
             gimli::RunTimeEndian::Big
         };
+
+        let debug_mmap = if read_dwarf && object.section_by_name(".debug_info").is_none() {
+            find_debug_file(path, &object, debug_file_directories)
+                .and_then(|debug_path| fs::File::open(debug_path).ok())
+                .and_then(|file| unsafe { memmap2::Mmap::map(&file).ok() })
+        } else {
+            None
+        };
+        let debug_object = match debug_mmap.as_ref() {
+            Some(debug_mmap) => Some(
+                object::File::parse(&**debug_mmap)
+                    .map_err(|e| gimli_wrapper::Error::ObjectError(e.to_string()))?,
+            ),
+            None => None,
+        };
+        let dwarf_object = debug_object.as_ref().unwrap_or(&object);
+
+        let (dwarf, index) = if read_dwarf {
+            let dwarf = gimli_wrapper::load_dwarf(dwarf_object, endian)?;
+            let index = Index::load(dwarf_object, &dwarf, index_cache)?;
+            (dwarf, index)
+        } else {
+            // No units, so every lookup comes back empty
+            let empty = gimli::EndianRcSlice::new(Rc::from(&[][..]), endian);
+            let dwarf = gimli::Dwarf::load(|_| Ok::<_, gimli::Error>(empty.clone()))
+                .map_err(gimli_wrapper::Error::from)?;
+            (dwarf, Index::default())
+        };
+        let symbols = object
+            .symbols()
+            .chain(object.dynamic_symbols())
+            .chain(debug_object.iter().flat_map(|debug_object| debug_object.symbols()))
+            .filter(|sym| sym.kind() == SymbolKind::Text && sym.address() != 0)
+            .filter_map(|sym| {
+                // Dynamic symbols may carry a version, e.g. `malloc@@GLIBC_2.2.5`
+                let linkage_name = sym.name().ok()?.split('@').next()?;
+                Some(Symbol {
+                    name: demangle(linkage_name),
+                    qualified_name: qualified_name(linkage_name),
+                    address: sym.address() as usize,
+                    size: sym.size() as usize,
+                })
+            })
+            .collect();
+        // addr2line shares the sections already in memory
+        let addr2line = Context::from_dwarf(dwarf.borrow(|section| section.clone()))
+            .map_err(gimli_wrapper::Error::from)?;
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            units: index.units.iter().map(|_| OnceCell::new()).collect(),
+            dwarf,
+            index,
+            frame_info: CallFrameInfo::load(&object, endian),
+            symbols,
+            addr2line,
+            load_bias: 0,
         })
     }
 
-    #[allow(dead_code)]
-    fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
+    pub fn load_bias(&self) -> usize {
+        self.load_bias
+    }
+
+    /// Moves the addresses of a position-independent executable to where it was loaded, `bias`
+    /// bytes above the addresses it was linked at.
+    pub fn set_load_bias(&mut self, bias: usize) {
+        let delta = bias.wrapping_sub(self.load_bias);
+        if delta == 0 {
+            return;
+        }
+        for unit in self.units.iter_mut().filter_map(OnceCell::get_mut) {
+            unit.relocate(delta);
+        }
+        for symbol in &mut self.symbols {
+            symbol.address = symbol.address.wrapping_add(delta);
+        }
+        self.load_bias = bias;
+    }
+
+    /// Returns true if DWARF debug info (rather than only a symbol table) was found.
+    pub fn has_debug_info(&self) -> bool {
+        !self.index.units.is_empty()
+    }
+
+    /// Returns the unit with the given position in `index.units`, parsing it the first time.
+    fn unit(&self, number: usize) -> &Unit {
+        self.units[number].get_or_init(|| {
+            let (offset, name) = &self.index.units[number];
+            match gimli_wrapper::load_unit(&self.dwarf, *offset) {
+                Ok((file, types)) => {
+                    let mut unit = Unit::new(file, types);
+                    unit.relocate(self.load_bias);
+                    unit
+                }
+                Err(err) => {
+                    println!("Could not read debugging symbols for {}: {}", name, err);
+                    Unit::default()
+                }
+            }
         })
     }
 
+    /// Returns the unit containing the DIE at `offset`.
+    fn unit_of(&self, offset: usize) -> Option<&Unit> {
+        let after = self.index.units.partition_point(|&(unit_offset, _)| unit_offset <= offset);
+        Some(self.unit(after.checked_sub(1)?))
+    }
+
+    /// Returns the unit whose code contains `addr`.
+    fn unit_at(&self, addr: usize) -> Option<&Unit> {
+        let addr = addr.wrapping_sub(self.load_bias);
+        let ranges = &self.index.ranges;
+        let after = ranges.partition_point(|&(begin, _, _)| begin <= addr);
+        let &(_, end, unit) = ranges.get(after.checked_sub(1)?)?;
+        if addr < end {
+            Some(self.unit(unit))
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether `addr` is in the code of a unit. Code the linker threw away (with
+    /// `--gc-sections` or LTO) keeps its line rows and ranges, but at address 0.
+    fn is_linked_code(&self, addr: usize) -> bool {
+        let addr = addr.wrapping_sub(self.load_bias);
+        let ranges = &self.index.ranges;
+        let after = ranges.partition_point(|&(begin, _, _)| begin <= addr);
+        after.checked_sub(1)
+            .and_then(|i| ranges.get(i))
+            .is_some_and(|&(begin, end, _)| begin > 0 && addr < end)
+    }
+
+    /// Returns the units compiled from `file`, a full path or the name of a file.
+    fn units_named<'a>(&'a self, file: &'a str) -> impl Iterator<Item = &'a Unit> {
+        self.index
+            .units
+            .iter()
+            .enumerate()
+            .filter(move |(_, (_, name))| {
+                name == file || (!file.contains('/') && name.ends_with(&format!("/{}", file)))
+            })
+            .map(|(number, _)| self.unit(number))
+    }
+
+    /// Returns the units that may define or inline a function called `name`.
+    fn units_with_function(&self, name: &str) -> Vec<&Unit> {
+        // Qualified names are indexed by their last component, e.g. `area` for `Rect::area`
+        let key = name.rsplit("::").next().unwrap_or(name);
+        let offsets = self.index.functions.get(key).into_iter().chain(self.index.declarations.get(key));
+        let mut units: Vec<&Unit> = Vec::new();
+        for &offset in offsets.flatten() {
+            if let Some(unit) = self.unit_of(offset) {
+                if !units.iter().any(|other| std::ptr::eq(*other, unit)) {
+                    units.push(unit);
+                }
+            }
+        }
+        units
+    }
+
+    /// Returns the function whose DIE is at `offset`, and the file it is in.
+    fn function_by_offset(&self, offset: usize) -> Option<(&File, &Function)> {
+        let unit = self.unit_of(offset)?;
+        Some((&unit.file, unit.file.functions.iter().find(|func| func.offset == offset)?))
+    }
+
     #[allow(dead_code)]
     pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
-        let target_file = match file {
-            Some(filename) => self.get_target_file(filename)?,
-            None => self.files.get(0)?,
+        let units: Vec<&Unit> = match file {
+            Some(filename) => self.units_named(filename).collect(),
+            None => self.index.units.first().map(|_| self.unit(0)).into_iter().collect(),
         };
-        Some(
-            target_file
-                .lines
-                .iter()
-                .find(|line| line.number >= line_number)?
-                .address,
-        )
+        // A file may be split across several units, as with Rust's codegen units. The line table
+        // is not in source order (Rust puts cleanup code for the end of a function before its
+        // body), so take the nearest line at or after `line_number`, at its lowest address.
+        units
+            .iter()
+            .flat_map(|unit| unit.file.lines.iter())
+            .filter(|line| line.number >= line_number && self.is_linked_code(line.address))
+            .min_by_key(|line| (line.number, line.address))
+            .map(|line| line.address)
     }
 
     #[allow(dead_code)]
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
         match file {
-            Some(filename) => Some(
-                self.get_target_file(filename)?
-                    .functions
-                    .iter()
-                    .find(|func| func.name == func_name)?
-                    .address,
-            ),
-            None => {
-                for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
-                        return Some(func.address);
-                    }
-                }
-                None
-            }
+            Some(filename) => self
+                .units_named(filename)
+                .flat_map(|unit| unit.file.functions.iter())
+                .find(|func| func.matches(func_name) && func.text_length > 0)
+                .map(|func| func.address),
+            None => self.get_function(func_name).map(|func| func.address),
         }
     }
 
@@ -91,7 +445,7 @@ impl DwarfData {
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
         let location = self
             .addr2line
-            .find_location(curr_addr.try_into().unwrap())
+            .find_location(curr_addr.wrapping_sub(self.load_bias) as u64)
             .ok()??;
         Some(Line {
             file: location.file?.to_string(),
@@ -100,80 +454,485 @@ impl DwarfData {
         })
     }
 
-    #[allow(dead_code)]
-    pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
-        let frame = self
-            .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
-            .ok()?
-            .next()
-            .ok()??;
-        Some(frame.function?.raw_name().ok()?.to_string())
+    /// Returns the source-level frames at `curr_addr`, innermost first: every function inlined
+    /// at that address followed by the function that physically contains it. The line of an
+    /// outer frame is the call site of the frame before it.
+    pub fn get_frames_from_addr(&self, curr_addr: usize) -> Vec<(String, Option<Line>)> {
+        let mut result = Vec::new();
+        let mut frames = match self.addr2line.find_frames(curr_addr.wrapping_sub(self.load_bias) as u64) {
+            Ok(frames) => frames,
+            Err(_) => return result,
+        };
+        while let Ok(Some(frame)) = frames.next() {
+            let name = match frame.function.as_ref().and_then(|f| f.raw_name().ok()) {
+                Some(name) => qualified_name(&name),
+                None => continue,
+            };
+            let line = frame.location.and_then(|location| {
+                Some(Line {
+                    file: location.file?.to_string(),
+                    number: location.line? as usize,
+                    address: curr_addr,
+                })
+            });
+            result.push((name, line));
+        }
+        result
     }
 
-    #[allow(dead_code)]
-    pub fn print(&self) {
-        for file in &self.files {
-            println!("------");
-            println!("{}", file.name);
-            println!("------");
-
-            println!("Global variables:");
-            for var in &file.global_variables {
-                println!(
-                    "  * {} ({}, located at {}, declared at line {})",
-                    var.name, var.entity_type.name, var.location, var.line_number
-                );
+
+    /// Returns the inlined copies of functions whose code contains `addr`, innermost first.
+    pub fn get_inlined_at(&self, addr: usize) -> Vec<&InlinedFunction> {
+        let mut inlined: Vec<&InlinedFunction> = self
+            .unit_at(addr)
+            .iter()
+            .flat_map(|unit| unit.file.inlined_functions.iter())
+            .filter(|f| f.ranges.iter().any(|&(begin, end)| begin <= addr && addr < end))
+            .collect();
+        inlined.sort_by_key(|f| f.ranges.iter().map(|(begin, end)| end - begin).sum::<usize>());
+        inlined
+    }
+
+    /// Returns the entry addresses of every place `func_name` was inlined into another function.
+    pub fn get_inlined_addrs(&self, func_name: &str) -> Vec<usize> {
+        self.units_with_function(func_name)
+            .iter()
+            .flat_map(|unit| unit.file.inlined_functions.iter())
+            .filter(|f| {
+                (f.name == func_name || names_match(&f.qualified_name, func_name))
+                    && f.entry_address != 0
+            })
+            .map(|f| f.entry_address)
+            .collect()
+    }
+
+    /// Returns true if `addr` is the first instruction of a row in the line table.
+    pub fn is_line_start(&self, addr: usize) -> bool {
+        self.unit_at(addr)
+            .is_some_and(|unit| unit.lines.binary_search_by_key(&addr, |&(address, _)| address).is_ok())
+    }
+
+    /// Returns the line-table row containing `addr` and the address where the code for that row
+    /// ends, as `info line` shows them.
+    pub fn get_line_range(&self, addr: usize) -> Option<(&Line, usize)> {
+        let unit = self.unit_at(addr)?;
+        let after = unit.lines.partition_point(|&(address, _)| address <= addr);
+        let &(start, line) = unit.lines.get(after.checked_sub(1)?)?;
+        let end = unit.lines[after..].iter().map(|&(address, _)| address).find(|&address| address > start)?;
+        Some((&unit.file.lines[line], end))
+    }
+
+    /// Returns the address of the first line of the function body following `func_addr`, i.e.
+    /// the address right after the function prologue has set up the stack frame.
+    pub fn skip_prologue(&self, func_addr: usize) -> usize {
+        let (func, unit) = match self.get_function_at(func_addr).zip(self.unit_at(func_addr)) {
+            Some(found) => found,
+            None => return func_addr,
+        };
+        let end = func.address + func.text_length;
+        let after = unit.lines.partition_point(|&(address, _)| address <= func.address);
+        match unit.lines.get(after) {
+            Some(&(address, _)) if address < end => address,
+            _ => func_addr,
+        }
+    }
+
+    /// Returns the function whose code contains `addr`.
+    pub fn get_function_at(&self, addr: usize) -> Option<&Function> {
+        self.unit_at(addr)?.file.functions.iter().find(|func| {
+            func.text_length > 0 && func.address <= addr && addr < func.address + func.text_length
+        })
+    }
+
+    /// Returns the function called `name`, if it has code in this binary.
+    pub fn get_function(&self, name: &str) -> Option<&Function> {
+        let key = name.rsplit("::").next().unwrap_or(name);
+        self.index
+            .functions
+            .get(key)?
+            .iter()
+            .filter_map(|&offset| self.function_by_offset(offset))
+            .map(|(_, func)| func)
+            .find(|func| func.matches(name) && func.text_length > 0)
+    }
+
+    /// Returns the address of the function called `name` in the ELF symbol table.
+    pub fn get_symbol_addr(&self, name: &str) -> Option<usize> {
+        self.symbols
+            .iter()
+            .find(|sym| sym.name == name || names_match(&sym.qualified_name, name))
+            .map(|sym| sym.address)
+    }
+
+    /// Returns the ELF symbol of the function containing `addr`.
+    pub fn get_symbol_at(&self, addr: usize) -> Option<&Symbol> {
+        self.symbols
+            .iter()
+            .filter(|sym| sym.address <= addr && addr < sym.address + sym.size.max(1))
+            // Of several aliases, prefer the public name (`puts` over `_IO_puts`)
+            .max_by_key(|sym| (sym.address, !sym.name.starts_with('_')))
+    }
+
+    pub fn get_global_variable(&self, name: &str) -> Option<&Variable> {
+        self.index.variables.get(name)?.iter().find_map(|&offset| {
+            self.unit_of(offset)?.file.global_variables.iter().find(|var| var.name == name)
+        })
+    }
+
+    /// Returns the functions with code whose names match `regex`, with the source file each
+    /// is in, ordered by file and name.
+    pub fn search_functions(&self, regex: &Regex) -> Vec<(&str, &Function)> {
+        let mut found: Vec<(&str, &Function)> = self
+            .index
+            .functions
+            .iter()
+            .filter(|(name, _)| regex.is_match(name))
+            .flat_map(|(_, offsets)| offsets.iter())
+            .filter_map(|&offset| self.function_by_offset(offset))
+            .map(|(file, func)| (file.name.as_str(), func))
+            .collect();
+        found.sort_by_key(|(file, func)| (*file, func.name.as_str()));
+        found
+    }
+
+    /// Returns the global variables whose names match `regex`, with the source file each is
+    /// in, ordered by file and name.
+    pub fn search_variables(&self, regex: &Regex) -> Vec<(&str, &Variable)> {
+        let mut found: Vec<(&str, &Variable)> = self
+            .index
+            .variables
+            .iter()
+            .filter(|(name, _)| regex.is_match(name))
+            .flat_map(|(name, offsets)| offsets.iter().map(move |&offset| (name, offset)))
+            .filter_map(|(name, offset)| {
+                let file = &self.unit_of(offset)?.file;
+                let var = file.global_variables.iter().find(|var| &var.name == name)?;
+                Some((file.name.as_str(), var))
+            })
+            .collect();
+        found.sort_by_key(|(file, var)| (*file, var.name.as_str()));
+        found.dedup_by_key(|(file, var)| (*file, var.name.as_str()));
+        found
+    }
+
+    /// Returns the names of the types matching `regex`, sorted.
+    pub fn search_types(&self, regex: &Regex) -> Vec<&str> {
+        let mut found: Vec<&str> = self
+            .index
+            .types
+            .iter()
+            .filter(|(name, _)| regex.is_match(name))
+            .flat_map(|(_, offsets)| offsets.iter())
+            .filter_map(|&offset| self.get_type(offset))
+            // Types are indexed under every name they may end up with
+            .map(|ty| ty.name.as_str())
+            .filter(|name| !name.is_empty() && regex.is_match(name))
+            .collect();
+        found.sort();
+        found.dedup();
+        found
+    }
+
+    /// Returns the ELF symbols matching `regex` that have no debug info, sorted by address.
+    pub fn search_symbols(&self, regex: &Regex) -> Vec<&Symbol> {
+        let mut found: Vec<&Symbol> = self
+            .symbols
+            .iter()
+            .filter(|sym| regex.is_match(&sym.name) && !self.index.functions.contains_key(&sym.name))
+            .collect();
+        found.sort_by_key(|sym| (sym.address, &sym.name));
+        found.dedup_by_key(|sym| (sym.address, &sym.name));
+        found
+    }
+
+    /// Returns the names of the functions with code in this binary, from the debug info and the
+    /// symbol table, sorted and without duplicates.
+    pub fn function_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .index
+            .functions
+            .keys()
+            .cloned()
+            .chain(self.symbols.iter().map(|sym| sym.qualified_name.clone()))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Returns the paths of the source files in the debug info.
+    pub fn file_names(&self) -> Vec<String> {
+        self.index.units.iter().map(|(_, name)| name.clone()).collect()
+    }
+
+    /// Returns the contents of every unit, parsing all of them.
+    pub fn files(&self) -> Vec<&File> {
+        (0..self.index.units.len()).map(|number| &self.unit(number).file).collect()
+    }
+
+    pub fn global_variable_names(&self) -> Vec<String> {
+        self.index.variables.keys().cloned().collect()
+    }
+
+    /// Returns the call frame information (how to find the CFA and saved registers) for `pc`.
+    pub fn unwind_row(&self, pc: usize) -> Option<UnwindRow> {
+        self.frame_info.unwind_row(pc.wrapping_sub(self.load_bias) as u64)
+    }
+
+    pub fn get_type(&self, offset: usize) -> Option<&Type> {
+        self.unit_of(offset)?.types.get(&offset)
+    }
+
+    /// Looks up a type by the name it is displayed with (e.g. `int`, `struct point`, `size_t`).
+    pub fn get_type_by_name(&self, name: &str) -> Option<&Type> {
+        let mut offsets = self.index.types.get(name)?.clone();
+        // Any unit defining the type will do, so avoid parsing another one
+        offsets.sort_by_key(|&offset| {
+            let after = self.index.units.partition_point(|&(unit_offset, _)| unit_offset <= offset);
+            after.checked_sub(1).is_none_or(|number| self.units[number].get().is_none())
+        });
+        offsets.into_iter().filter_map(|offset| self.get_type(offset)).find(|ty| ty.name == name)
+    }
+
+    /// Strips typedefs and qualifiers until the underlying type is reached.
+    pub fn resolve_type<'a>(&'a self, dtype: &'a Type) -> &'a Type {
+        let mut dtype = dtype;
+        for _ in 0..16 {
+            match dtype.kind {
+                TypeKind::Typedef(Some(target)) => match self.get_type(target) {
+                    Some(target) => dtype = target,
+                    None => break,
+                },
+                _ => break,
             }
+        }
+        dtype
+    }
+}
 
-            println!("Functions:");
-            for func in &file.functions {
-                println!(
-                    "  * {} (declared on line {}, located at {:#x}, {} bytes long)",
-                    func.name, func.line_number, func.address, func.text_length
-                );
-                for var in &func.variables {
-                    println!(
-                        "    * Variable: {} ({}, located at {}, declared at line {})",
-                        var.name, var.entity_type.name, var.location, var.line_number
-                    );
-                }
+/// Returns the build ID of `object` in hex, which identifies the exact build of a binary and of
+/// its separate debug file.
+fn build_id(object: &object::File) -> Option<String> {
+    let build_id = object.build_id().ok()??;
+    Some(build_id.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Returns where the index of the binary with the given build ID is cached:
+/// `$XDG_CACHE_HOME/deet/` or `~/.cache/deet/`.
+fn index_cache_path(build_id: &str) -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(dir.join("deet").join(format!("{}.index", build_id)))
+}
+
+/// Looks for the separate debug file of `object`, the way gdb does: first by build ID under
+/// `<dir>/.build-id/`, then by the name in `.gnu_debuglink` next to `path`, in its `.debug`
+/// subdirectory, or under `<dir>/<directory of path>/`. Debug links are checked against their CRC.
+fn find_debug_file(
+    path: &str,
+    object: &object::File,
+    debug_file_directories: &[String],
+) -> Option<PathBuf> {
+    if let Some(hex) = build_id(object) {
+        if hex.len() > 2 {
+            let found = debug_file_directories
+                .iter()
+                .map(|dir| Path::new(dir).join(".build-id").join(&hex[..2]).join(format!("{}.debug", &hex[2..])))
+                .find(|candidate| candidate.is_file());
+            if found.is_some() {
+                return found;
             }
+        }
+    }
+
+    let (name, crc) = object.gnu_debuglink().ok()??;
+    let name = std::str::from_utf8(name).ok()?;
+    let binary = fs::canonicalize(path).ok()?;
+    let dir = binary.parent()?;
+    let mut candidates = vec![dir.join(name), dir.join(".debug").join(name)];
+    for debug_dir in debug_file_directories {
+        candidates.push(Path::new(debug_dir).join(dir.strip_prefix("/").ok()?).join(name));
+    }
+    candidates.into_iter().find(|candidate| {
+        *candidate != binary && fs::read(candidate).is_ok_and(|data| crc32(&data) == crc)
+    })
+}
 
-            println!("Line numbers:");
-            for line in &file.lines {
-                println!("  * {} (at {:#x})", line.number, line.address);
+/// Demangles a C++ (Itanium) or Rust (v0 or legacy) symbol name. Other names are returned as
+/// they are.
+pub fn demangle(linkage_name: &str) -> String {
+    addr2line::demangle_auto(linkage_name.into(), None).into_owned()
+}
+
+/// Returns the name a function is shown and looked up by: demangled, but without parameter
+/// types, qualifiers or Rust's hash, e.g. `geo::Rect::area` for `_ZNK3geo4Rect4areaEv`.
+pub fn qualified_name(linkage_name: &str) -> String {
+    let demangled = demangle(linkage_name);
+    let mut name = demangled.as_str();
+    for qualifier in [" const", " volatile", " &&", " &"] {
+        name = name.strip_suffix(qualifier).unwrap_or(name);
+    }
+    // Strip the parameter list, which may itself contain parentheses
+    if name.ends_with(')') {
+        let mut depth = 0;
+        for (i, c) in name.char_indices().rev() {
+            match c {
+                ')' => depth += 1,
+                '(' => depth -= 1,
+                _ => {}
             }
+            if depth == 0 {
+                return name[..i].to_string();
+            }
+        }
+    }
+    name.to_string()
+}
+
+/// Returns true if `name` is `qualified_name` or a suffix of it starting after a `::`.
+fn names_match(qualified_name: &str, name: &str) -> bool {
+    qualified_name == name
+        || qualified_name
+            .strip_suffix(name)
+            .is_some_and(|prefix| prefix.ends_with("::"))
+}
+
+/// The CRC-32 (IEEE) checksum `.gnu_debuglink` uses to identify the debug file.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
         }
     }
+    !crc
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
+    pub offset: Option<usize>, // Offset of the DIE this type was read from, if any
 }
 
 impl Type {
     pub fn new(name: String, size: usize) -> Self {
         Type {
-            name: name,
-            size: size,
+            name,
+            size,
+            kind: TypeKind::Unknown,
+            offset: None,
+        }
+    }
+
+    pub fn with_kind(name: &str, size: usize, kind: TypeKind) -> Self {
+        Type {
+            name: name.to_string(),
+            size,
+            kind,
+            offset: None,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaseEncoding {
+    Signed,
+    Unsigned,
+    SignedChar,
+    UnsignedChar,
+    Float,
+    Bool,
+}
+
+// Types that refer to other types (pointers, arrays, typedefs, ...) store the DIE offset of the
+// referenced type, which can be looked up with DwarfData::get_type.
+#[derive(Debug, Clone, Default)]
+pub enum TypeKind {
+    #[default]
+    Unknown,
+    Void,
+    Base(BaseEncoding),
+    Pointer(Option<usize>),
+    Struct(Vec<Member>),
+    Union(Vec<Member>),
+    Array(Option<usize>, Option<usize>), // element type, element count
+    Enum(Vec<(String, i64)>),
+    Typedef(Option<usize>), // also used for const and volatile qualifiers
+    Function,
+    // A Rust enum: the discriminant member, and each variant with the discriminant value that
+    // selects it (None for the default variant)
+    Variants(Option<Member>, Vec<(Option<i64>, Member)>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub type_offset: Option<usize>,
+    pub offset: usize, // Byte offset inside the enclosing struct
+}
+
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
     FramePointerOffset(isize),
+    // Any other DWARF location expression, evaluated against the live inferior
+    Expression(gimli::Encoding, Vec<u8>),
+    // Location list: the expression to use depends on the current pc
+    List(gimli::Encoding, Vec<LocationRange>),
+    // DW_AT_const_value: the variable has no storage, only a known value
+    Value(Vec<u8>),
+    OptimizedOut,
+}
+
+#[derive(Clone, Debug)]
+pub struct LocationRange {
+    pub begin: usize,
+    pub end: usize,
+    pub expression: Vec<u8>,
+}
+
+impl Location {
+    fn relocate(&mut self, delta: usize) {
+        match self {
+            Location::Address(addr) => *addr = addr.wrapping_add(delta),
+            Location::List(_, ranges) => {
+                for range in ranges {
+                    range.begin = range.begin.wrapping_add(delta);
+                    range.end = range.end.wrapping_add(delta);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the expression describing the location at `pc`, if the location is described by
+    /// an expression.
+    pub fn expression_at(&self, pc: usize) -> Option<(gimli::Encoding, &[u8])> {
+        match self {
+            Location::Expression(encoding, bytes) => Some((*encoding, bytes)),
+            Location::List(encoding, ranges) => ranges
+                .iter()
+                .find(|range| range.begin <= pc && pc < range.end)
+                .map(|range| (*encoding, range.expression.as_slice())),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
             Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(_, bytes) => write!(f, "Expression({:02x?})", bytes),
+            Location::List(_, ranges) => write!(f, "List({} ranges)", ranges.len()),
+            Location::Value(bytes) => write!(f, "Value({:02x?})", bytes),
+            Location::OptimizedOut => write!(f, "OptimizedOut"),
         }
     }
 }
@@ -191,25 +950,93 @@ pub struct Variable {
     pub entity_type: Type,
     pub location: Location,
     pub line_number: usize, // Line number in source file
+    pub is_parameter: bool,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct Function {
     pub name: String,
+    pub offset: usize, // Offset of the DIE this function was read from
+    pub qualified_name: String, // Demangled, with namespaces and classes (e.g. `geo::Rect::area`)
     pub address: usize,
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
     pub variables: Vec<Variable>,
+    pub frame_base: Option<Location>,
+    pub return_type: Option<Type>, // None for void functions
+}
+
+impl Function {
+    /// Returns true if `name` refers to this function, either by its plain name or by its
+    /// qualified name (`Rect::area`, `geo::Rect::area`, `shapes::Circle::area`...).
+    pub fn matches(&self, name: &str) -> bool {
+        self.name == name || names_match(&self.qualified_name, name)
+    }
+
+    /// Returns the formal parameters, in declaration order.
+    pub fn parameters(&self) -> impl Iterator<Item = &Variable> {
+        self.variables.iter().filter(|var| var.is_parameter)
+    }
+
+    /// Returns the C declaration of this function, e.g. `int add(int, int);`.
+    pub fn signature(&self) -> String {
+        let parameters: Vec<&str> = self.parameters().map(|var| var.entity_type.name.as_str()).collect();
+        format!(
+            "{} {}({});",
+            self.return_type.as_ref().map_or("void", |ty| ty.name.as_str()),
+            self.name,
+            if parameters.is_empty() { "void".to_string() } else { parameters.join(", ") }
+        )
+    }
+}
+
+// A function from the ELF symbol table, for code without debug info
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String, // Demangled
+    pub qualified_name: String, // Demangled, without parameter types
+    pub address: usize,
+    pub size: usize,
+}
+
+impl Symbol {
+    /// Describes `addr` relative to the start of this symbol, e.g. `puts+0x1c`.
+    pub fn describe(&self, addr: usize) -> String {
+        match addr.checked_sub(self.address) {
+            Some(0) => self.name.clone(),
+            Some(offset) => format!("{}+{:#x}", self.name, offset),
+            None => format!("{}-{:#x}", self.name, self.address - addr),
+        }
+    }
+}
+
+// A copy of a function that the compiler inlined into another one
+#[derive(Debug, Default, Clone)]
+pub struct InlinedFunction {
+    pub name: String,
+    pub qualified_name: String,
+    pub ranges: Vec<(usize, usize)>, // [begin, end) address ranges of the inlined code
+    pub entry_address: usize,
+    pub variables: Vec<Variable>,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct File {
     pub name: String,
+    pub comp_dir: String, // the directory a relative name is relative to
     pub global_variables: Vec<Variable>,
     pub functions: Vec<Function>,
+    pub inlined_functions: Vec<InlinedFunction>,
     pub lines: Vec<Line>,
 }
 
+impl File {
+    /// Returns the path of the source file, which is `name` unless that is relative.
+    pub fn path(&self) -> PathBuf {
+        Path::new(&self.comp_dir).join(&self.name)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Line {
     pub file: String,
@@ -221,4 +1048,102 @@ impl fmt::Display for Line {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}:{}", self.file, self.number)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn demangled_names() {
+        assert_eq!(qualified_name("main"), "main");
+        assert_eq!(qualified_name("_ZNK3geo4Rect4areaEv"), "geo::Rect::area");
+        assert_eq!(
+            qualified_name("_ZN4demo6shapes6Circle4area17h2e7ddd3c6341d375E"),
+            "demo::shapes::Circle::area"
+        );
+        assert_eq!(qualified_name("_RNvNtCs1234_4demo6shapes4area"), "demo::shapes::area");
+        assert!(names_match("geo::Rect::area", "Rect::area"));
+        assert!(!names_match("geo::Rect::area", "ect::area"));
+    }
+
+    #[test]
+    fn index_cache_format() {
+        let mut index = Index {
+            units: vec![(0, "calls.c".to_string()), (0x3a2, "/src/lib dir/util.c".to_string())],
+            ranges: vec![(0x401136, 0x4013a0, 0), (0x4013a0, 0x401400, 1)],
+            ..Default::default()
+        };
+        index.functions.insert("add".to_string(), vec![0x3dd]);
+        index.declarations.insert("square".to_string(), vec![0x40, 0x3f0]);
+        index.variables.insert("counter".to_string(), vec![0x2a]);
+        index.types.insert("struct point".to_string(), vec![0x50]);
+        assert_eq!(Index::from_text(&index.to_text()), Some(index));
+        assert_eq!(Index::from_text("deet index 0\n"), None);
+        assert_eq!(Index::from_text("deet index 1\nfunction\tzz\tadd\n"), None);
+    }
+
+    #[test]
+    fn gc_sections() {
+        let dir = std::env::temp_dir().join(format!("deet-gc-sections-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("gc.c");
+        fs::write(&source, "int unused(int x) {\n    return x * 2;\n}\nint main() {\n    return 0;\n}\n").unwrap();
+        let binary = dir.join("gc");
+        let status = std::process::Command::new("gcc")
+            .args(["-g", "-no-pie", "-ffunction-sections", "-Wl,--gc-sections", "-o"])
+            .arg(&binary)
+            .arg(&source)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let data = DwarfData::from_file(binary.to_str().unwrap(), &[], false).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The rows of `unused` are still there, at address 0
+        let addr = data.get_addr_for_line(Some("gc.c"), 2).unwrap();
+        assert_eq!(data.get_line_range(addr).unwrap().0.number, 4);
+    }
+
+    #[test]
+    fn rust_sample() {
+        let dir = std::env::temp_dir().join(format!("deet-rust-sample-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join("shapes");
+        let status = std::process::Command::new("rustc")
+            .args(["-g", "-o"])
+            .arg(&binary)
+            .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/samples/shapes.rs"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let mut data = DwarfData::from_file(binary.to_str().unwrap(), &[], false).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // A cached index is only used if it fits the debug info
+        let mut index = gimli_wrapper::scan_units(&data.dwarf).unwrap();
+        assert!(index.fits(&data.dwarf));
+        index.functions.insert("stale".to_string(), vec![usize::MAX]);
+        assert!(!index.fits(&data.dwarf));
+        index.functions.remove("stale");
+        index.units.pop();
+        assert!(!index.fits(&data.dwarf));
+
+        // The cleanup code at the end of main (line 20) comes before line 18 in the line table
+        let addr = data.get_addr_for_line(Some("shapes.rs"), 18).unwrap();
+        assert_eq!(data.get_line_range(addr).unwrap().0.number, 18);
+        let area = data.get_function("shapes::Rect::area").unwrap().address;
+
+        // rustc links position-independent executables
+        let bias = 0x5555_5555_4000;
+        data.set_load_bias(bias);
+        assert_eq!(data.get_addr_for_line(Some("shapes.rs"), 18), Some(addr + bias));
+        assert_eq!(data.get_function_at(area + bias).map(|func| func.address), Some(area + bias));
+        assert_eq!(data.get_frames_from_addr(addr + bias)[0].0, "shapes::main");
+    }
+
+    #[test]
+    fn debuglink_crc() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+}
@@ -0,0 +1,2063 @@
+//! Parsing and evaluation of the C-like expressions accepted by `print`, breakpoint conditions
+//! watchpoints and `set var`, e.g. `p->next->val + 1`, `arr[i * 2]`, `*(int *)$rsp`, `&global`,
+//! `add(x, 2)` or `buf = "hi"`.
+
+use crate::dwarf_data::{BaseEncoding, DwarfData, Function, Location, Member, Type, TypeKind};
+use crate::gimli_wrapper::{self, EvaluationContext, PieceLocation};
+use crate::inferior::{CallArg, CallOutcome, Inferior, Status};
+use crate::registers;
+use nix::libc::{user_fpregs_struct, user_regs_struct};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Parse(String),
+    UnknownSymbol(String),
+    UnknownType(String),
+    Memory(usize),
+    OptimizedOut,
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(msg) => write!(f, "syntax error: {}", msg),
+            Error::UnknownSymbol(name) => write!(f, "no symbol \"{}\" in current context", name),
+            Error::UnknownType(name) => write!(f, "no type named \"{}\"", name),
+            Error::Memory(addr) => write!(f, "cannot access memory at address {:#x}", addr),
+            Error::OptimizedOut => write!(f, "value has been optimized out"),
+            Error::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(u64),
+    Float(f64),
+    Str(Vec<u8>),
+    Ident(String),
+    Register(String),
+    Punct(&'static str),
+}
+
+// Longer operators come first so that e.g. "->" is not lexed as "-" followed by ">"
+const PUNCTUATION: [&str; 28] = [
+    "->", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "(", ")", "[",
+    "]", ".", "&", "!", "~", "<", ">", "|", "^", ",", "=",
+];
+
+fn tokenize(src: &str) -> Result<Vec<Token>, Error> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            tokens.push(parse_number(&text)?);
+        } else if c == '\'' {
+            // Character literal, e.g. 'a', '\n' or '\x41'
+            let (value, len) = match chars.get(i + 1) {
+                Some('\\') => lex_escape(&chars, i + 1)?,
+                Some(c) if !c.is_ascii() => {
+                    return Err(Error::Parse(format!("non-ASCII character literal '{}'", c)))
+                }
+                Some(c) => (*c as u8, 1),
+                None => return Err(Error::Parse("unterminated character literal".to_string())),
+            };
+            if chars.get(i + 1 + len) != Some(&'\'') {
+                return Err(Error::Parse("unterminated character literal".to_string()));
+            }
+            tokens.push(Token::Int(value as u64));
+            i += len + 2;
+        } else if c == '"' {
+            // String literal, with C escapes including \xHH
+            let mut bytes = Vec::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some('"') => break,
+                    Some('\\') => {
+                        let (byte, len) = lex_escape(&chars, i)?;
+                        bytes.push(byte);
+                        i += len;
+                    }
+                    Some(c) => {
+                        let mut buf = [0u8; 4];
+                        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                        i += 1;
+                    }
+                    None => return Err(Error::Parse("unterminated string literal".to_string())),
+                }
+            }
+            tokens.push(Token::Str(bytes));
+            i += 1;
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            match text.strip_prefix('$') {
+                Some(reg) if !reg.is_empty() => tokens.push(Token::Register(reg.to_string())),
+                Some(_) => return Err(Error::Parse("expected register name after $".to_string())),
+                None => tokens.push(Token::Ident(text)),
+            }
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            match PUNCTUATION.iter().find(|p| rest.starts_with(*p)) {
+                Some(p) => {
+                    tokens.push(Token::Punct(p));
+                    i += p.len();
+                }
+                None => return Err(Error::Parse(format!("unexpected character '{}'", c))),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Lexes the escape sequence starting with the backslash at `chars[i]`, e.g. `\n` or `\x41`.
+/// Returns the byte it stands for and the number of characters it spans.
+fn lex_escape(chars: &[char], i: usize) -> Result<(u8, usize), Error> {
+    match chars.get(i + 1) {
+        Some('x') => {
+            let hex: String =
+                chars[i + 2..].iter().take(2).take_while(|c| c.is_ascii_hexdigit()).collect();
+            let byte = u8::from_str_radix(&hex, 16)
+                .map_err(|_| Error::Parse("invalid escape \\x without hex digits".to_string()))?;
+            Ok((byte, 2 + hex.len()))
+        }
+        Some(escaped) => Ok((unescape(*escaped), 2)),
+        None => Err(Error::Parse("unterminated escape sequence".to_string())),
+    }
+}
+
+fn parse_number(text: &str) -> Result<Token, Error> {
+    let lower = text.to_lowercase();
+    let digits = lower.trim_end_matches(['u', 'l']);
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()
+    } else if digits.contains('.') || digits.contains('e') {
+        return digits
+            .trim_end_matches('f')
+            .parse::<f64>()
+            .map(Token::Float)
+            .map_err(|_| Error::Parse(format!("invalid number \"{}\"", text)));
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8).ok()
+    } else {
+        digits.parse::<u64>().ok()
+    };
+    value
+        .map(Token::Int)
+        .ok_or_else(|| Error::Parse(format!("invalid number \"{}\"", text)))
+}
+
+fn unescape(c: char) -> u8 {
+    match c {
+        'n' => b'\n',
+        't' => b'\t',
+        'r' => b'\r',
+        '0' => 0,
+        other => other as u8,
+    }
+}
+
+/// A type named in a cast, e.g. `unsigned long` or `struct node *`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeName {
+    pub name: String,
+    pub pointers: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Int(u64),
+    Float(f64),
+    Str(Vec<u8>),
+    Variable(String),
+    Register(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Member(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Cast(TypeName, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Assign(Box<Expr>, Box<Expr>),
+}
+
+const TYPE_KEYWORDS: [&str; 14] = [
+    "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "_Bool",
+    "struct", "union", "enum", "const",
+];
+
+fn binary_precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | "<=" | ">" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        _ => return None,
+    })
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    is_type: &'a dyn Fn(&str) -> bool,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Token::Punct(p)) if *p == punct)
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), Error> {
+        if self.peek_punct(punct) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(Error::Parse(format!("expected '{}'", punct)))
+        }
+    }
+
+    fn parse_expr(&mut self, min_precedence: u8) -> Result<Expr, Error> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(Token::Punct(op)) = self.peek() {
+            let op = *op;
+            let precedence = match binary_precedence(op) {
+                Some(p) if p >= min_precedence => p,
+                _ => break,
+            };
+            self.pos += 1;
+            let rhs = self.parse_expr(precedence + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        // Assignment binds loosest and groups to the right, as in `a = b = 0`
+        if min_precedence == 0 && self.peek_punct("=") {
+            self.pos += 1;
+            let rhs = self.parse_expr(0)?;
+            return Ok(Expr::Assign(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, Error> {
+        if let Some(Token::Punct(op)) = self.peek() {
+            let op = *op;
+            if matches!(op, "-" | "!" | "~" | "*" | "&") {
+                self.pos += 1;
+                return Ok(Expr::Unary(op, Box::new(self.parse_unary()?)));
+            }
+            if op == "(" {
+                if let Some(type_name) = self.try_parse_cast()? {
+                    return Ok(Expr::Cast(type_name, Box::new(self.parse_unary()?)));
+                }
+            }
+        }
+        self.parse_postfix()
+    }
+
+    /// Parses `(type name)` if the parenthesized tokens name a type, otherwise leaves the
+    /// position untouched.
+    fn try_parse_cast(&mut self) -> Result<Option<TypeName>, Error> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut words: Vec<String> = Vec::new();
+        while let Some(Token::Ident(word)) = self.peek() {
+            let is_keyword = TYPE_KEYWORDS.contains(&word.as_str());
+            let follows_tag = matches!(
+                words.last().map(|w| w.as_str()),
+                Some("struct") | Some("union") | Some("enum")
+            );
+            let is_typedef = words.is_empty() && (self.is_type)(word);
+            if !(is_keyword || follows_tag || is_typedef) {
+                break;
+            }
+            words.push(word.clone());
+            self.pos += 1;
+        }
+        let mut pointers = 0;
+        while self.peek_punct("*") {
+            pointers += 1;
+            self.pos += 1;
+        }
+        if words.is_empty() || !self.peek_punct(")") {
+            self.pos = start;
+            return Ok(None);
+        }
+        self.pos += 1;
+        words.retain(|w| w != "const");
+        Ok(Some(TypeName {
+            name: words.join(" "),
+            pointers,
+        }))
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            if self.peek_punct("[") {
+                self.pos += 1;
+                let index = self.parse_expr(0)?;
+                self.expect("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.peek_punct(".") || self.peek_punct("->") {
+                let arrow = self.peek_punct("->");
+                self.pos += 1;
+                let field = match self.peek() {
+                    Some(Token::Ident(field)) => field.clone(),
+                    _ => return Err(Error::Parse("expected field name".to_string())),
+                };
+                self.pos += 1;
+                if arrow {
+                    expr = Expr::Unary("*", Box::new(expr));
+                }
+                expr = Expr::Member(Box::new(expr), field);
+            } else if self.peek_punct("(") && matches!(expr, Expr::Variable(_)) {
+                self.pos += 1;
+                let mut args = Vec::new();
+                while !self.peek_punct(")") {
+                    if !args.is_empty() {
+                        self.expect(",")?;
+                    }
+                    args.push(self.parse_expr(0)?);
+                }
+                self.pos += 1;
+                let name = match expr {
+                    Expr::Variable(name) => name,
+                    _ => unreachable!(),
+                };
+                expr = Expr::Call(name, args);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, Error> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| Error::Parse("unexpected end of expression".to_string()))?;
+        self.pos += 1;
+        match token {
+            Token::Int(value) => Ok(Expr::Int(value)),
+            Token::Float(value) => Ok(Expr::Float(value)),
+            Token::Str(bytes) => Ok(Expr::Str(bytes)),
+            Token::Ident(name) => Ok(Expr::Variable(name)),
+            Token::Register(name) => Ok(Expr::Register(name)),
+            Token::Punct("(") => {
+                let expr = self.parse_expr(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Punct(p) => Err(Error::Parse(format!("unexpected '{}'", p))),
+        }
+    }
+}
+
+impl Expr {
+    /// Returns the names of all variables the expression refers to.
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            Expr::Variable(name) => vec![name.as_str()],
+            Expr::Unary(_, operand) | Expr::Member(operand, _) | Expr::Cast(_, operand) => {
+                operand.variables()
+            }
+            Expr::Binary(_, lhs, rhs) | Expr::Index(lhs, rhs) | Expr::Assign(lhs, rhs) => {
+                let mut names = lhs.variables();
+                names.extend(rhs.variables());
+                names
+            }
+            Expr::Call(_, args) => args.iter().flat_map(|arg| arg.variables()).collect(),
+            Expr::Int(_) | Expr::Float(_) | Expr::Str(_) | Expr::Register(_) => Vec::new(),
+        }
+    }
+}
+
+/// Parses `src` into an expression tree. `is_type` tells the parser which identifiers name types
+/// (typedefs), so that `(size_t)x` can be recognized as a cast.
+pub fn parse(src: &str, is_type: &dyn Fn(&str) -> bool) -> Result<Expr, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        pos: 0,
+        is_type,
+    };
+    let expr = parser.parse_expr(0)?;
+    if parser.pos != parser.tokens.len() {
+        return Err(Error::Parse("unexpected trailing input".to_string()));
+    }
+    Ok(expr)
+}
+
+/// Parses the arguments of `dprintf`: a format string literal followed by comma-separated
+/// expressions. Returns the format with its escapes processed, and the expressions.
+pub fn parse_format_args(src: &str) -> Result<(Vec<u8>, Vec<String>), Error> {
+    // Split at the commas that are not inside parentheses or literals
+    let mut parts = Vec::new();
+    let (mut depth, mut quote, mut escaped, mut start) = (0, None, false, 0);
+    for (i, c) in src.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '[') => depth += 1,
+            (None, ')' | ']') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(src[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(src[start..].trim());
+    let format = match tokenize(parts[0])?.as_slice() {
+        [Token::Str(bytes)] => bytes.clone(),
+        _ => return Err(Error::Parse("format string must be a string literal".to_string())),
+    };
+    if parts[1..].iter().any(|arg| arg.is_empty()) {
+        return Err(Error::Parse("missing argument".to_string()));
+    }
+    // Every conversion but %% takes an argument
+    let mut conversions = 0;
+    let mut specs = format.split(|&c| c == b'%').skip(1);
+    while let Some(spec) = specs.next() {
+        if spec.is_empty() {
+            specs.next();
+        } else {
+            conversions += 1;
+        }
+    }
+    if conversions != parts.len() - 1 {
+        return Err(Error::Parse(format!(
+            "format expects {} arguments but {} were given",
+            conversions,
+            parts.len() - 1
+        )));
+    }
+    Ok((format, parts[1..].iter().map(|arg| arg.to_string()).collect()))
+}
+
+/// The result of evaluating an expression. `addr` is set for values that live in the inferior's
+/// memory (lvalues), which is what makes `&x` and member access on them possible.
+#[derive(Debug, Clone)]
+pub struct Value {
+    pub ty: Type,
+    pub bytes: Vec<u8>,
+    pub addr: Option<usize>,
+}
+
+impl Value {
+    /// Returns true for the result of calling a function that returns void.
+    pub fn is_void(&self) -> bool {
+        matches!(self.ty.kind, TypeKind::Void)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Scalar {
+    Int(i64),
+    Uint(u64),
+    Float(f64),
+}
+
+impl Scalar {
+    fn as_i64(self) -> i64 {
+        match self {
+            Scalar::Int(v) => v,
+            Scalar::Uint(v) => v as i64,
+            Scalar::Float(v) => v as i64,
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Scalar::Int(v) => v as f64,
+            Scalar::Uint(v) => v as f64,
+            Scalar::Float(v) => v,
+        }
+    }
+
+    fn is_true(self) -> bool {
+        match self {
+            Scalar::Int(v) => v != 0,
+            Scalar::Uint(v) => v != 0,
+            Scalar::Float(v) => v != 0.0,
+        }
+    }
+}
+
+fn int_type(size: usize, signed: bool) -> Type {
+    let name = match (size, signed) {
+        (8, true) => "long",
+        (8, false) => "unsigned long",
+        (_, true) => "int",
+        (_, false) => "unsigned int",
+    };
+    let encoding = if signed {
+        BaseEncoding::Signed
+    } else {
+        BaseEncoding::Unsigned
+    };
+    Type::with_kind(name, size.max(4), TypeKind::Base(encoding))
+}
+
+/// Spells a C base type the way gcc names it in DWARF, e.g. `unsigned long` as
+/// `long unsigned int`.
+fn canonical_type_name(name: &str) -> String {
+    let words: Vec<&str> = name.split_whitespace().collect();
+    let unsigned = words.contains(&"unsigned");
+    let longs = words.iter().filter(|w| **w == "long").count();
+    let base = if words.contains(&"char") {
+        return match (unsigned, words.contains(&"signed")) {
+            (true, _) => "unsigned char".to_string(),
+            (_, true) => "signed char".to_string(),
+            _ => "char".to_string(),
+        };
+    } else if words.contains(&"short") {
+        "short int"
+    } else if longs == 2 {
+        "long long int"
+    } else if longs == 1 && words.contains(&"double") {
+        return "long double".to_string();
+    } else if longs == 1 {
+        "long int"
+    } else if words.iter().all(|w| matches!(*w, "int" | "signed" | "unsigned")) {
+        "int"
+    } else {
+        return name.to_string();
+    };
+    match (unsigned, base) {
+        (true, "int") => "unsigned int".to_string(),
+        (true, base) => base.replace(" int", " unsigned int"),
+        (false, base) => base.to_string(),
+    }
+}
+
+/// Base types that casts may name even if the program's debug info never mentions them.
+fn builtin_type(canonical: &str) -> Option<Type> {
+    let (size, encoding) = match canonical {
+        "char" | "signed char" => (1, BaseEncoding::SignedChar),
+        "unsigned char" => (1, BaseEncoding::UnsignedChar),
+        "short int" | "int" | "long int" | "long long int" => {
+            (int_size(canonical), BaseEncoding::Signed)
+        }
+        "short unsigned int" | "unsigned int" | "long unsigned int" | "long long unsigned int" => {
+            (int_size(canonical), BaseEncoding::Unsigned)
+        }
+        "float" => (4, BaseEncoding::Float),
+        "double" => (8, BaseEncoding::Float),
+        "_Bool" => (1, BaseEncoding::Bool),
+        _ => return None,
+    };
+    Some(Type::with_kind(canonical, size, TypeKind::Base(encoding)))
+}
+
+fn int_size(canonical: &str) -> usize {
+    if canonical.starts_with("short") {
+        2
+    } else if canonical.starts_with("long") {
+        8
+    } else {
+        4
+    }
+}
+
+fn double_type() -> Type {
+    Type::with_kind("double", 8, TypeKind::Base(BaseEncoding::Float))
+}
+
+fn scalar_value(scalar: Scalar, ty: Type) -> Value {
+    let bytes = match (scalar, &ty.kind) {
+        (Scalar::Float(v), _) if ty.size == 4 => (v as f32).to_le_bytes().to_vec(),
+        (Scalar::Float(v), _) => v.to_le_bytes().to_vec(),
+        (other, TypeKind::Base(BaseEncoding::Float)) if ty.size == 4 => {
+            (other.as_f64() as f32).to_le_bytes().to_vec()
+        }
+        (other, TypeKind::Base(BaseEncoding::Float)) => other.as_f64().to_le_bytes().to_vec(),
+        (other, _) => other.as_i64().to_le_bytes()[..ty.size.min(8)].to_vec(),
+    };
+    Value {
+        ty,
+        bytes,
+        addr: None,
+    }
+}
+
+/// How values are rendered: `set print pretty` puts each struct member on a line of its own, and
+/// `set output-radix 16` shows integers in hex.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FormatOptions {
+    pub pretty: bool,
+    pub hex: bool,
+}
+
+/// Everything needed to evaluate an expression against a stopped inferior.
+pub struct Context<'a> {
+    pub inferior: &'a Inferior,
+    pub dwarf: &'a DwarfData,
+    pub regs: user_regs_struct,
+    pub fpregs: Option<user_fpregs_struct>,
+    pub format: FormatOptions,
+}
+
+/// Evaluation callbacks for DWARF expressions of variables in the function `func`.
+struct FrameContext<'b, 'a> {
+    ctx: &'b Context<'a>,
+    func: Option<&'b Function>,
+}
+
+impl EvaluationContext for FrameContext<'_, '_> {
+    fn register(&self, register: u16) -> Option<u64> {
+        registers::get_dwarf_register(&self.ctx.regs, self.ctx.fpregs.as_ref(), register)
+    }
+
+    fn memory(&self, address: u64, size: u8) -> Option<u64> {
+        let bytes = self
+            .ctx
+            .inferior
+            .read_memory(address as usize, size as usize)
+            .ok()?;
+        let mut raw = [0u8; 8];
+        raw[..bytes.len().min(8)].copy_from_slice(&bytes[..bytes.len().min(8)]);
+        Some(u64::from_le_bytes(raw))
+    }
+
+    fn frame_base(&self) -> Option<u64> {
+        self.ctx.frame_base(self.func).ok().map(|base| base as u64)
+    }
+
+    fn call_frame_cfa(&self) -> Option<u64> {
+        self.ctx.cfa().map(|cfa| cfa as u64)
+    }
+
+    fn relocate(&self, address: u64) -> u64 {
+        address.wrapping_add(self.ctx.dwarf.load_bias() as u64)
+    }
+}
+
+impl<'a> Context<'a> {
+    pub fn new(inferior: &'a Inferior, dwarf: &'a DwarfData) -> Result<Context<'a>, Error> {
+        let regs = inferior
+            .get_regs()
+            .map_err(|e| Error::Invalid(format!("failed to read registers: {}", e)))?;
+        Ok(Context {
+            inferior,
+            dwarf,
+            regs,
+            fpregs: inferior.get_fpregs().ok(),
+            format: FormatOptions::default(),
+        })
+    }
+
+    /// Parses and evaluates `src`.
+    pub fn evaluate(&self, src: &str) -> Result<Value, Error> {
+        let expr = parse(src, &|name| self.dwarf.get_type_by_name(name).is_some())?;
+        self.eval(&expr)
+    }
+
+    pub fn eval(&self, expr: &Expr) -> Result<Value, Error> {
+        match expr {
+            Expr::Int(value) => {
+                let ty = int_type(if *value > i32::MAX as u64 { 8 } else { 4 }, true);
+                Ok(scalar_value(Scalar::Uint(*value), ty))
+            }
+            Expr::Float(value) => Ok(scalar_value(Scalar::Float(*value), double_type())),
+            Expr::Variable(name) => self.variable(name),
+            Expr::Register(name) => {
+                let value = registers::get_register(&self.regs, name)
+                    .ok_or_else(|| Error::UnknownSymbol(format!("${}", name)))?;
+                let ty = match name.as_str() {
+                    "rip" | "pc" | "rsp" | "sp" | "rbp" | "fp" => {
+                        Type::with_kind("void *", 8, TypeKind::Pointer(None))
+                    }
+                    _ => int_type(8, true),
+                };
+                Ok(scalar_value(Scalar::Uint(value), ty))
+            }
+            Expr::Unary(op, operand) => self.unary(op, self.eval(operand)?),
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.eval(lhs)?;
+                // Short-circuit like C does, so that `p && p->x` is safe
+                match *op {
+                    "&&" if !self.is_true(&lhs)? => {
+                        return Ok(scalar_value(Scalar::Int(0), int_type(4, true)))
+                    }
+                    "||" if self.is_true(&lhs)? => {
+                        return Ok(scalar_value(Scalar::Int(1), int_type(4, true)))
+                    }
+                    _ => {}
+                }
+                self.binary(op, lhs, self.eval(rhs)?)
+            }
+            Expr::Member(operand, field) => self.member(self.eval(operand)?, field),
+            Expr::Index(array, index) => {
+                let array = self.eval(array)?;
+                let index = self.eval(index)?;
+                let pointer = self.binary("+", array, index)?;
+                self.unary("*", pointer)
+            }
+            Expr::Cast(type_name, operand) => {
+                let ty = self.lookup_type(type_name)?;
+                self.cast(self.eval(operand)?, ty)
+            }
+            Expr::Call(name, args) => self.call(name, args),
+            Expr::Assign(lhs, rhs) => self.assign(lhs, rhs),
+            Expr::Str(_) => Err(Error::Invalid(
+                "string literals can only be assigned to char arrays".to_string(),
+            )),
+        }
+    }
+
+    /// Stores the value of `rhs` into the object or register `lhs` refers to, converted to its
+    /// type, and returns the new value. A string can be stored into a char array, which it must
+    /// fit in, or at the address of a single char, e.g. `*(char *)0x601040 = "abc"`.
+    fn assign(&self, lhs: &Expr, rhs: &Expr) -> Result<Value, Error> {
+        if let Expr::Register(name) = lhs {
+            let value = self.scalar(&self.eval(rhs)?)?.as_i64() as u64;
+            let mut regs = self.regs;
+            *registers::register_mut(&mut regs, name)
+                .ok_or_else(|| Error::UnknownSymbol(format!("${}", name)))? = value;
+            nix::sys::ptrace::setregs(self.inferior.pid(), regs)
+                .map_err(|e| Error::Invalid(format!("failed to write registers: {}", e)))?;
+            return Ok(scalar_value(Scalar::Uint(value), int_type(8, true)));
+        }
+
+        let target = self.eval(lhs)?;
+        let addr = target
+            .addr
+            .ok_or_else(|| Error::Invalid("left operand of assignment is not an lvalue".into()))?;
+        let resolved = self.dwarf.resolve_type(&target.ty);
+        let bytes = match (rhs, &resolved.kind) {
+            (Expr::Str(string), TypeKind::Array(_, _)) if self.is_char_array(resolved) => {
+                if string.len() > resolved.size {
+                    return Err(Error::Invalid(format!(
+                        "string of length {} does not fit in {}",
+                        string.len(),
+                        target.ty.name
+                    )));
+                }
+                // The rest of the array is zeroed, as a C initializer would
+                let mut bytes = string.clone();
+                bytes.resize(resolved.size, 0);
+                bytes
+            }
+            (Expr::Str(string), TypeKind::Base(_)) if resolved.size == 1 => {
+                let mut bytes = string.clone();
+                bytes.push(0);
+                bytes
+            }
+            (Expr::Str(_), _) => {
+                return Err(Error::Invalid(format!(
+                    "cannot assign a string to a value of type {}",
+                    target.ty.name
+                )))
+            }
+            (_, TypeKind::Base(_) | TypeKind::Pointer(_) | TypeKind::Enum(_)) => {
+                self.cast(self.eval(rhs)?, target.ty.clone())?.bytes
+            }
+            _ => {
+                // Aggregates are copied whole from an object of the same type
+                let value = self.eval(rhs)?;
+                let rhs_type = self.dwarf.resolve_type(&value.ty);
+                if rhs_type.name != resolved.name || rhs_type.size != resolved.size {
+                    return Err(Error::Invalid(format!(
+                        "cannot assign a value of type {} to {}",
+                        value.ty.name, target.ty.name
+                    )));
+                }
+                value.bytes
+            }
+        };
+        self.inferior
+            .write_memory(addr, &bytes)
+            .map_err(|_| Error::Memory(addr))?;
+        self.read_value(target.ty, addr)
+    }
+
+    fn is_char_array(&self, ty: &Type) -> bool {
+        let elem = match ty.kind {
+            TypeKind::Array(Some(elem), _) => self.dwarf.get_type(elem),
+            _ => None,
+        };
+        elem.is_some_and(|elem| {
+            let elem = self.dwarf.resolve_type(elem);
+            elem.size == 1
+                && matches!(
+                    elem.kind,
+                    TypeKind::Base(BaseEncoding::SignedChar)
+                        | TypeKind::Base(BaseEncoding::UnsignedChar)
+                )
+        })
+    }
+
+    /// Calls the function `name` in the inferior and returns its result. Arguments are converted
+    /// to the types of the parameters; without debug info for the function, it is assumed to
+    /// return an int, like C does for undeclared functions.
+    fn call(&self, name: &str, args: &[Expr]) -> Result<Value, Error> {
+        let (addr, params, return_type) = match self.dwarf.get_function(name) {
+            Some(func) => (
+                func.address,
+                func.parameters().map(|param| param.entity_type.clone()).collect(),
+                func.return_type.clone(),
+            ),
+            None => {
+                let addr = self
+                    .dwarf
+                    .get_symbol_addr(name)
+                    .ok_or_else(|| Error::UnknownSymbol(name.to_string()))?;
+                (addr, Vec::new(), Some(int_type(4, true)))
+            }
+        };
+        if args.len() < params.len() {
+            return Err(Error::Invalid(format!("too few arguments in call to {}", name)));
+        }
+
+        let mut call_args = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            let mut value = self.eval(arg)?;
+            let param = params.get(i).map(|ty| self.dwarf.resolve_type(ty));
+            if let Some(TypeKind::Base(_) | TypeKind::Pointer(_) | TypeKind::Enum(_)) =
+                param.map(|ty| &ty.kind)
+            {
+                value = self.cast(value, params[i].clone())?;
+            }
+            let ty = self.dwarf.resolve_type(&value.ty);
+            match &ty.kind {
+                TypeKind::Struct(_) | TypeKind::Union(_) if ty.size <= 16 => {
+                    for (i, sse) in self.classify(ty).into_iter().enumerate() {
+                        let mut raw = [0u8; 8];
+                        let chunk = &value.bytes[8 * i..value.bytes.len().min(8 * i + 8)];
+                        raw[..chunk.len()].copy_from_slice(chunk);
+                        let eightbyte = u64::from_le_bytes(raw);
+                        call_args.push(if sse {
+                            CallArg::Sse(eightbyte)
+                        } else {
+                            CallArg::Integer(eightbyte)
+                        });
+                    }
+                }
+                TypeKind::Struct(_) | TypeKind::Union(_) => {
+                    return Err(Error::Invalid(format!(
+                        "passing {} (larger than 16 bytes) by value is not supported",
+                        value.ty.name
+                    )))
+                }
+                // Without a prototype, floats are promoted to double
+                TypeKind::Base(BaseEncoding::Float) if ty.size == 4 && param.is_some() => {
+                    let v = self.scalar(&value)?.as_f64() as f32;
+                    call_args.push(CallArg::Sse(v.to_bits() as u64));
+                }
+                TypeKind::Base(BaseEncoding::Float) => {
+                    call_args.push(CallArg::Sse(self.scalar(&value)?.as_f64().to_bits()));
+                }
+                _ => call_args.push(CallArg::Integer(self.scalar(&value)?.as_i64() as u64)),
+            }
+        }
+
+        let resolved = return_type.as_ref().map(|ty| self.dwarf.resolve_type(ty));
+        let return_size = match resolved {
+            Some(ty) if matches!(ty.kind, TypeKind::Base(BaseEncoding::Float)) && ty.size > 8 => {
+                return Err(Error::Invalid(format!(
+                    "calling functions that return {} is not supported",
+                    ty.name
+                )))
+            }
+            Some(ty) if ty.size > 16 => ty.size,
+            _ => 0,
+        };
+        // The breakpoint the function returns to must be somewhere it won't run into by itself
+        let return_addr = self
+            .dwarf
+            .get_symbol_addr("_start")
+            .unwrap_or(self.regs.rip as usize);
+        let outcome = self
+            .inferior
+            .call_function(addr, &call_args, return_size, return_addr)
+            .map_err(|e| Error::Invalid(format!("calling {} failed: {}", name, e)))?;
+        let result = match outcome {
+            CallOutcome::Returned(result) => result,
+            CallOutcome::Interrupted(status) => {
+                let what = match status {
+                    Status::Stopped(nix::sys::signal::Signal::SIGTRAP, _) => {
+                        "stopped at a breakpoint".to_string()
+                    }
+                    Status::Stopped(signal, _) => format!("received signal {}", signal),
+                    Status::Exited(code) => format!("exited with code {}", code),
+                    Status::Signaled(signal) => format!("was terminated by signal {}", signal),
+                    Status::Syscall(..) => "stopped".to_string(),
+                };
+                return Err(Error::Invalid(format!(
+                    "The program {} while in {}(), called from the debugger. The call was abandoned.",
+                    what, name
+                )));
+            }
+        };
+
+        let (ty, resolved) = match (&return_type, resolved) {
+            (Some(ty), Some(resolved)) => (ty.clone(), resolved),
+            _ => {
+                return Ok(Value {
+                    ty: Type::with_kind("void", 0, TypeKind::Void),
+                    bytes: Vec::new(),
+                    addr: None,
+                })
+            }
+        };
+        let mut bytes = if return_size > 0 {
+            result.memory
+        } else {
+            let mut integer = result.integer.iter();
+            let mut sse = result.sse.iter();
+            self.classify(resolved)
+                .into_iter()
+                .flat_map(|is_sse| {
+                    let register = if is_sse { sse.next() } else { integer.next() };
+                    register.copied().unwrap_or(0).to_le_bytes()
+                })
+                .collect()
+        };
+        bytes.resize(resolved.size, 0);
+        Ok(Value {
+            ty,
+            bytes,
+            addr: None,
+        })
+    }
+
+    /// Classifies each eightbyte of a value of type `ty` (at most 16 bytes) for the calling
+    /// convention: true if it only holds floating point members and so goes in an SSE register.
+    fn classify(&self, ty: &Type) -> Vec<bool> {
+        let mut leaves = Vec::new();
+        self.scalar_leaves(ty, 0, &mut leaves);
+        (0..ty.size.div_ceil(8).max(1))
+            .map(|i| {
+                let mut eightbyte = leaves.iter().filter(|(offset, _)| offset / 8 == i).peekable();
+                eightbyte.peek().is_some() && eightbyte.all(|(_, is_float)| *is_float)
+            })
+            .collect()
+    }
+
+    /// Collects the offset of every scalar inside a value of type `ty`, and whether it is a
+    /// floating point number.
+    fn scalar_leaves(&self, ty: &Type, offset: usize, leaves: &mut Vec<(usize, bool)>) {
+        let resolved = self.dwarf.resolve_type(ty);
+        match &resolved.kind {
+            TypeKind::Struct(members) | TypeKind::Union(members) => {
+                for member in members {
+                    if let Some(member_type) = member.type_offset.and_then(|o| self.dwarf.get_type(o)) {
+                        self.scalar_leaves(member_type, offset + member.offset, leaves);
+                    }
+                }
+            }
+            TypeKind::Array(Some(elem), Some(count)) => {
+                if let Some(elem_type) = self.dwarf.get_type(*elem) {
+                    let size = self.dwarf.resolve_type(elem_type).size.max(1);
+                    for i in 0..*count {
+                        self.scalar_leaves(elem_type, offset + i * size, leaves);
+                    }
+                }
+            }
+            TypeKind::Base(BaseEncoding::Float) => leaves.push((offset, true)),
+            _ => leaves.push((offset, false)),
+        }
+    }
+
+    /// If `src` refers to local variables of the current function, returns the function's address
+    /// and the CFA, which together identify the stack frame the expression is valid in.
+    pub fn local_scope(&self, src: &str) -> Option<(usize, usize)> {
+        let expr = parse(src, &|name| self.dwarf.get_type_by_name(name).is_some()).ok()?;
+        let func = self.dwarf.get_function_at(self.regs.rip as usize)?;
+        let names = expr.variables();
+        if func.variables.iter().any(|var| names.contains(&var.name.as_str())) {
+            Some((func.address, self.cfa()?))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the canonical frame address of the current frame: the value of rsp in the caller
+    /// right before the call instruction. Falls back to the frame pointer convention (rbp + 16)
+    /// when there is no call frame information for the current pc.
+    pub fn cfa(&self) -> Option<usize> {
+        match self.dwarf.unwind_row(self.regs.rip as usize) {
+            Some(row) => {
+                let base = registers::get_dwarf_register(
+                    &self.regs,
+                    self.fpregs.as_ref(),
+                    row.cfa_register,
+                )?;
+                Some((base as i64 + row.cfa_offset) as usize)
+            }
+            None => Some(self.regs.rbp as usize + 16),
+        }
+    }
+
+    /// Returns the frame base (`DW_AT_frame_base`) of `func`, which `DW_OP_fbreg` offsets are
+    /// relative to.
+    fn frame_base(&self, func: Option<&Function>) -> Result<usize, Error> {
+        let location = match func.and_then(|func| func.frame_base.as_ref()) {
+            Some(location) => location,
+            None => return self.cfa().ok_or(Error::OptimizedOut),
+        };
+        let frame = FrameContext {
+            ctx: self,
+            func: None,
+        };
+        let (encoding, bytes) = location
+            .expression_at(self.regs.rip as usize)
+            .ok_or(Error::OptimizedOut)?;
+        let pieces = gimli_wrapper::evaluate_location(encoding, bytes, &frame)
+            .map_err(|_| Error::OptimizedOut)?;
+        match pieces.first() {
+            Some((_, PieceLocation::Memory(addr))) => Ok(*addr as usize),
+            Some((_, PieceLocation::Register(register))) => {
+                frame.register(*register).map(|v| v as usize).ok_or(Error::OptimizedOut)
+            }
+            _ => Err(Error::OptimizedOut),
+        }
+    }
+
+    fn variable(&self, name: &str) -> Result<Value, Error> {
+        let pc = self.regs.rip as usize;
+        let func = self.dwarf.get_function_at(pc);
+        // Variables of functions inlined at pc shadow those of the function they were inlined into
+        let inlined = self
+            .dwarf
+            .get_inlined_at(pc)
+            .into_iter()
+            .find_map(|f| f.variables.iter().rev().find(|var| var.name == name));
+        let local = inlined.or_else(|| {
+            func.and_then(|func| func.variables.iter().rev().find(|var| var.name == name))
+        });
+        let var = local
+            .or_else(|| self.dwarf.get_global_variable(name))
+            .ok_or_else(|| Error::UnknownSymbol(name.to_string()))?;
+        self.locate(&var.entity_type, &var.location, func)
+    }
+
+    /// Reads the value of an object of type `ty` from `location`, which may use the frame base of
+    /// `func`.
+    fn locate(
+        &self,
+        ty: &Type,
+        location: &Location,
+        func: Option<&Function>,
+    ) -> Result<Value, Error> {
+        let size = self.dwarf.resolve_type(ty).size;
+        let (encoding, bytes) = match location {
+            Location::Address(addr) => return self.read_value(ty.clone(), *addr),
+            Location::FramePointerOffset(offset) => {
+                let addr = (self.frame_base(func)? as isize + offset) as usize;
+                return self.read_value(ty.clone(), addr);
+            }
+            Location::Value(bytes) => {
+                let mut bytes = bytes.clone();
+                bytes.resize(size, 0);
+                return Ok(Value {
+                    ty: ty.clone(),
+                    bytes,
+                    addr: None,
+                });
+            }
+            Location::OptimizedOut => return Err(Error::OptimizedOut),
+            Location::Expression(_, _) | Location::List(_, _) => location
+                .expression_at(self.regs.rip as usize)
+                .ok_or(Error::OptimizedOut)?,
+        };
+
+        let frame = FrameContext { ctx: self, func };
+        let pieces = gimli_wrapper::evaluate_location(encoding, bytes, &frame)
+            .map_err(|_| Error::OptimizedOut)?;
+        if let [(_, PieceLocation::Memory(addr))] = pieces.as_slice() {
+            return self.read_value(ty.clone(), *addr as usize);
+        }
+
+        // The object lives (partially) in registers or has a computed value
+        let mut value = Vec::new();
+        for (piece_size, piece) in pieces {
+            let piece_size = piece_size.unwrap_or(size);
+            let mut piece_bytes = match piece {
+                PieceLocation::Memory(addr) => self
+                    .inferior
+                    .read_memory(addr as usize, piece_size)
+                    .map_err(|_| Error::Memory(addr as usize))?,
+                PieceLocation::Register(register) => frame
+                    .register(register)
+                    .ok_or(Error::OptimizedOut)?
+                    .to_le_bytes()
+                    .to_vec(),
+                PieceLocation::Bytes(bytes) => bytes,
+                PieceLocation::OptimizedOut => return Err(Error::OptimizedOut),
+            };
+            piece_bytes.resize(piece_size, 0);
+            value.extend(piece_bytes);
+        }
+        value.resize(size, 0);
+        Ok(Value {
+            ty: ty.clone(),
+            bytes: value,
+            addr: None,
+        })
+    }
+
+    fn read_value(&self, ty: Type, addr: usize) -> Result<Value, Error> {
+        let size = self.dwarf.resolve_type(&ty).size;
+        let bytes = self
+            .inferior
+            .read_memory(addr, size)
+            .map_err(|_| Error::Memory(addr))?;
+        Ok(Value {
+            ty,
+            bytes,
+            addr: Some(addr),
+        })
+    }
+
+    fn pointee(&self, ty: &Type) -> Option<Type> {
+        match self.dwarf.resolve_type(ty).kind {
+            TypeKind::Pointer(target) | TypeKind::Array(target, _) => Some(match target {
+                Some(offset) => self.dwarf.get_type(offset)?.clone(),
+                None => Type::with_kind("void", 0, TypeKind::Void),
+            }),
+            _ => None,
+        }
+    }
+
+    fn lookup_type(&self, type_name: &TypeName) -> Result<Type, Error> {
+        // gcc names base types like "long int" or "long unsigned int"
+        let canonical = canonical_type_name(&type_name.name);
+        let mut ty = match self
+            .dwarf
+            .get_type_by_name(&type_name.name)
+            .or_else(|| self.dwarf.get_type_by_name(&canonical))
+        {
+            Some(ty) => ty.clone(),
+            None if type_name.name == "void" => Type::with_kind("void", 0, TypeKind::Void),
+            None => builtin_type(&canonical)
+                .ok_or_else(|| Error::UnknownType(type_name.name.clone()))?,
+        };
+        for _ in 0..type_name.pointers {
+            ty = self.pointer_to(&ty)?;
+        }
+        Ok(ty)
+    }
+
+    fn pointer_to(&self, ty: &Type) -> Result<Type, Error> {
+        let target = match ty.kind {
+            TypeKind::Void => None,
+            _ => Some(ty.offset.ok_or_else(|| {
+                Error::Invalid(format!("cannot create a pointer to {}", ty.name))
+            })?),
+        };
+        let name = if ty.name.ends_with('*') {
+            format!("{}*", ty.name)
+        } else {
+            format!("{} *", ty.name)
+        };
+        Ok(Type::with_kind(&name, 8, TypeKind::Pointer(target)))
+    }
+
+    fn scalar(&self, value: &Value) -> Result<Scalar, Error> {
+        let ty = self.dwarf.resolve_type(&value.ty);
+        if let TypeKind::Array(_, _) = ty.kind {
+            // Arrays decay into a pointer to their first element
+            return value
+                .addr
+                .map(|addr| Scalar::Uint(addr as u64))
+                .ok_or_else(|| Error::Invalid("array is not in memory".to_string()));
+        }
+        let mut raw = [0u8; 8];
+        let len = value.bytes.len().min(8);
+        raw[..len].copy_from_slice(&value.bytes[..len]);
+        let unsigned = u64::from_le_bytes(raw);
+        let shift = 64 - 8 * len.max(1) as u32;
+        let signed = ((unsigned << shift) as i64) >> shift;
+        match ty.kind {
+            TypeKind::Base(BaseEncoding::Float) if len == 4 => {
+                Ok(Scalar::Float(f32::from_le_bytes(raw[..4].try_into().unwrap()) as f64))
+            }
+            TypeKind::Base(BaseEncoding::Float) => Ok(Scalar::Float(f64::from_le_bytes(raw))),
+            TypeKind::Base(BaseEncoding::Signed)
+            | TypeKind::Base(BaseEncoding::SignedChar)
+            | TypeKind::Enum(_) => Ok(Scalar::Int(signed)),
+            TypeKind::Base(_) | TypeKind::Pointer(_) | TypeKind::Function => {
+                Ok(Scalar::Uint(unsigned))
+            }
+            _ => Err(Error::Invalid(format!(
+                "cannot use a value of type {} here",
+                value.ty.name
+            ))),
+        }
+    }
+
+    /// Returns whether the value is non-zero, as a C `if` would decide.
+    pub fn is_true(&self, value: &Value) -> Result<bool, Error> {
+        Ok(self.scalar(value)?.is_true())
+    }
+
+    fn unary(&self, op: &str, value: Value) -> Result<Value, Error> {
+        match op {
+            "*" => {
+                let target = self.pointee(&value.ty).ok_or_else(|| {
+                    Error::Invalid(format!("cannot dereference a value of type {}", value.ty.name))
+                })?;
+                if let TypeKind::Void = self.dwarf.resolve_type(&target).kind {
+                    return Err(Error::Invalid("cannot dereference a void pointer".to_string()));
+                }
+                let addr = self.scalar(&value)?.as_i64() as usize;
+                self.read_value(target, addr)
+            }
+            "&" => {
+                let addr = value
+                    .addr
+                    .ok_or_else(|| Error::Invalid("cannot take the address of an rvalue".into()))?;
+                Ok(scalar_value(Scalar::Uint(addr as u64), self.pointer_to(&value.ty)?))
+            }
+            "!" => Ok(scalar_value(
+                Scalar::Int(!self.is_true(&value)? as i64),
+                int_type(4, true),
+            )),
+            _ => {
+                let scalar = self.scalar(&value)?;
+                let result = match (op, scalar) {
+                    ("-", Scalar::Float(v)) => Scalar::Float(-v),
+                    ("-", Scalar::Int(v)) => Scalar::Int(v.wrapping_neg()),
+                    ("-", Scalar::Uint(v)) => Scalar::Uint(v.wrapping_neg()),
+                    ("~", Scalar::Int(v)) => Scalar::Int(!v),
+                    ("~", Scalar::Uint(v)) => Scalar::Uint(!v),
+                    _ => return Err(Error::Invalid(format!("invalid operand to unary {}", op))),
+                };
+                let ty = match scalar {
+                    Scalar::Float(_) => double_type(),
+                    Scalar::Int(_) => int_type(value.ty.size, true),
+                    Scalar::Uint(_) => int_type(value.ty.size, false),
+                };
+                Ok(scalar_value(result, ty))
+            }
+        }
+    }
+
+    fn binary(&self, op: &str, lhs: Value, rhs: Value) -> Result<Value, Error> {
+        let bool_type = int_type(4, true);
+        if op == "&&" || op == "||" {
+            let result = self.is_true(&rhs)?;
+            return Ok(scalar_value(Scalar::Int(result as i64), bool_type));
+        }
+
+        // Pointer arithmetic scales by the size of the pointed-to type
+        let lhs_pointee = self.pointee(&lhs.ty);
+        let rhs_pointee = self.pointee(&rhs.ty);
+        if matches!(op, "+" | "-") && (lhs_pointee.is_some() || rhs_pointee.is_some()) {
+            let elem_size = |ty: &Type| self.dwarf.resolve_type(ty).size.max(1) as i64;
+            let (l, r) = (self.scalar(&lhs)?.as_i64(), self.scalar(&rhs)?.as_i64());
+            return match (&lhs_pointee, &rhs_pointee, op) {
+                (Some(target), Some(_), "-") => Ok(scalar_value(
+                    Scalar::Int((l - r) / elem_size(target)),
+                    int_type(8, true),
+                )),
+                (Some(target), None, _) => {
+                    let offset = r * elem_size(target);
+                    let addr = if op == "+" { l + offset } else { l - offset };
+                    let ty = self.pointer_to(target)?;
+                    Ok(scalar_value(Scalar::Uint(addr as u64), ty))
+                }
+                (None, Some(target), "+") => {
+                    let ty = self.pointer_to(target)?;
+                    Ok(scalar_value(Scalar::Uint((r + l * elem_size(target)) as u64), ty))
+                }
+                _ => Err(Error::Invalid(format!("invalid operands to binary {}", op))),
+            };
+        }
+
+        let (l, r) = (self.scalar(&lhs)?, self.scalar(&rhs)?);
+        if let (Scalar::Float(_), _) | (_, Scalar::Float(_)) = (l, r) {
+            let (a, b) = (l.as_f64(), r.as_f64());
+            let cmp = |result: bool| Ok(scalar_value(Scalar::Int(result as i64), int_type(4, true)));
+            return match op {
+                "+" => Ok(scalar_value(Scalar::Float(a + b), double_type())),
+                "-" => Ok(scalar_value(Scalar::Float(a - b), double_type())),
+                "*" => Ok(scalar_value(Scalar::Float(a * b), double_type())),
+                "/" => Ok(scalar_value(Scalar::Float(a / b), double_type())),
+                "==" => cmp(a == b),
+                "!=" => cmp(a != b),
+                "<" => cmp(a < b),
+                "<=" => cmp(a <= b),
+                ">" => cmp(a > b),
+                ">=" => cmp(a >= b),
+                _ => Err(Error::Invalid(format!("invalid operands to binary {}", op))),
+            };
+        }
+
+        // Usual arithmetic conversions, simplified: the result is at least int-sized, and
+        // unsigned if either operand is
+        let size = lhs.ty.size.max(rhs.ty.size).clamp(4, 8);
+        let unsigned = matches!(l, Scalar::Uint(_)) && lhs.ty.size >= 4
+            || matches!(r, Scalar::Uint(_)) && rhs.ty.size >= 4;
+        let result_type = int_type(size, !unsigned);
+        let (a, b) = (l.as_i64(), r.as_i64());
+        let int = |v: i64| Ok(scalar_value(Scalar::Int(v), result_type.clone()));
+        let cmp = |result: bool| Ok(scalar_value(Scalar::Int(result as i64), bool_type.clone()));
+        if matches!(op, "/" | "%") && b == 0 {
+            return Err(Error::Invalid("division by zero".to_string()));
+        }
+        match op {
+            "+" => int(a.wrapping_add(b)),
+            "-" => int(a.wrapping_sub(b)),
+            "*" => int(a.wrapping_mul(b)),
+            "/" if unsigned => int(((a as u64) / (b as u64)) as i64),
+            "/" => int(a.wrapping_div(b)),
+            "%" if unsigned => int(((a as u64) % (b as u64)) as i64),
+            "%" => int(a.wrapping_rem(b)),
+            "&" => int(a & b),
+            "|" => int(a | b),
+            "^" => int(a ^ b),
+            "<<" => int(a.wrapping_shl(b as u32)),
+            ">>" if unsigned => int(((a as u64) >> (b as u32 & 63)) as i64),
+            ">>" => int(a.wrapping_shr(b as u32)),
+            "==" => cmp(a == b),
+            "!=" => cmp(a != b),
+            "<" if unsigned => cmp((a as u64) < (b as u64)),
+            "<=" if unsigned => cmp((a as u64) <= (b as u64)),
+            ">" if unsigned => cmp((a as u64) > (b as u64)),
+            ">=" if unsigned => cmp((a as u64) >= (b as u64)),
+            "<" => cmp(a < b),
+            "<=" => cmp(a <= b),
+            ">" => cmp(a > b),
+            ">=" => cmp(a >= b),
+            _ => Err(Error::Invalid(format!("invalid operands to binary {}", op))),
+        }
+    }
+
+    fn member(&self, mut value: Value, field: &str) -> Result<Value, Error> {
+        // `.` goes through pointers and references, as for `self.w` in a Rust method taking
+        // `&self` or `this.w` in C++
+        while let TypeKind::Pointer(_) = self.dwarf.resolve_type(&value.ty).kind {
+            value = self.unary("*", value)?;
+        }
+        let members = match &self.dwarf.resolve_type(&value.ty).kind {
+            TypeKind::Struct(members) | TypeKind::Union(members) => members.clone(),
+            _ => {
+                return Err(Error::Invalid(format!(
+                    "attempt to extract a component of a value that is not a structure ({})",
+                    value.ty.name
+                )))
+            }
+        };
+        let member = members
+            .iter()
+            .find(|m| m.name == field)
+            .ok_or_else(|| Error::Invalid(format!("there is no member named {}", field)))?;
+        let ty = member
+            .type_offset
+            .and_then(|offset| self.dwarf.get_type(offset))
+            .cloned()
+            .ok_or_else(|| Error::UnknownType(field.to_string()))?;
+        let size = self.dwarf.resolve_type(&ty).size;
+        let bytes = value
+            .bytes
+            .get(member.offset..member.offset + size)
+            .ok_or_else(|| Error::Invalid(format!("member {} is out of bounds", field)))?
+            .to_vec();
+        Ok(Value {
+            ty,
+            bytes,
+            addr: value.addr.map(|addr| addr + member.offset),
+        })
+    }
+
+    fn cast(&self, value: Value, ty: Type) -> Result<Value, Error> {
+        let target = self.dwarf.resolve_type(&ty);
+        match target.kind {
+            TypeKind::Base(_) | TypeKind::Pointer(_) | TypeKind::Enum(_) => {
+                let scalar = self.scalar(&value)?;
+                let scalar = match target.kind {
+                    TypeKind::Base(BaseEncoding::Float) => Scalar::Float(scalar.as_f64()),
+                    _ => Scalar::Int(scalar.as_i64()),
+                };
+                let mut result = scalar_value(scalar, ty.clone());
+                result.bytes.resize(target.size.max(1), 0);
+                Ok(result)
+            }
+            _ => {
+                // Reinterpret memory, e.g. `(struct point)buf`
+                let addr = value
+                    .addr
+                    .ok_or_else(|| Error::Invalid(format!("invalid cast to {}", ty.name)))?;
+                self.read_value(ty, addr)
+            }
+        }
+    }
+
+    /// Renders a value the way gdb would, e.g. `{x = 1, y = 2}` or `0x4006f4 "hello"`.
+    pub fn format(&self, value: &Value) -> String {
+        self.format_bytes(&value.ty, &value.bytes, value.addr, 0)
+    }
+
+    fn format_bytes(&self, ty: &Type, bytes: &[u8], addr: Option<usize>, depth: usize) -> String {
+        let resolved = self.dwarf.resolve_type(ty);
+        let value = Value {
+            ty: ty.clone(),
+            bytes: bytes.to_vec(),
+            addr,
+        };
+        match &resolved.kind {
+            TypeKind::Base(BaseEncoding::Bool) => {
+                (bytes.iter().any(|b| *b != 0)).to_string()
+            }
+            TypeKind::Base(BaseEncoding::SignedChar) | TypeKind::Base(BaseEncoding::UnsignedChar)
+                if resolved.size == 1 =>
+            {
+                let c = bytes.first().copied().unwrap_or(0);
+                let number = match resolved.kind {
+                    _ if self.format.hex => format!("{:#x}", c),
+                    TypeKind::Base(BaseEncoding::SignedChar) => (c as i8).to_string(),
+                    _ => c.to_string(),
+                };
+                format!("{} '{}'", number, escape_char(c))
+            }
+            TypeKind::Base(_) => match self.scalar(&value) {
+                // Negative numbers are shown as their two's complement in the value's size
+                Ok(Scalar::Int(v)) if self.format.hex => {
+                    let bits = 8 * resolved.size.clamp(1, 8) as u32;
+                    format!("{:#x}", (v as u64) & (u64::MAX >> (64 - bits)))
+                }
+                Ok(Scalar::Uint(v)) if self.format.hex => format!("{:#x}", v),
+                Ok(Scalar::Int(v)) => v.to_string(),
+                Ok(Scalar::Uint(v)) => v.to_string(),
+                Ok(Scalar::Float(v)) => v.to_string(),
+                Err(e) => format!("<{}>", e),
+            },
+            TypeKind::Enum(values) => {
+                let v = self.scalar(&value).map(|s| s.as_i64()).unwrap_or(0);
+                match values.iter().find(|(_, value)| *value == v) {
+                    Some((name, _)) => name.clone(),
+                    None => v.to_string(),
+                }
+            }
+            TypeKind::Pointer(target) => {
+                let ptr = self.scalar(&value).map(|s| s.as_i64() as usize).unwrap_or(0);
+                let target = target.and_then(|offset| self.dwarf.get_type(offset));
+                let is_char = matches!(
+                    target.map(|t| &self.dwarf.resolve_type(t).kind),
+                    Some(TypeKind::Base(BaseEncoding::SignedChar))
+                        | Some(TypeKind::Base(BaseEncoding::UnsignedChar))
+                );
+                if is_char && ptr != 0 {
+                    match self.read_c_string(ptr) {
+                        Some(s) => format!("{:#x} {}", ptr, s),
+                        None => format!("{:#x} <error reading string>", ptr),
+                    }
+                } else {
+                    format!("({}) {:#x}", ty.name, ptr)
+                }
+            }
+            TypeKind::Struct(members) | TypeKind::Union(members) => {
+                if depth > 8 {
+                    return "{...}".to_string();
+                }
+                if let Some(pretty) = self.pretty_print(resolved, &value, depth) {
+                    return pretty;
+                }
+                let fields: Vec<String> = self
+                    .format_fields(members, bytes, addr, depth)
+                    .into_iter()
+                    .map(|(name, field)| format!("{} = {}", name, field))
+                    .collect();
+                if self.format.pretty && !fields.is_empty() {
+                    let indent = "  ".repeat(depth + 1);
+                    let lines: Vec<String> =
+                        fields.iter().map(|field| format!("{}{}", indent, field)).collect();
+                    return format!("{{\n{}\n{}}}", lines.join(",\n"), "  ".repeat(depth));
+                }
+                format!("{{{}}}", fields.join(", "))
+            }
+            TypeKind::Variants(discr, variants) => {
+                if depth > 8 {
+                    return "{...}".to_string();
+                }
+                // The discriminant member is usually artificial and unnamed
+                let discr_value = discr.as_ref().and_then(|discr| {
+                    let ty = self.dwarf.get_type(discr.type_offset?)?.clone();
+                    let size = self.dwarf.resolve_type(&ty).size;
+                    let bytes = bytes.get(discr.offset..discr.offset + size)?.to_vec();
+                    let field = Value { ty, bytes, addr: None };
+                    self.scalar(&field).ok().map(|s| s.as_i64())
+                });
+                let variant = variants
+                    .iter()
+                    .find(|(value, _)| value.is_some() && *value == discr_value)
+                    .or_else(|| variants.iter().find(|(value, _)| value.is_none()));
+                let member = match variant {
+                    Some((_, member)) => member,
+                    None => return format!("<invalid discriminant of {}>", ty.name),
+                };
+                let variant_type = member.type_offset.and_then(|offset| self.dwarf.get_type(offset));
+                let members = match variant_type.map(|t| &self.dwarf.resolve_type(t).kind) {
+                    Some(TypeKind::Struct(members)) => members,
+                    _ => return member.name.clone(),
+                };
+                let bytes = bytes.get(member.offset..).unwrap_or_default();
+                let fields = self.format_fields(members, bytes, addr.map(|a| a + member.offset), depth);
+                if fields.is_empty() {
+                    member.name.clone()
+                } else if fields.iter().all(|(name, _)| name.starts_with("__")) {
+                    // Tuple variant, e.g. `Some(5)`
+                    let values: Vec<String> = fields.into_iter().map(|(_, field)| field).collect();
+                    format!("{}({})", member.name, values.join(", "))
+                } else {
+                    let fields: Vec<String> = fields
+                        .into_iter()
+                        .map(|(name, field)| format!("{}: {}", name, field))
+                        .collect();
+                    format!("{} {{{}}}", member.name, fields.join(", "))
+                }
+            }
+            TypeKind::Array(elem, _) => {
+                let elem_type = elem
+                    .and_then(|offset| self.dwarf.get_type(offset))
+                    .cloned()
+                    .unwrap_or_default();
+                let elem_size = self.dwarf.resolve_type(&elem_type).size;
+                if elem_size == 1
+                    && matches!(
+                        self.dwarf.resolve_type(&elem_type).kind,
+                        TypeKind::Base(BaseEncoding::SignedChar)
+                            | TypeKind::Base(BaseEncoding::UnsignedChar)
+                    )
+                {
+                    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                    return quote_bytes(&bytes[..end]);
+                }
+                if elem_size == 0 || depth > 8 {
+                    return "{...}".to_string();
+                }
+                let mut elems: Vec<String> = bytes
+                    .chunks(elem_size)
+                    .take(200)
+                    .enumerate()
+                    .map(|(i, chunk)| {
+                        self.format_bytes(
+                            &elem_type,
+                            chunk,
+                            addr.map(|a| a + i * elem_size),
+                            depth + 1,
+                        )
+                    })
+                    .collect();
+                if bytes.len() / elem_size > 200 {
+                    elems.push("...".to_string());
+                }
+                format!("{{{}}}", elems.join(", "))
+            }
+            TypeKind::Function => match addr {
+                Some(addr) => format!("{{{}}} {:#x}", ty.name, addr),
+                None => format!("{{{}}}", ty.name),
+            },
+            TypeKind::Void if bytes.is_empty() => "void".to_string(),
+            TypeKind::Void | TypeKind::Unknown | TypeKind::Typedef(_) => {
+                format!("<value of type {}>", ty.name)
+            }
+        }
+    }
+
+    /// Formats each member of a struct stored in `bytes`, returning (name, value) pairs.
+    fn format_fields(
+        &self,
+        members: &[Member],
+        bytes: &[u8],
+        addr: Option<usize>,
+        depth: usize,
+    ) -> Vec<(String, String)> {
+        members
+            .iter()
+            .map(|member| {
+                let member_type = member
+                    .type_offset
+                    .and_then(|offset| self.dwarf.get_type(offset))
+                    .cloned()
+                    .unwrap_or_default();
+                let size = self.dwarf.resolve_type(&member_type).size;
+                let field = match bytes.get(member.offset..member.offset + size) {
+                    Some(field) => self.format_bytes(
+                        &member_type,
+                        field,
+                        addr.map(|a| a + member.offset),
+                        depth + 1,
+                    ),
+                    None => "<unavailable>".to_string(),
+                };
+                (member.name.clone(), field)
+            })
+            .collect()
+    }
+
+    /// Renders the standard library types that are unreadable as plain structs: Rust's `String`
+    /// and `Vec<T>`, and C++'s `std::string`. Returns None for other types, or if the layout is
+    /// not the expected one.
+    fn pretty_print(&self, resolved: &Type, value: &Value, depth: usize) -> Option<String> {
+        let name = resolved.name.strip_prefix("struct ").unwrap_or(&resolved.name);
+        if name == "String" {
+            let len = self.scalar(&self.find_member(value, "len", 0)?).ok()?.as_i64() as usize;
+            let ptr = self.find_pointer(value, 0)?;
+            let bytes = self.inferior.read_memory(ptr, len.min(200)).ok()?;
+            let suffix = if len > 200 { "..." } else { "" };
+            Some(format!("{}{}", quote_bytes(&bytes), suffix))
+        } else if let Some(args) = name.strip_prefix("Vec<") {
+            let len = self.scalar(&self.find_member(value, "len", 0)?).ok()?.as_i64() as usize;
+            let ptr = self.find_pointer(value, 0)?;
+            let elem_type = self.type_from_rust_name(first_generic_arg(args))?;
+            let elem_size = self.dwarf.resolve_type(&elem_type).size;
+            let count = len.min(200);
+            let bytes = self.inferior.read_memory(ptr, elem_size * count).ok()?;
+            let mut elems: Vec<String> = (0..count)
+                .map(|i| {
+                    let chunk = &bytes[i * elem_size..(i + 1) * elem_size];
+                    self.format_bytes(&elem_type, chunk, Some(ptr + i * elem_size), depth + 1)
+                })
+                .collect();
+            if len > count {
+                elems.push("...".to_string());
+            }
+            Some(format!("Vec(size={}) = {{{}}}", len, elems.join(", ")))
+        } else if name.starts_with("basic_string<char") {
+            let len = self.scalar(&self.find_member(value, "_M_string_length", 0)?).ok()?;
+            let len = len.as_i64() as usize;
+            let ptr = self.scalar(&self.find_member(value, "_M_p", 0)?).ok()?.as_i64() as usize;
+            let bytes = self.inferior.read_memory(ptr, len.min(200)).ok()?;
+            let suffix = if len > 200 { "..." } else { "" };
+            Some(format!("{}{}", quote_bytes(&bytes), suffix))
+        } else {
+            None
+        }
+    }
+
+    /// Searches the members of `value`, and recursively their members, for one called `name`.
+    fn find_member(&self, value: &Value, name: &str, depth: usize) -> Option<Value> {
+        let members = match &self.dwarf.resolve_type(&value.ty).kind {
+            TypeKind::Struct(members) => members.clone(),
+            _ => return None,
+        };
+        if depth > 8 {
+            return None;
+        }
+        if let Ok(member) = self.member(value.clone(), name) {
+            return Some(member);
+        }
+        members.iter().find_map(|member| {
+            let member = self.member(value.clone(), &member.name).ok()?;
+            self.find_member(&member, name, depth + 1)
+        })
+    }
+
+    /// Returns the first pointer found in `value` or its members, depth-first: the data pointer
+    /// of a Rust `Vec`, however many wrappers (`RawVec`, `Unique`, `NonNull`...) it is in.
+    fn find_pointer(&self, value: &Value, depth: usize) -> Option<usize> {
+        match &self.dwarf.resolve_type(&value.ty).kind {
+            TypeKind::Pointer(_) => Some(self.scalar(value).ok()?.as_i64() as usize),
+            TypeKind::Struct(members) if depth <= 8 => members.iter().find_map(|member| {
+                let member = self.member(value.clone(), &member.name).ok()?;
+                self.find_pointer(&member, depth + 1)
+            }),
+            _ => None,
+        }
+    }
+
+    /// Looks up a type named the way Rust type parameters are, e.g. `i32` or
+    /// `demo::shapes::Circle`.
+    fn type_from_rust_name(&self, name: &str) -> Option<Type> {
+        let short = name.rsplit("::").next().unwrap_or(name);
+        [name.to_string(), format!("struct {}", short), short.to_string()]
+            .iter()
+            .find_map(|name| self.dwarf.get_type_by_name(name))
+            .cloned()
+    }
+
+    fn read_c_string(&self, addr: usize) -> Option<String> {
+        let (bytes, complete) = self.inferior.read_c_string(addr, 200).ok()?;
+        if complete {
+            Some(quote_bytes(&bytes))
+        } else {
+            Some(format!("{}...", quote_bytes(&bytes)))
+        }
+    }
+
+    /// Formats `args` according to the printf-style `format`, as `dprintf` does. Supports the
+    /// flags `-`, `0`, `+` and space, a width and precision, and the conversions `d i u x X o c s
+    /// f e g p`; length modifiers are accepted and ignored, as the argument types are known.
+    pub fn printf(&self, format: &[u8], args: &[String]) -> Result<String, Error> {
+        let mut out = Vec::new();
+        let mut args = args.iter();
+        let mut i = 0;
+        while i < format.len() {
+            if format[i] != b'%' {
+                out.push(format[i]);
+                i += 1;
+                continue;
+            }
+            i += 1;
+            let spec_start = i;
+            while i < format.len() && b"-0+ #".contains(&format[i]) {
+                i += 1;
+            }
+            let flags = &format[spec_start..i];
+            let number = |i: &mut usize| {
+                let start = *i;
+                while *i < format.len() && format[*i].is_ascii_digit() {
+                    *i += 1;
+                }
+                std::str::from_utf8(&format[start..*i]).ok()?.parse::<usize>().ok()
+            };
+            let width = number(&mut i).unwrap_or(0);
+            let precision = if format.get(i) == Some(&b'.') {
+                i += 1;
+                Some(number(&mut i).unwrap_or(0))
+            } else {
+                None
+            };
+            while i < format.len() && b"hlLqjzt".contains(&format[i]) {
+                i += 1;
+            }
+            let conversion = *format
+                .get(i)
+                .ok_or_else(|| Error::Parse("incomplete format specifier".to_string()))?;
+            i += 1;
+            if conversion == b'%' {
+                out.push(b'%');
+                continue;
+            }
+            let src = args
+                .next()
+                .ok_or_else(|| Error::Invalid("missing argument for format".to_string()))?;
+            if conversion == b's' {
+                // String literals have no address in the inferior to read them from
+                if let [Token::Str(bytes)] = tokenize(src)?.as_slice() {
+                    let len = precision.unwrap_or(bytes.len()).min(bytes.len());
+                    out.extend(pad(&bytes[..len], width, flags.contains(&b'-')));
+                    continue;
+                }
+            }
+            let value = self.evaluate(src)?;
+            let sign = |negative: bool| {
+                if negative {
+                    "-"
+                } else if flags.contains(&b'+') {
+                    "+"
+                } else if flags.contains(&b' ') {
+                    " "
+                } else {
+                    ""
+                }
+            };
+            let (prefix, body, numeric) = match conversion {
+                b'd' | b'i' => {
+                    let v = self.scalar(&value)?.as_i64();
+                    (sign(v < 0), v.unsigned_abs().to_string(), true)
+                }
+                b'u' | b'x' | b'X' | b'o' => {
+                    let v = self.scalar(&value)?.as_i64() as u64;
+                    // Unsigned conversions see only as many bytes as the value has
+                    let v = match value.bytes.len() {
+                        len @ 1..=7 => v & ((1 << (8 * len)) - 1),
+                        _ => v,
+                    };
+                    let body = match conversion {
+                        b'u' => v.to_string(),
+                        b'x' => format!("{:x}", v),
+                        b'X' => format!("{:X}", v),
+                        _ => format!("{:o}", v),
+                    };
+                    let prefix = match conversion {
+                        b'x' if flags.contains(&b'#') && v != 0 => "0x",
+                        b'X' if flags.contains(&b'#') && v != 0 => "0X",
+                        _ => "",
+                    };
+                    (prefix, body, true)
+                }
+                b'f' | b'F' | b'e' | b'E' | b'g' | b'G' => {
+                    let v = self.scalar(&value)?.as_f64();
+                    let precision = precision.unwrap_or(6);
+                    let body = match conversion {
+                        b'f' | b'F' => format!("{:.*}", precision, v.abs()),
+                        b'e' | b'E' => c_exponent(&format!("{:.*e}", precision, v.abs())),
+                        _ => format!("{}", v.abs()),
+                    };
+                    let body = if conversion.is_ascii_uppercase() { body.to_uppercase() } else { body };
+                    (sign(v.is_sign_negative() && v != 0.0), body, true)
+                }
+                b'c' => {
+                    let byte = self.scalar(&value)?.as_i64() as u8;
+                    out.extend(pad(&[byte], width, flags.contains(&b'-')));
+                    continue;
+                }
+                b's' => {
+                    let addr = self.scalar(&value)?.as_i64() as usize;
+                    let (bytes, _) = self.inferior.read_c_string(addr, precision.unwrap_or(4096))
+                        .map_err(|_| Error::Memory(addr))?;
+                    out.extend(pad(&bytes, width, flags.contains(&b'-')));
+                    continue;
+                }
+                b'p' => ("0x", format!("{:x}", self.scalar(&value)?.as_i64() as u64), false),
+                other => {
+                    return Err(Error::Parse(format!(
+                        "unsupported format conversion '%{}'",
+                        other as char
+                    )))
+                }
+            };
+            // Integers are zero-padded to the precision; the 0 flag pads to the width after
+            // the sign
+            let body = match precision {
+                Some(precision) if numeric && b"diuxXo".contains(&conversion) => {
+                    format!("{:0>width$}", body, width = precision)
+                }
+                _ => body,
+            };
+            let zero_pad = flags.contains(&b'0') && !flags.contains(&b'-') && numeric;
+            let text = if zero_pad {
+                let zeros = width.saturating_sub(prefix.len() + body.len());
+                format!("{}{}{}", prefix, "0".repeat(zeros), body)
+            } else {
+                format!("{}{}", prefix, body)
+            };
+            out.extend(pad(text.as_bytes(), width, flags.contains(&b'-')));
+        }
+        if args.next().is_some() {
+            return Err(Error::Invalid("too many arguments for format".to_string()));
+        }
+        Ok(String::from_utf8_lossy(&out).to_string())
+    }
+}
+
+/// Pads `text` with spaces to `width` bytes, on the right if `left` is set.
+fn pad(text: &[u8], width: usize, left: bool) -> Vec<u8> {
+    let padding = vec![b' '; width.saturating_sub(text.len())];
+    if left {
+        [text, &padding].concat()
+    } else {
+        [&padding, text].concat()
+    }
+}
+
+/// Rewrites Rust's exponent notation (`1.5e2`) the way C prints it (`1.5e+02`).
+fn c_exponent(text: &str) -> String {
+    match text.split_once('e') {
+        Some((mantissa, exponent)) => {
+            let (sign, digits) = match exponent.strip_prefix('-') {
+                Some(digits) => ('-', digits),
+                None => ('+', exponent),
+            };
+            format!("{}e{}{:0>2}", mantissa, sign, digits)
+        }
+        None => text.to_string(),
+    }
+}
+
+/// Returns the first argument of a generic argument list, given the text following its `<`.
+fn first_generic_arg(args: &str) -> &str {
+    let mut depth = 0;
+    for (i, c) in args.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            '>' | ')' | ']' if depth > 0 => depth -= 1,
+            ',' | '>' if depth == 0 => return args[..i].trim(),
+            _ => {}
+        }
+    }
+    args.trim()
+}
+
+fn escape_char(c: u8) -> String {
+    match c {
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        b'\r' => "\\r".to_string(),
+        0 => "\\000".to_string(),
+        b'\'' => "\\'".to_string(),
+        c if c.is_ascii_graphic() || c == b' ' => (c as char).to_string(),
+        c => format!("\\{:03o}", c),
+    }
+}
+
+pub fn quote_bytes(bytes: &[u8]) -> String {
+    let escaped: String = bytes
+        .iter()
+        .map(|&c| match c {
+            b'"' => "\\\"".to_string(),
+            b'\'' => "'".to_string(),
+            c => escape_char(c),
+        })
+        .collect();
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_plain(src: &str) -> Expr {
+        parse(src, &|name| name == "size_t").expect("expression should parse")
+    }
+
+    #[test]
+    fn test_format_args() {
+        let (format, args) = parse_format_args(r#""%d, %s%%\n", f(a, b), "x,y""#).unwrap();
+        assert_eq!(format, b"%d, %s%%\n");
+        assert_eq!(args, vec!["f(a, b)", "\"x,y\""]);
+        assert!(parse_format_args(r#""%d %d\n", a"#).is_err());
+        assert!(parse_format_args("a, b").is_err());
+        assert_eq!(c_exponent("1.500e2"), "1.500e+02");
+        assert_eq!(c_exponent("2.5e-7"), "2.5e-07");
+    }
+
+    #[test]
+    fn test_char_literals() {
+        assert_eq!(parse_plain("'a'"), Expr::Int(97));
+        assert_eq!(parse_plain("'\\n'"), Expr::Int(10));
+        assert_eq!(parse_plain("'\\x41'"), Expr::Int(0x41));
+        assert_eq!(parse_plain("'\\x7'"), Expr::Int(7));
+        assert!(parse("'é'", &|_| false).is_err());
+        assert!(parse("'ab'", &|_| false).is_err());
+        // Strings share the escapes
+        assert_eq!(tokenize(r#""\x41\x4g""#).unwrap(), [Token::Str(b"A\x04g".to_vec())]);
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(
+            parse_plain("a + b * 2"),
+            Expr::Binary(
+                "+",
+                Box::new(Expr::Variable("a".to_string())),
+                Box::new(Expr::Binary(
+                    "*",
+                    Box::new(Expr::Variable("b".to_string())),
+                    Box::new(Expr::Int(2))
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn test_member_access() {
+        assert_eq!(
+            parse_plain("p->next.val"),
+            Expr::Member(
+                Box::new(Expr::Member(
+                    Box::new(Expr::Unary("*", Box::new(Expr::Variable("p".to_string())))),
+                    "next".to_string()
+                )),
+                "val".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_casts() {
+        assert_eq!(
+            parse_plain("*(unsigned int *)$rsp"),
+            Expr::Unary(
+                "*",
+                Box::new(Expr::Cast(
+                    TypeName {
+                        name: "unsigned int".to_string(),
+                        pointers: 1
+                    },
+                    Box::new(Expr::Register("rsp".to_string()))
+                ))
+            )
+        );
+        assert!(matches!(parse_plain("(size_t)x"), Expr::Cast(_, _)));
+        // A parenthesized variable is not a cast
+        assert!(matches!(parse_plain("(x) - 1"), Expr::Binary("-", _, _)));
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!(
+            parse_plain("add(x, 2) * 3"),
+            Expr::Binary(
+                "*",
+                Box::new(Expr::Call(
+                    "add".to_string(),
+                    vec![Expr::Variable("x".to_string()), Expr::Int(2)]
+                )),
+                Box::new(Expr::Int(3))
+            )
+        );
+        assert_eq!(parse_plain("f()"), Expr::Call("f".to_string(), Vec::new()));
+        assert!(parse("f(1,", &|_| false).is_err());
+    }
+
+    #[test]
+    fn test_assignment() {
+        assert_eq!(
+            parse_plain("a = b = 1"),
+            Expr::Assign(
+                Box::new(Expr::Variable("a".to_string())),
+                Box::new(Expr::Assign(
+                    Box::new(Expr::Variable("b".to_string())),
+                    Box::new(Expr::Int(1))
+                ))
+            )
+        );
+        assert!(matches!(parse_plain("x == 1"), Expr::Binary("==", _, _)));
+        assert_eq!(
+            parse_plain(r#"buf = "a\tb\x41""#),
+            Expr::Assign(
+                Box::new(Expr::Variable("buf".to_string())),
+                Box::new(Expr::Str(b"a\tbA".to_vec()))
+            )
+        );
+        assert!(parse("\"abc", &|_| false).is_err());
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(parse_plain("0x10"), Expr::Int(16));
+        assert_eq!(parse_plain("'a'"), Expr::Int(97));
+        assert_eq!(parse_plain("1.5"), Expr::Float(1.5));
+    }
+
+    #[test]
+    fn test_canonical_type_name() {
+        assert_eq!(canonical_type_name("long"), "long int");
+        assert_eq!(canonical_type_name("unsigned long"), "long unsigned int");
+        assert_eq!(canonical_type_name("unsigned"), "unsigned int");
+        assert_eq!(canonical_type_name("unsigned short"), "short unsigned int");
+        assert_eq!(canonical_type_name("long long"), "long long int");
+        assert_eq!(canonical_type_name("unsigned char"), "unsigned char");
+        assert_eq!(canonical_type_name("size_t"), "size_t");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse("a +", &|_| false).is_err());
+        assert!(parse("arr[1", &|_| false).is_err());
+        assert!(parse("a b", &|_| false).is_err());
+        assert!(parse("a @ b", &|_| false).is_err());
+    }
+}
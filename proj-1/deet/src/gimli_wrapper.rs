@@ -5,249 +5,858 @@
 //!
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
-use gimli;
 use gimli::{UnitOffset, UnitSectionOffset};
+use gimli::UnwindSection;
 use object::{Object, ObjectSection};
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{
+    qualified_name, BaseEncoding, File, Function, Index, InlinedFunction, Line, Location, LocationRange, Member, Type,
+    TypeKind, Variable,
+};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
+use std::rc::Rc;
 use std::{io, path};
 
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
-    // Load a section and return as `Cow<[u8]>`.
-    let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
-        match object.section_by_name(id.name()) {
-            Some(ref section) => Ok(section
-                .uncompressed_data()
-                .unwrap_or(borrow::Cow::Borrowed(&[][..]))),
-            None => Ok(borrow::Cow::Borrowed(&[][..])),
-        }
+pub type DwarfReader = gimli::EndianRcSlice<gimli::RunTimeEndian>;
+
+/// Reads the DWARF sections of `object` into memory. Units are parsed later, as they are needed,
+/// by `load_unit`.
+pub fn load_dwarf(object: &object::File, endian: gimli::RunTimeEndian) -> Result<gimli::Dwarf<DwarfReader>, Error> {
+    let load_section = |id: gimli::SectionId| -> Result<DwarfReader, gimli::Error> {
+        let data = match object.section_by_name(id.name()) {
+            Some(ref section) => section.uncompressed_data().unwrap_or(borrow::Cow::Borrowed(&[][..])),
+            None => borrow::Cow::Borrowed(&[][..]),
+        };
+        Ok(gimli::EndianRcSlice::new(Rc::from(&*data), endian))
     };
-    // Load a supplementary section. We don't have a supplementary object file,
-    // so always return an empty slice.
-    // let load_section_sup = |_| Ok(borrow::Cow::Borrowed(&[][..]));
+    Ok(gimli::Dwarf::load(&load_section)?)
+}
+
+/// Builds the index of the debug info: the name of each compilation unit, the code addresses it
+/// covers, and the DIE offsets of the functions, global variables and named types declared at its
+/// top level or in its namespaces. Only those DIEs are read; function bodies are left to
+/// `load_unit`.
+pub fn scan_units<R: Reader>(dwarf: &gimli::Dwarf<R>) -> Result<Index, Error> {
+    let mut index = Index::default();
 
-    // Load all of the sections.
-    let dwarf_cow = gimli::Dwarf::load(&load_section)?;
+    // Address ranges by unit offset. Compilers don't always emit .debug_aranges, so units missing
+    // from it fall back to the ranges of their root DIE.
+    let mut aranges: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+    let mut headers = dwarf.debug_aranges.headers();
+    while let Some(header) = headers.next()? {
+        let ranges = aranges.entry(header.debug_info_offset().0).or_default();
+        let mut entries = header.entries();
+        while let Some(entry) = entries.next()? {
+            if entry.length() > 0 {
+                ranges.push((entry.address() as usize, (entry.address() + entry.length()) as usize));
+            }
+        }
+    }
 
-    // Borrow a `Cow<[u8]>` to create an `EndianSlice`.
-    let borrow_section: &dyn for<'a> Fn(
-        &'a borrow::Cow<[u8]>,
-    ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(&*section, endian);
+    let mut iter = dwarf.units();
+    while let Some(header) = iter.next()? {
+        let unit_offset = match header.offset() {
+            UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+            UnitSectionOffset::DebugTypesOffset(_) => continue,
+        };
+        let unit = dwarf.unit(header)?;
+        let unit_number = index.units.len();
+        let ranges = match aranges.remove(&unit_offset) {
+            Some(ranges) => ranges,
+            None => {
+                let mut ranges = Vec::new();
+                let mut iter = dwarf.unit_ranges(&unit)?;
+                while let Some(range) = iter.next()? {
+                    ranges.push((range.begin as usize, range.end as usize));
+                }
+                ranges
+            }
+        };
+        index.ranges.extend(ranges.into_iter().map(|(begin, end)| (begin, end, unit_number)));
+
+        let mut tree = unit.entries_tree(None)?;
+        let root = tree.root()?;
+        let mut name = get_attr_str(root.entry(), gimli::DW_AT_name, &unit, dwarf)
+            .unwrap_or_else(|| "<unknown>".to_string());
+        if let Some(end) = name.find("/@/") {
+            name.truncate(end);
+        }
+        index.units.push((unit_offset, name));
+        scan_entries(root, &unit, dwarf, &mut index, true)?;
+    }
+    index.ranges.sort();
+    Ok(index)
+}
+
+/// Adds the functions, variables and types among the children of `node` to `index`, descending
+/// into namespaces.
+fn scan_entries<R: Reader>(
+    node: gimli::EntriesTreeNode<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    index: &mut Index,
+    top_level: bool,
+) -> Result<(), Error> {
+    let mut children = node.children();
+    while let Some(child) = children.next()? {
+        let entry = child.entry();
+        let die_offset = match entry.offset().to_unit_section_offset(unit) {
+            UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+            UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+        };
+        let is_declaration = entry.attr_value(gimli::DW_AT_declaration)?.is_some();
+        match entry.tag() {
+            gimli::DW_TAG_namespace => scan_entries(child, unit, dwarf, index, false)?,
+            gimli::DW_TAG_subprogram => {
+                let name = match declared_name(entry, unit, dwarf) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let has_code = entry.attr_value(gimli::DW_AT_low_pc)?.is_some()
+                    || entry.attr_value(gimli::DW_AT_ranges)?.is_some();
+                // Functions without code are still needed for the copies inlined elsewhere
+                let names = if has_code { &mut index.functions } else { &mut index.declarations };
+                names.entry(name).or_default().push(die_offset);
+            }
+            // Variables in namespaces and functions are not globals
+            gimli::DW_TAG_variable if top_level && !is_declaration => {
+                let has_location = entry.attr_value(gimli::DW_AT_location)?.is_some()
+                    || entry.attr_value(gimli::DW_AT_const_value)?.is_some();
+                if let Some(name) = declared_name(entry, unit, dwarf).filter(|_| has_location) {
+                    index.variables.entry(name).or_default().push(die_offset);
+                }
+            }
+            gimli::DW_TAG_base_type
+            | gimli::DW_TAG_typedef
+            | gimli::DW_TAG_structure_type
+            | gimli::DW_TAG_class_type
+            | gimli::DW_TAG_union_type
+            | gimli::DW_TAG_enumeration_type
+                if !is_declaration =>
+            {
+                let name = match get_attr_str(entry, gimli::DW_AT_name, unit, dwarf) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                // Under the names `name_types` may give them: Rust enums keep their own name
+                let names = match entry.tag() {
+                    gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type => {
+                        vec![format!("struct {}", name), name]
+                    }
+                    gimli::DW_TAG_union_type => vec![format!("union {}", name)],
+                    gimli::DW_TAG_enumeration_type => vec![format!("enum {}", name)],
+                    _ => vec![name],
+                };
+                for name in names {
+                    index.types.entry(name).or_default().push(die_offset);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
 
-    // Create `EndianSlice`s for all of the sections.
-    let dwarf = dwarf_cow.borrow(&borrow_section);
+/// Returns the name of a function or variable DIE, following DW_AT_specification and
+/// DW_AT_abstract_origin to the declaration that has it.
+fn declared_name<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    if let Some(name) = get_attr_str(entry, gimli::DW_AT_name, unit, dwarf) {
+        return Some(name);
+    }
+    let mut offset = entry.offset();
+    for _ in 0..8 {
+        let entry = unit.entry(offset).ok()?;
+        if let Some(name) = get_attr_str(&entry, gimli::DW_AT_name, unit, dwarf) {
+            return Some(name);
+        }
+        let origin = match entry.attr_value(gimli::DW_AT_specification).ok()? {
+            Some(origin) => origin,
+            None => entry.attr_value(gimli::DW_AT_abstract_origin).ok()??,
+        };
+        // References into other units only occur with link-time optimization
+        offset = match origin {
+            gimli::AttributeValue::UnitRef(origin) => origin,
+            _ => return None,
+        };
+    }
+    None
+}
 
+/// Parses the compilation unit at `offset` in .debug_info: its functions, variables, types and
+/// line table.
+pub fn load_unit<R: Reader>(
+    dwarf: &gimli::Dwarf<R>,
+    offset: usize,
+) -> Result<(File, HashMap<usize, Type>), Error> {
     // Define a mapping from type offsets to type structs
     let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
 
     let mut compilation_units: Vec<File> = Vec::new();
 
+    // Optimized code splits functions and variables into an abstract instance holding the name,
+    // type and declaration line, and concrete instances referring to it via DW_AT_abstract_origin
+    let mut abstract_origins: HashMap<usize, (String, Option<usize>, usize)> = HashMap::new();
+    let mut function_origins: Vec<(usize, usize, usize)> = Vec::new(); // file, function, DIE
+    let mut inlined_origins: Vec<(usize, usize, usize)> = Vec::new(); // file, inlined, DIE
+    let mut variable_origins: Vec<(usize, VariableOwner, usize, usize)> = Vec::new();
+    // Declarations of C++ methods and Rust functions carry the mangled name, which definitions
+    // and inlined copies reach through DW_AT_specification/DW_AT_abstract_origin
+    let mut linkage_names: HashMap<usize, String> = HashMap::new();
+    let mut subprogram_origins: HashMap<usize, usize> = HashMap::new();
+
     // Iterate over the compilation units.
-    let mut iter = dwarf.units();
-    while let Some(header) = iter.next()? {
-        let unit = dwarf.unit(header)?;
+    let header = dwarf.debug_info.header_from_offset(gimli::DebugInfoOffset(offset))?;
+    let unit = dwarf.unit(header)?;
 
-        // Iterate over the Debugging Information Entries (DIEs) in the unit.
-        let mut depth = 0;
-        let mut entries = unit.entries();
-        while let Some((delta_depth, entry)) = entries.next_dfs()? {
-            depth += delta_depth;
-            // Update the offset_to_type mapping for types
-            // Update the variable list for formal params/variables
-            match entry.tag() {
-                gimli::DW_TAG_compile_unit => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
+    // Iterate over the Debugging Information Entries (DIEs) in the unit.
+    let mut depth = 0;
+    // Enclosing struct/union/enum/array types, so that members, enumerators and subranges can
+    // be attached to their parent
+    let mut type_parents: Vec<(isize, usize)> = Vec::new();
+    // Inlined subroutines we are inside of (depth, index into File::inlined_functions). Their
+    // variables belong to the inlined function rather than the function it was inlined into.
+    let mut inline_stack: Vec<(isize, usize)> = Vec::new();
+    // Depth of the last subprogram, whose direct formal_parameter children are its parameters
+    let mut subprogram_depth: Option<isize> = None;
+    // Variant part being read (depth, enum type, discriminant member DIE), and the variant
+    // within it (depth, discriminant value)
+    let mut variant_part: Option<(isize, usize, Option<usize>)> = None;
+    let mut variant: Option<(isize, Option<i64>)> = None;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        while matches!(type_parents.last(), Some((parent_depth, _)) if *parent_depth >= depth)
+        {
+            type_parents.pop();
+        }
+        while matches!(inline_stack.last(), Some((inline_depth, _)) if *inline_depth >= depth)
+        {
+            inline_stack.pop();
+        }
+        if matches!(variant_part, Some((part_depth, _, _)) if part_depth >= depth) {
+            variant_part = None;
+        }
+        if matches!(variant, Some((variant_depth, _)) if variant_depth >= depth) {
+            variant = None;
+        }
+        let die_offset = match entry.offset().to_unit_section_offset(&unit) {
+            UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+            UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+        };
+        // Update the offset_to_type mapping for types
+        // Update the variable list for formal params/variables
+        let origin = match entry.attr_value(gimli::DW_AT_abstract_origin)? {
+            Some(origin) => Some(origin),
+            None => entry.attr_value(gimli::DW_AT_specification)?,
+        };
+        let origin = match origin {
+            Some(gimli::AttributeValue::UnitRef(offset)) => {
+                match offset.to_unit_section_offset(&unit) {
+                    UnitSectionOffset::DebugInfoOffset(goff) => Some(goff.0),
+                    UnitSectionOffset::DebugTypesOffset(goff) => Some(goff.0),
+                }
+            }
+            Some(gimli::AttributeValue::DebugInfoRef(offset)) => Some(offset.0),
+            _ => None,
+        };
+        match entry.tag() {
+            gimli::DW_TAG_inlined_subroutine => {
+                let mut inlined = InlinedFunction::default();
+                let mut ranges = dwarf.die_ranges(&unit, entry)?;
+                while let Some(range) = ranges.next()? {
+                    inlined.ranges.push((range.begin as usize, range.end as usize));
+                }
+                inlined.entry_address = match entry.attr_value(gimli::DW_AT_entry_pc)? {
+                    Some(gimli::AttributeValue::Addr(addr)) => addr as usize,
+                    _ => inlined.ranges.iter().map(|r| r.0).min().unwrap_or(0),
+                };
+                let file_index = compilation_units.len() - 1;
+                let file = compilation_units.last_mut().unwrap();
+                if let Some(origin) = origin {
+                    subprogram_origins.insert(die_offset, origin);
+                    inlined_origins.push((file_index, file.inlined_functions.len(), die_offset));
+                }
+                inline_stack.push((depth, file.inlined_functions.len()));
+                file.inlined_functions.push(inlined);
+            }
+            gimli::DW_TAG_compile_unit => {
+                let mut name = get_attr_str(entry, gimli::DW_AT_name, &unit, dwarf)
+                    .unwrap_or_else(|| "<unknown>".to_string());
+                // rustc names units after the crate root and codegen unit, e.g.
+                // "src/main.rs/@/demo.1ba8404ce28d149d-cgu.0"
+                if let Some(end) = name.find("/@/") {
+                    name.truncate(end);
+                }
+                let comp_dir = get_attr_str(entry, gimli::DW_AT_comp_dir, &unit, dwarf).unwrap_or_default();
+                compilation_units.push(File {
+                    name,
+                    comp_dir,
+                    global_variables: Vec::new(),
+                    functions: Vec::new(),
+                    inlined_functions: Vec::new(),
+                    lines: Vec::new(),
+                });
+            }
+            gimli::DW_TAG_base_type => {
+                let name = get_attr_str(entry, gimli::DW_AT_name, &unit, dwarf)
+                    .unwrap_or_else(|| "<unknown>".to_string());
+                let byte_size = get_attr_uint(entry, gimli::DW_AT_byte_size, &unit, dwarf)
+                    .unwrap_or(0);
+                let encoding = match entry.attr_value(gimli::DW_AT_encoding)? {
+                    Some(gimli::AttributeValue::Encoding(encoding)) => encoding,
+                    _ => gimli::DW_ATE_signed,
+                };
+                let kind = match encoding {
+                    gimli::DW_ATE_unsigned => BaseEncoding::Unsigned,
+                    gimli::DW_ATE_signed_char => BaseEncoding::SignedChar,
+                    gimli::DW_ATE_unsigned_char => BaseEncoding::UnsignedChar,
+                    gimli::DW_ATE_float => BaseEncoding::Float,
+                    gimli::DW_ATE_boolean => BaseEncoding::Bool,
+                    _ => BaseEncoding::Signed,
+                };
+                let mut dtype = Type::new(name, byte_size.try_into().unwrap());
+                dtype.kind = TypeKind::Base(kind);
+                dtype.offset = Some(die_offset);
+                offset_to_type.insert(die_offset, dtype);
+            }
+            gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_reference_type
+            | gimli::DW_TAG_typedef
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type
+            | gimli::DW_TAG_structure_type
+            | gimli::DW_TAG_class_type
+            | gimli::DW_TAG_union_type
+            | gimli::DW_TAG_enumeration_type
+            | gimli::DW_TAG_array_type
+            | gimli::DW_TAG_subroutine_type => {
+                let name = get_attr_str(entry, gimli::DW_AT_name, &unit, dwarf)
+                    .unwrap_or_default();
+                let byte_size = get_attr_uint(entry, gimli::DW_AT_byte_size, &unit, dwarf)
+                    .unwrap_or(0);
+                let target = get_attr_type(entry, &unit, dwarf);
+                let kind = match entry.tag() {
+                    gimli::DW_TAG_pointer_type | gimli::DW_TAG_reference_type => {
+                        TypeKind::Pointer(target)
+                    }
+                    gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type => {
+                        TypeKind::Struct(Vec::new())
+                    }
+                    gimli::DW_TAG_union_type => TypeKind::Union(Vec::new()),
+                    gimli::DW_TAG_enumeration_type => TypeKind::Enum(Vec::new()),
+                    gimli::DW_TAG_array_type => TypeKind::Array(target, None),
+                    gimli::DW_TAG_subroutine_type => TypeKind::Function,
+                    _ => TypeKind::Typedef(target),
+                };
+                let mut dtype = Type::new(name, byte_size.try_into().unwrap());
+                dtype.kind = kind;
+                dtype.offset = Some(die_offset);
+                // Qualifiers and typedefs only get their final name once everything is read
+                match entry.tag() {
+                    gimli::DW_TAG_const_type => dtype.name = "const".to_string(),
+                    gimli::DW_TAG_volatile_type => dtype.name = "volatile".to_string(),
+                    gimli::DW_TAG_restrict_type => dtype.name = "restrict".to_string(),
+                    _ => {}
+                }
+                if entry.has_children() {
+                    type_parents.push((depth, die_offset));
+                }
+                offset_to_type.insert(die_offset, dtype);
+            }
+            gimli::DW_TAG_variant_part => {
+                if let Some(&(_, parent)) = type_parents.last() {
+                    let discr = match entry.attr_value(gimli::DW_AT_discr)? {
+                        Some(gimli::AttributeValue::UnitRef(offset)) => {
+                            match offset.to_unit_section_offset(&unit) {
+                                UnitSectionOffset::DebugInfoOffset(goff) => Some(goff.0),
+                                UnitSectionOffset::DebugTypesOffset(goff) => Some(goff.0),
+                            }
                         }
-                    } else {
-                        "<unknown>".to_string()
+                        _ => None,
                     };
-                    compilation_units.push(File {
-                        name,
-                        global_variables: Vec::new(),
-                        functions: Vec::new(),
-                        lines: Vec::new(),
-                    });
+                    if let Some(dtype) = offset_to_type.get_mut(&parent) {
+                        dtype.kind = TypeKind::Variants(None, Vec::new());
+                    }
+                    variant_part = Some((depth, parent, discr));
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
+            }
+            gimli::DW_TAG_variant => {
+                let value = get_attr_uint(entry, gimli::DW_AT_discr_value, &unit, dwarf);
+                variant = Some((depth, value.map(|v| v as i64)));
+            }
+            gimli::DW_TAG_member => {
+                if let Some((_, parent)) = type_parents.last() {
+                    let member = Member {
+                        name: get_attr_str(entry, gimli::DW_AT_name, &unit, dwarf)
+                            .unwrap_or_default(),
+                        type_offset: get_attr_type(entry, &unit, dwarf),
+                        offset: get_attr_uint(
+                            entry,
+                            gimli::DW_AT_data_member_location,
+                            &unit,
+                            dwarf,
+                        )
+                        .unwrap_or(0)
+                        .try_into()
+                        .unwrap(),
                     };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
+                    match offset_to_type.get_mut(parent).map(|t| &mut t.kind) {
+                        Some(TypeKind::Struct(members)) | Some(TypeKind::Union(members)) => {
+                            members.push(member)
                         }
-                    } else {
-                        // TODO: report error?
-                        0
+                        Some(TypeKind::Variants(discr, variants)) => {
+                            if let Some((_, value)) = variant {
+                                variants.push((value, member));
+                            } else if matches!(variant_part, Some((_, _, Some(d))) if d == die_offset) {
+                                *discr = Some(member);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            gimli::DW_TAG_enumerator => {
+                if let Some((_, parent)) = type_parents.last() {
+                    let name = get_attr_str(entry, gimli::DW_AT_name, &unit, dwarf)
+                        .unwrap_or_default();
+                    let value = match entry.attr_value(gimli::DW_AT_const_value)? {
+                        Some(gimli::AttributeValue::Sdata(v)) => v,
+                        Some(gimli::AttributeValue::Udata(v)) => v as i64,
+                        Some(gimli::AttributeValue::Data1(v)) => v as i64,
+                        Some(gimli::AttributeValue::Data2(v)) => v as i64,
+                        Some(gimli::AttributeValue::Data4(v)) => v as i64,
+                        Some(gimli::AttributeValue::Data8(v)) => v as i64,
+                        _ => 0,
+                    };
+                    if let Some(TypeKind::Enum(values)) =
+                        offset_to_type.get_mut(parent).map(|t| &mut t.kind)
+                    {
+                        values.push((name, value));
+                    }
+                }
+            }
+            gimli::DW_TAG_subrange_type => {
+                if let Some((_, parent)) = type_parents.last() {
+                    let count = match get_attr_uint(entry, gimli::DW_AT_count, &unit, dwarf) {
+                        Some(count) => Some(count as usize),
+                        None => get_attr_uint(entry, gimli::DW_AT_upper_bound, &unit, dwarf)
+                            .map(|upper| upper as usize + 1),
                     };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
-                gimli::DW_TAG_subprogram => {
-                    let mut func: Function = Default::default();
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(name)) = val {
-                                    func.name = name;
-                                }
+                    if let Some(TypeKind::Array(_, array_count)) =
+                        offset_to_type.get_mut(parent).map(|t| &mut t.kind)
+                    {
+                        // Multi-dimensional arrays are flattened into their outer dimension
+                        if array_count.is_none() {
+                            *array_count = count;
+                        }
+                    }
+                }
+            }
+            gimli::DW_TAG_subprogram => {
+                let mut func = Function { offset: die_offset, ..Default::default() };
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    let val = get_attr_value(&attr, &unit, dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                func.name = name;
                             }
-                            gimli::DW_AT_high_pc => {
-                                if let Ok(DebugValue::Uint(high_pc)) = val {
-                                    func.text_length = high_pc.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_high_pc => {
+                            if let Ok(DebugValue::Uint(high_pc)) = val {
+                                func.text_length = high_pc.try_into().unwrap();
                             }
-                            gimli::DW_AT_low_pc => {
-                                //println!("low pc {:?}", attr.value());
-                                if let Ok(DebugValue::Uint(low_pc)) = val {
-                                    func.address = low_pc.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_low_pc => {
+                            //println!("low pc {:?}", attr.value());
+                            if let Ok(DebugValue::Uint(low_pc)) = val {
+                                func.address = low_pc.try_into().unwrap();
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(line_number)) = val {
-                                    func.line_number = line_number.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(line_number)) = val {
+                                func.line_number = line_number.try_into().unwrap();
                             }
-                            _ => {}
                         }
+                        gimli::DW_AT_frame_base => {
+                            func.frame_base = get_location(&attr, &unit, dwarf);
+                        }
+                        gimli::DW_AT_type => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                func.return_type = Some(Type {
+                                    offset: Some(offset),
+                                    ..Default::default()
+                                });
+                            }
+                        }
+                        gimli::DW_AT_linkage_name | gimli::DW_AT_MIPS_linkage_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                linkage_names.insert(die_offset, name);
+                            }
+                        }
+                        _ => {}
                     }
-                    compilation_units.last_mut().unwrap().functions.push(func);
-                }
-                gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
-                    let mut name = String::new();
-                    let mut entity_type: Option<Type> = None;
-                    let mut location: Option<Location> = None;
-                    let mut line_number = 0;
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(attr_name)) = val {
-                                    name = attr_name;
-                                }
+                }
+                // DW_AT_high_pc is either an offset from low_pc or an absolute address
+                if let Some(gimli::AttributeValue::Addr(_)) =
+                    entry.attr_value(gimli::DW_AT_high_pc)?
+                {
+                    func.text_length = func.text_length.saturating_sub(func.address);
+                }
+                if !func.name.is_empty() {
+                    let return_type = func.return_type.as_ref().and_then(|t| t.offset);
+                    abstract_origins.insert(
+                        die_offset,
+                        (func.name.clone(), return_type, func.line_number),
+                    );
+                }
+                subprogram_depth = Some(depth);
+                let file_index = compilation_units.len() - 1;
+                let file = compilation_units.last_mut().unwrap();
+                if let Some(origin) = origin {
+                    subprogram_origins.insert(die_offset, origin);
+                }
+                function_origins.push((file_index, file.functions.len(), die_offset));
+                file.functions.push(func);
+            }
+            gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
+                let mut name = String::new();
+                let mut type_offset: Option<usize> = None;
+                let mut location: Option<Location> = None;
+                let mut line_number = 0;
+                let mut is_declaration = false;
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    let val = get_attr_value(&attr, &unit, dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(attr_name)) = val {
+                                name = attr_name;
                             }
-                            gimli::DW_AT_type => {
-                                if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
-                                        entity_type = Some(dtype.clone());
-                                    }
-                                }
+                        }
+                        gimli::DW_AT_type => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                type_offset = Some(offset);
                             }
-                            gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
-                                    location = Some(loc);
-                                }
+                        }
+                        gimli::DW_AT_location => {
+                            if let Some(loc) = get_location(&attr, &unit, dwarf) {
+                                location = Some(loc);
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(num)) = val {
-                                    line_number = num;
-                                }
+                        }
+                        gimli::DW_AT_const_value => {
+                            if let Some(loc) = get_const_value(&attr) {
+                                location = Some(loc);
                             }
-                            _ => {}
                         }
-                    }
-                    if entity_type.is_some() && location.is_some() {
-                        let var = Variable {
-                            name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
-                            line_number: line_number.try_into().unwrap(),
-                        };
-                        if depth == 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .global_variables
-                                .push(var);
-                        } else if depth > 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .functions
-                                .last_mut()
-                                .unwrap()
-                                .variables
-                                .push(var);
+                        gimli::DW_AT_declaration => {
+                            is_declaration = true;
                         }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(num)) = val {
+                                line_number = num;
+                            }
+                        }
+                        _ => {}
                     }
                 }
-                // NOTE: :You may consider supporting other types by extending this
-                // match statement
-                _ => {}
+                // Locals without a location have been optimized away
+                if location.is_none() && depth > 1 && !is_declaration {
+                    location = Some(Location::OptimizedOut);
+                }
+                if !name.is_empty() {
+                    abstract_origins.insert(
+                        die_offset,
+                        (name.clone(), type_offset, line_number.try_into().unwrap()),
+                    );
+                }
+                if type_offset.is_none() && origin.is_none() {
+                    continue;
+                }
+                if let Some(location) = location {
+                    // The type itself is filled in once all types have been read
+                    let entity_type = Type {
+                        offset: type_offset,
+                        ..Default::default()
+                    };
+                    let var = Variable {
+                        name,
+                        entity_type,
+                        location,
+                        line_number: line_number.try_into().unwrap(),
+                        is_parameter: entry.tag() == gimli::DW_TAG_formal_parameter
+                            && inline_stack.is_empty()
+                            && subprogram_depth == Some(depth - 1),
+                    };
+                    let file_index = compilation_units.len() - 1;
+                    let file = compilation_units.last_mut().unwrap();
+                    let (owner, variables) = if depth == 1 {
+                        (VariableOwner::Global, &mut file.global_variables)
+                    } else if let Some(&(_, inlined)) = inline_stack.last() {
+                        (
+                            VariableOwner::Inlined(inlined),
+                            &mut file.inlined_functions[inlined].variables,
+                        )
+                    } else if let Some(func) = file.functions.len().checked_sub(1) {
+                        (VariableOwner::Function(func), &mut file.functions[func].variables)
+                    } else {
+                        continue;
+                    };
+                    if let Some(origin) = origin {
+                        variable_origins.push((file_index, owner, variables.len(), origin));
+                    }
+                    variables.push(var);
+                }
             }
+            // NOTE: :You may consider supporting other types by extending this
+            // match statement
+            _ => {}
         }
+    }
 
-        // Get line numbers
-        if let Some(program) = unit.line_program.clone() {
-            // Iterate over the line program rows.
-            let mut rows = program.rows();
-            while let Some((header, row)) = rows.next_row()? {
-                if !row.end_sequence() {
-                    // Determine the path. Real applications should cache this for performance.
-                    let mut path = path::PathBuf::new();
-                    if let Some(file) = row.file(header) {
-                        if let Some(dir) = file.directory(header) {
-                            path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
-                        }
-                        path.push(
-                            dwarf
-                                .attr_string(&unit, file.path_name())?
-                                .to_string_lossy()
-                                .as_ref(),
-                        );
+    // Get line numbers
+    if let Some(program) = unit.line_program.clone() {
+        // Iterate over the line program rows.
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            if !row.end_sequence() {
+                // Determine the path. Real applications should cache this for performance.
+                // Relative directories are relative to the compilation directory
+                let mut path = path::PathBuf::new();
+                if let Some(comp_dir) = unit.comp_dir.as_ref() {
+                    path.push(comp_dir.to_string_lossy()?.as_ref());
+                }
+                if let Some(file) = row.file(header) {
+                    if let Some(dir) = file.directory(header) {
+                        path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy()?.as_ref());
                     }
+                    path.push(
+                        dwarf
+                            .attr_string(&unit, file.path_name())?
+                            .to_string_lossy()?
+                            .as_ref(),
+                    );
+                }
 
-                    // Get the File
-                    let file = compilation_units
-                        .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
+                // Get the File. Units compiled from a relative path are named relative to the
+                // compilation directory, while line program paths are absolute.
+                let file = compilation_units
+                    .iter_mut()
+                    .find(|f| path.as_os_str() == f.name.as_str() || path.ends_with(&f.name));
 
-                    // Determine line/column. DWARF line/column is never 0, so we use that
-                    // but other applications may want to display this differently.
-                    let line = if let Some(line) = row.line() {
-                        line.get().try_into().unwrap()
-                    } else {
-                        0
-                    };
+                // Determine line/column. DWARF line/column is never 0, so we use that
+                // but other applications may want to display this differently.
+                let line = if let Some(line) = row.line() {
+                    line.get().try_into().unwrap()
+                } else {
+                    0
+                };
 
-                    if let Some(file) = file {
-                        file.lines.push(Line {
-                            file: file.name.clone(),
-                            number: line.try_into().unwrap(),
-                            address: row.address().try_into().unwrap(),
-                        });
-                    }
+                if let Some(file) = file {
+                    file.lines.push(Line {
+                        file: file.name.clone(),
+                        number: line.try_into().unwrap(),
+                        address: row.address().try_into().unwrap(),
+                    });
                 }
             }
         }
     }
-    Ok(compilation_units)
+    // Follows DW_AT_abstract_origin/DW_AT_specification links from a subprogram DIE until `get`
+    // finds something
+    fn follow<'a, T>(
+        die: usize,
+        origins: &HashMap<usize, usize>,
+        get: impl Fn(usize) -> Option<&'a T>,
+    ) -> Option<&'a T> {
+        let mut die = die;
+        for _ in 0..8 {
+            if let Some(value) = get(die) {
+                return Some(value);
+            }
+            die = *origins.get(&die)?;
+        }
+        None
+    }
+    for (file, func, die) in function_origins {
+        let func = &mut compilation_units[file].functions[func];
+        if let Some((name, return_type, line_number)) =
+            follow(die, &subprogram_origins, |d| abstract_origins.get(&d))
+        {
+            if func.name.is_empty() {
+                func.name = name.clone();
+                func.line_number = *line_number;
+            }
+            if func.return_type.is_none() {
+                func.return_type = return_type.map(|offset| Type {
+                    offset: Some(offset),
+                    ..Default::default()
+                });
+            }
+        }
+        func.qualified_name = match follow(die, &subprogram_origins, |d| linkage_names.get(&d)) {
+            Some(linkage_name) => qualified_name(linkage_name),
+            None => func.name.clone(),
+        };
+    }
+    for (file, inlined, die) in inlined_origins {
+        let inlined = &mut compilation_units[file].inlined_functions[inlined];
+        if let Some((name, _, _)) = follow(die, &subprogram_origins, |d| abstract_origins.get(&d)) {
+            inlined.name = name.clone();
+        }
+        inlined.qualified_name = match follow(die, &subprogram_origins, |d| linkage_names.get(&d)) {
+            Some(linkage_name) => qualified_name(linkage_name),
+            None => inlined.name.clone(),
+        };
+    }
+    for (file, owner, var, origin) in variable_origins {
+        let file = &mut compilation_units[file];
+        let var = match owner {
+            VariableOwner::Global => &mut file.global_variables[var],
+            VariableOwner::Function(func) => &mut file.functions[func].variables[var],
+            VariableOwner::Inlined(inlined) => &mut file.inlined_functions[inlined].variables[var],
+        };
+        if let Some((name, type_offset, line_number)) = abstract_origins.get(&origin) {
+            if var.name.is_empty() {
+                var.name = name.clone();
+                var.line_number = *line_number;
+            }
+            if var.entity_type.offset.is_none() {
+                var.entity_type.offset = *type_offset;
+            }
+        }
+    }
+
+    name_types(&mut offset_to_type);
+    for file in compilation_units.iter_mut() {
+        file.global_variables.retain(|var| var.entity_type.offset.is_some());
+        for func in file.functions.iter_mut() {
+            func.variables.retain(|var| var.entity_type.offset.is_some());
+        }
+        for inlined in file.inlined_functions.iter_mut() {
+            inlined.variables.retain(|var| var.entity_type.offset.is_some());
+        }
+        let variables = file
+            .global_variables
+            .iter_mut()
+            .chain(file.functions.iter_mut().flat_map(|f| f.variables.iter_mut()))
+            .chain(file.inlined_functions.iter_mut().flat_map(|f| f.variables.iter_mut()));
+        for var in variables {
+            if let Some(dtype) = var.entity_type.offset.and_then(|o| offset_to_type.get(&o)) {
+                var.entity_type = dtype.clone();
+            }
+        }
+        for func in file.functions.iter_mut() {
+            let return_type = func.return_type.as_ref().and_then(|t| t.offset);
+            if let Some(dtype) = return_type.and_then(|o| offset_to_type.get(&o)) {
+                func.return_type = Some(dtype.clone());
+            }
+        }
+    }
+    Ok((compilation_units.pop().unwrap_or_default(), offset_to_type))
+}
+
+/// Gives every derived type (pointers, arrays, qualifiers, ...) a C-like display name, such as
+/// `const char *` or `struct point [4]`.
+fn name_types(types: &mut HashMap<usize, Type>) {
+    fn type_name(types: &HashMap<usize, Type>, offset: Option<usize>, depth: usize) -> String {
+        let dtype = match offset.and_then(|o| types.get(&o)) {
+            Some(dtype) => dtype,
+            None => return "void".to_string(),
+        };
+        if depth > 16 {
+            return dtype.name.clone();
+        }
+        match &dtype.kind {
+            TypeKind::Pointer(target) => {
+                let target = types.get(&target.unwrap_or(usize::MAX));
+                if let Some(TypeKind::Function) = target.map(|t| &t.kind) {
+                    return "void (*)()".to_string();
+                }
+                let inner = type_name(types, target.and_then(|t| t.offset), depth + 1);
+                if inner.ends_with('*') {
+                    format!("{}*", inner)
+                } else {
+                    format!("{} *", inner)
+                }
+            }
+            TypeKind::Array(elem, count) => format!(
+                "{} [{}]",
+                type_name(types, *elem, depth + 1),
+                count.map(|c| c.to_string()).unwrap_or_default()
+            ),
+            TypeKind::Typedef(target) => match dtype.name.as_str() {
+                "const" | "volatile" | "restrict" => {
+                    format!("{} {}", dtype.name, type_name(types, *target, depth + 1))
+                }
+                "" => type_name(types, *target, depth + 1),
+                name => name.to_string(),
+            },
+            TypeKind::Struct(_) if !dtype.name.is_empty() => format!("struct {}", dtype.name),
+            TypeKind::Union(_) if !dtype.name.is_empty() => format!("union {}", dtype.name),
+            TypeKind::Enum(_) if !dtype.name.is_empty() => format!("enum {}", dtype.name),
+            TypeKind::Struct(_) => "struct {...}".to_string(),
+            TypeKind::Union(_) => "union {...}".to_string(),
+            TypeKind::Enum(_) => "enum {...}".to_string(),
+            TypeKind::Function => "void ()".to_string(),
+            // Rust enums keep their own name, e.g. `Option<i32>`
+            _ => dtype.name.clone(),
+        }
+    }
+
+    let names: Vec<(usize, String)> = types
+        .keys()
+        .map(|&offset| (offset, type_name(types, Some(offset), 0)))
+        .collect();
+    let mut sizes: Vec<(usize, usize)> = Vec::new();
+    for (&offset, dtype) in types.iter() {
+        if dtype.size == 0 {
+            let size = match dtype.kind {
+                TypeKind::Pointer(_) => 8,
+                TypeKind::Typedef(target) => type_size(types, target, 0),
+                TypeKind::Array(elem, count) => type_size(types, elem, 0) * count.unwrap_or(0),
+                _ => 0,
+            };
+            sizes.push((offset, size));
+        }
+    }
+    for (offset, name) in names {
+        types.get_mut(&offset).unwrap().name = name;
+    }
+    for (offset, size) in sizes {
+        types.get_mut(&offset).unwrap().size = size;
+    }
+}
+
+fn type_size(types: &HashMap<usize, Type>, offset: Option<usize>, depth: usize) -> usize {
+    match offset.and_then(|o| types.get(&o)) {
+        Some(dtype) if dtype.size != 0 || depth > 16 => dtype.size,
+        Some(dtype) => match dtype.kind {
+            TypeKind::Pointer(_) => 8,
+            TypeKind::Typedef(target) => type_size(types, target, depth + 1),
+            TypeKind::Array(elem, count) => type_size(types, elem, depth + 1) * count.unwrap_or(0),
+            _ => 0,
+        },
+        None => 0,
+    }
+}
+
+/// Which list a variable read from a DIE was added to.
+enum VariableOwner {
+    Global,
+    Function(usize),
+    Inlined(usize),
 }
 
 #[derive(Debug, Clone)]
@@ -260,6 +869,7 @@ pub enum DebugValue {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     GimliError(gimli::Error),
     // Addr2lineError(addr2line::gimli::Error),
@@ -267,6 +877,16 @@ pub enum Error {
     IoError,
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::GimliError(err) => write!(f, "{}", err),
+            Error::ObjectError(msg) => write!(f, "{}", msg),
+            Error::IoError => write!(f, "I/O error"),
+        }
+    }
+}
+
 impl From<gimli::Error> for Error {
     fn from(err: gimli::Error) -> Self {
         Error::GimliError(err)
@@ -291,32 +911,266 @@ impl From<std::fmt::Error> for Error {
     }
 }
 
-impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
-    Endian: gimli::Endianity + Send + Sync
-{
-}
+impl<R: gimli::Reader<Offset = usize>> Reader for R {}
 
-trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
+pub trait Reader: gimli::Reader<Offset = usize> {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
+    let encoding = unit.encoding();
     if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
         let mut pc = data.0.clone();
-        if pc.len() > 0 {
-            if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
-                    }
-                    _ => {}
+        if pc.is_empty() {
+            return Some(Location::OptimizedOut);
+        }
+        // The common cases in unoptimized code are a single DW_OP_fbreg or DW_OP_addr
+        if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
+            match op {
+                gimli::Operation::FrameOffset { offset } if pc.is_empty() => {
+                    return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
                 }
+                gimli::Operation::Address { address } if pc.is_empty() => {
+                    return Some(Location::Address(address.try_into().unwrap()));
+                }
+                _ => {}
             }
         }
+        return Some(Location::Expression(
+            encoding,
+            data.0.to_slice().ok()?.into_owned(),
+        ));
+    }
+
+    let mut locations = dwarf.attr_locations(unit, attr.value()).ok()??;
+    let mut ranges = Vec::new();
+    while let Ok(Some(entry)) = locations.next() {
+        ranges.push(LocationRange {
+            begin: entry.range.begin as usize,
+            end: entry.range.end as usize,
+            expression: entry.data.0.to_slice().ok()?.into_owned(),
+        });
+    }
+    Some(Location::List(encoding, ranges))
+}
+
+fn get_const_value<R: Reader>(attr: &gimli::Attribute<R>) -> Option<Location> {
+    let bytes = match attr.value() {
+        gimli::AttributeValue::Block(data) => data.to_slice().ok()?.into_owned(),
+        gimli::AttributeValue::Sdata(v) => v.to_le_bytes().to_vec(),
+        gimli::AttributeValue::Udata(v) => v.to_le_bytes().to_vec(),
+        gimli::AttributeValue::Data1(v) => vec![v],
+        gimli::AttributeValue::Data2(v) => v.to_le_bytes().to_vec(),
+        gimli::AttributeValue::Data4(v) => v.to_le_bytes().to_vec(),
+        gimli::AttributeValue::Data8(v) => v.to_le_bytes().to_vec(),
+        _ => return None,
+    };
+    Some(Location::Value(bytes))
+}
+
+/// Gives the evaluation of a DWARF expression access to the state of the stopped inferior.
+/// Returning None means the value is not available.
+pub trait EvaluationContext {
+    fn register(&self, register: u16) -> Option<u64>;
+    fn memory(&self, address: u64, size: u8) -> Option<u64>;
+    fn frame_base(&self) -> Option<u64>;
+    fn call_frame_cfa(&self) -> Option<u64>;
+    /// Returns where the object that `DW_OP_addr` refers to at `address` was loaded.
+    fn relocate(&self, address: u64) -> u64;
+}
+
+/// Where (part of) an object lives after evaluating its location expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PieceLocation {
+    Memory(u64),
+    Register(u16),
+    Bytes(Vec<u8>),
+    OptimizedOut,
+}
+
+/// Evaluates a DWARF location expression. Returns the pieces making up the object along with
+/// their sizes in bytes (None for an object made up of a single piece).
+pub fn evaluate_location(
+    encoding: gimli::Encoding,
+    bytes: &[u8],
+    ctx: &dyn EvaluationContext,
+) -> Result<Vec<(Option<usize>, PieceLocation)>, Error> {
+    let unavailable = || Error::GimliError(gimli::Error::UnsupportedEvaluation);
+    let mut eval =
+        gimli::Evaluation::new(gimli::EndianSlice::new(bytes, gimli::LittleEndian), encoding);
+    let mut result = eval.evaluate()?;
+    loop {
+        result = match result {
+            gimli::EvaluationResult::Complete => break,
+            gimli::EvaluationResult::RequiresRegister { register, .. } => {
+                let value = ctx.register(register.0).ok_or_else(unavailable)?;
+                eval.resume_with_register(gimli::Value::Generic(value))?
+            }
+            gimli::EvaluationResult::RequiresMemory { address, size, .. } => {
+                let value = ctx.memory(address, size).ok_or_else(unavailable)?;
+                eval.resume_with_memory(gimli::Value::Generic(value))?
+            }
+            gimli::EvaluationResult::RequiresFrameBase => {
+                eval.resume_with_frame_base(ctx.frame_base().ok_or_else(unavailable)?)?
+            }
+            gimli::EvaluationResult::RequiresCallFrameCfa => {
+                eval.resume_with_call_frame_cfa(ctx.call_frame_cfa().ok_or_else(unavailable)?)?
+            }
+            gimli::EvaluationResult::RequiresRelocatedAddress(address) => {
+                eval.resume_with_relocated_address(ctx.relocate(address))?
+            }
+            // Entry values, TLS and references into other DIEs would need call site information
+            // or thread support we don't have
+            _ => return Err(unavailable()),
+        };
+    }
+
+    Ok(eval
+        .result()
+        .into_iter()
+        .map(|piece| {
+            let size = piece.size_in_bits.map(|bits| (bits / 8) as usize);
+            let location = match piece.location {
+                gimli::Location::Address { address } => PieceLocation::Memory(address),
+                gimli::Location::Register { register } => PieceLocation::Register(register.0),
+                gimli::Location::Value { value } => {
+                    let bytes = match value {
+                        gimli::Value::F32(v) => v.to_le_bytes().to_vec(),
+                        gimli::Value::F64(v) => v.to_le_bytes().to_vec(),
+                        other => other.to_u64(u64::MAX).unwrap_or(0).to_le_bytes().to_vec(),
+                    };
+                    PieceLocation::Bytes(bytes)
+                }
+                gimli::Location::Bytes { value } => PieceLocation::Bytes(value.to_vec()),
+                _ => PieceLocation::OptimizedOut,
+            };
+            (size, location)
+        })
+        .collect())
+}
+
+/// One row of the call frame information: how to compute the CFA at some pc, and where the
+/// caller's registers were saved relative to it.
+#[derive(Debug, Clone)]
+pub struct UnwindRow {
+    pub cfa_register: u16,
+    pub cfa_offset: i64,
+    /// Registers saved in the frame, as (DWARF register, offset from the CFA) pairs.
+    pub saved_registers: Vec<(u16, i64)>,
+}
+
+/// The `.eh_frame` and `.debug_frame` sections of an executable.
+pub struct CallFrameInfo {
+    eh_frame: Vec<u8>,
+    eh_frame_address: u64,
+    debug_frame: Vec<u8>,
+    text_address: u64,
+    endian: gimli::RunTimeEndian,
+}
+
+impl CallFrameInfo {
+    pub fn load(object: &object::File, endian: gimli::RunTimeEndian) -> CallFrameInfo {
+        let section = |name: &str| {
+            object
+                .section_by_name(name)
+                .map(|section| {
+                    let data = section.uncompressed_data().unwrap_or_default().into_owned();
+                    (data, section.address())
+                })
+                .unwrap_or_default()
+        };
+        let (eh_frame, eh_frame_address) = section(".eh_frame");
+        let (debug_frame, _) = section(".debug_frame");
+        let text_address = object
+            .section_by_name(".text")
+            .map(|section| section.address())
+            .unwrap_or(0);
+        CallFrameInfo {
+            eh_frame,
+            eh_frame_address,
+            debug_frame,
+            text_address,
+            endian,
+        }
+    }
+
+    pub fn unwind_row(&self, pc: u64) -> Option<UnwindRow> {
+        let bases = gimli::BaseAddresses::default()
+            .set_eh_frame(self.eh_frame_address)
+            .set_text(self.text_address);
+        let mut ctx = gimli::UnwindContext::new();
+        let eh_frame = gimli::EhFrame::new(&self.eh_frame, self.endian);
+        let debug_frame = gimli::DebugFrame::new(&self.debug_frame, self.endian);
+        let row = eh_frame
+            .unwind_info_for_address(&bases, &mut ctx, pc, gimli::EhFrame::cie_from_offset)
+            .cloned()
+            .or_else(|_| {
+                debug_frame
+                    .unwind_info_for_address(&bases, &mut ctx, pc, gimli::DebugFrame::cie_from_offset)
+                    .cloned()
+            })
+            .ok()?;
+        let (cfa_register, cfa_offset) = match row.cfa() {
+            gimli::CfaRule::RegisterAndOffset { register, offset } => (register.0, *offset),
+            gimli::CfaRule::Expression(_) => return None,
+        };
+        let saved_registers = row
+            .registers()
+            .filter_map(|(register, rule)| match rule {
+                gimli::RegisterRule::Offset(offset) => Some((register.0, *offset)),
+                _ => None,
+            })
+            .collect();
+        Some(UnwindRow {
+            cfa_register,
+            cfa_offset,
+            saved_registers,
+        })
+    }
+}
+
+fn get_attr_str<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    name: gimli::DwAt,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    match get_attr_value(&entry.attr(name).ok()??, unit, dwarf) {
+        Ok(DebugValue::Str(s)) => Some(s),
+        _ => None,
+    }
+}
+
+fn get_attr_uint<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    name: gimli::DwAt,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<u64> {
+    match entry.attr(name).ok()??.value() {
+        gimli::AttributeValue::Data1(v) => Some(v as u64),
+        gimli::AttributeValue::Data2(v) => Some(v as u64),
+        gimli::AttributeValue::Data4(v) => Some(v as u64),
+        gimli::AttributeValue::Data8(v) => Some(v),
+        _ => match get_attr_value(&entry.attr(name).ok()??, unit, dwarf) {
+            Ok(DebugValue::Uint(v)) => Some(v),
+            Ok(DebugValue::Int(v)) => Some(v as u64),
+            _ => None,
+        },
+    }
+}
+
+fn get_attr_type<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<usize> {
+    match get_attr_value(&entry.attr(gimli::DW_AT_type).ok()??, unit, dwarf) {
+        Ok(DebugValue::Size(offset)) => Some(offset),
+        _ => None,
     }
-    None
 }
 
 // based on dwarf_dump.rs
@@ -1,11 +1,22 @@
+use nix::sys::personality::{self, Persona};
 use nix::sys::ptrace;
 use nix::sys::signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::process::Child;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::os::fd::BorrowedFd;
+use std::path::{Path, PathBuf};
 use std::mem::size_of;
+use std::time::Instant;
+use std::rc::Rc;
 use crate::dwarf_data::DwarfData;
+use crate::record::SyscallLog;
+use crate::shared_libraries::{self, SharedLibrary};
+use crate::syscalls;
 
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
@@ -18,140 +29,1009 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates the inferior stopped at a system call caught with `catch syscall`. Contains the
+    /// system call number, whether it is returning (rather than being entered), and the
+    /// instruction pointer.
+    Syscall(u64, bool, usize),
+}
+
+/// An argument of a function called in the inferior, classified the way the x86-64 System V
+/// calling convention passes it: in a general purpose register, or in an SSE register as the raw
+/// bits of a float or double.
+#[derive(Debug, Clone, Copy)]
+pub enum CallArg {
+    Integer(u64),
+    Sse(u64),
+}
+
+/// The registers a function called in the inferior returned its result in.
+pub struct CallReturn {
+    /// rax and rdx
+    pub integer: [u64; 2],
+    /// The low 8 bytes of xmm0 and xmm1
+    pub sse: [u64; 2],
+    /// The memory a large struct was returned in
+    pub memory: Vec<u8>,
+}
+
+pub enum CallOutcome {
+    Returned(CallReturn),
+    /// The inferior stopped, exited or was killed before the function returned.
+    Interrupted(Status),
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
 /// pre_exec with Command to call this in the child process.
 fn child_traceme() -> Result<(), std::io::Error> {
-    ptrace::traceme().or(Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "ptrace TRACEME failed",
-    )))
+    // Load shared libraries at the same addresses on every run, so that breakpoints in them can
+    // be reinserted
+    let persona = personality::get().or(Err(std::io::Error::other("personality failed")))?;
+    personality::set(persona | Persona::ADDR_NO_RANDOMIZE)
+        .or(Err(std::io::Error::other("personality failed")))?;
+    ptrace::traceme().or(Err(std::io::Error::other("ptrace TRACEME failed")))
+}
+
+/// Makes the terminal on stdin the controlling terminal of the child, in a new session, so that
+/// it gets the signals typed there (e.g. Ctrl+C) rather than the debugger.
+fn child_set_tty() -> Result<(), std::io::Error> {
+    nix::unistd::setsid().or(Err(std::io::Error::other("setsid failed")))?;
+    if unsafe { nix::libc::ioctl(nix::libc::STDIN_FILENO, nix::libc::TIOCSCTTY, 0) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A redirection of one of the inferior's standard streams, given to `run` as in a shell.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Redirection {
+    /// `< file`, `> file`, `2>> file`...
+    File { fd: usize, path: String, append: bool },
+    /// `2>&1`
+    Duplicate { fd: usize, target: usize },
+}
+
+impl fmt::Display for Redirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Redirection::File { fd: 0, path, .. } => write!(f, "< {}", path),
+            Redirection::File { fd, path, append } => {
+                let fd = if *fd == 1 { String::new() } else { fd.to_string() };
+                write!(f, "{}{} {}", fd, if *append { ">>" } else { ">" }, path)
+            }
+            Redirection::Duplicate { fd, target } => write!(f, "{}>&{}", fd, target),
+        }
+    }
+}
+
+/// Splits the words given to `run` or `set args` into the arguments of the program and the
+/// redirections of its standard streams.
+pub fn parse_run_args(words: &[String]) -> Result<(Vec<String>, Vec<Redirection>), String> {
+    let mut args = Vec::new();
+    let mut redirections = Vec::new();
+    let mut words = words.iter();
+    while let Some(word) = words.next() {
+        // The stream number and operator, e.g. `2>>`, followed by a path or `&1` in the same word
+        // or the next one
+        let digits = word.len() - word.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let (fd, rest) = word.split_at(digits);
+        let (operator, rest) = match ["&>", ">>", ">", "<"].iter().find(|op| rest.starts_with(*op)) {
+            Some(op) if digits == 0 || *op != "&>" => rest.split_at(op.len()),
+            _ => {
+                args.push(word.clone());
+                continue;
+            }
+        };
+        let default_fd = if operator == "<" { 0 } else { 1 };
+        let fd = if fd.is_empty() { default_fd } else { fd.parse().map_err(|_| format!("Bad file descriptor \"{}\".", fd))? };
+        if fd > 2 {
+            return Err(format!("Only standard input, output and error can be redirected, not {}.", fd));
+        }
+        if let Some(target) = rest.strip_prefix('&').filter(|_| operator == ">") {
+            let target = target.parse().ok().filter(|&target: &usize| target <= 2);
+            let target = target.ok_or_else(|| format!("Bad redirection \"{}\".", word))?;
+            redirections.push(Redirection::Duplicate { fd, target });
+            continue;
+        }
+        let path = match rest {
+            "" => words.next().ok_or_else(|| format!("Missing file name after \"{}\".", word))?.clone(),
+            path => path.to_string(),
+        };
+        let append = operator == ">>";
+        redirections.push(Redirection::File { fd, path: path.clone(), append });
+        if operator == "&>" {
+            redirections.push(Redirection::Duplicate { fd: 2, target: 1 });
+        }
+    }
+    Ok((args, redirections))
+}
+
+/// Where and how the inferior runs: set with `run < in > out`, `set environment`, `set cwd` and
+/// `tty`, and kept for every run after that.
+#[derive(Clone)]
+pub struct RunEnvironment {
+    pub redirections: Vec<Redirection>,
+    pub environment: Vec<(String, String)>,
+    pub cwd: Option<String>,
+    pub tty: Option<String>,
+}
+
+impl Default for RunEnvironment {
+    fn default() -> RunEnvironment {
+        RunEnvironment {
+            redirections: Vec::new(),
+            environment: std::env::vars().collect(),
+            cwd: None,
+            tty: None,
+        }
+    }
+}
+
+impl RunEnvironment {
+    pub fn get_var(&self, name: &str) -> Option<&str> {
+        self.environment.iter().find(|(var, _)| var == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        match self.environment.iter_mut().find(|(var, _)| var == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.environment.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Removes `name` from the environment, or every variable if `name` is None.
+    pub fn unset_var(&mut self, name: Option<&str>) {
+        self.environment.retain(|(var, _)| name.is_some_and(|name| var != name));
+    }
+
+    /// Opens the files the standard streams of the inferior go to: the tty, then the
+    /// redirections in order. None means the stream is inherited from the debugger.
+    fn open_streams(&self) -> Result<[Option<File>; 3], String> {
+        let mut streams: [Option<File>; 3] = [None, None, None];
+        if let Some(tty) = &self.tty {
+            let file = OpenOptions::new().read(true).write(true).open(tty).map_err(|err| format!("{}: {}", tty, err))?;
+            for stream in streams.iter_mut() {
+                *stream = Some(file.try_clone().map_err(|err| err.to_string())?);
+            }
+        }
+        for redirection in &self.redirections {
+            match redirection {
+                Redirection::File { fd, path, append } => {
+                    let mut options = OpenOptions::new();
+                    if *fd == 0 {
+                        options.read(true);
+                    } else {
+                        options.write(true).create(true).append(*append).truncate(!*append);
+                    }
+                    let path = match &self.cwd {
+                        Some(cwd) => Path::new(cwd).join(path),
+                        None => PathBuf::from(path),
+                    };
+                    streams[*fd] = Some(options.open(&path).map_err(|err| format!("{}: {}", path.display(), err))?);
+                }
+                Redirection::Duplicate { fd, target } => {
+                    let file = match &streams[*target] {
+                        Some(file) => file.try_clone(),
+                        // Still the debugger's own stream
+                        None => unsafe { BorrowedFd::borrow_raw(*target as i32) }.try_clone_to_owned().map(File::from),
+                    };
+                    streams[*fd] = Some(file.map_err(|err| err.to_string())?);
+                }
+            }
+        }
+        Ok(streams)
+    }
 }
 
 pub struct Inferior {
-    child: Child,
+    pid: Pid,
+    // When set, the inferior is resumed with PTRACE_SYSCALL and its system calls are recorded or
+    // replayed
+    syscall_log: Option<Rc<RefCell<SyscallLog>>>,
+    // System calls to stop at, one list per catchpoint, where an empty list catches them all
+    catch_syscalls: Vec<Vec<u64>>,
+    trace_syscalls: bool,
+    // The system call the inferior is in, with its arguments
+    current_syscall: Cell<Option<(u64, [u64; 6])>>,
+    // Pages of memory read since the inferior last stopped, by address
+    page_cache: RefCell<HashMap<usize, Vec<u8>>>,
+    // A signal the inferior stopped with, to be delivered when it is resumed
+    pending_signal: Cell<Option<signal::Signal>>,
 }
 
+/// Syscall stops are reported as SIGTRAP | 0x80, to tell them apart from breakpoints.
+const OPTIONS: ptrace::Options = ptrace::Options::PTRACE_O_TRACESYSGOOD;
+
 impl Inferior {
-    /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
-    /// an error is encountered.
-    pub fn new(target: &str, args: &Vec<String>) -> Option<Inferior> {
-        // TODO: implement me!
+    /// Attempts to start a new inferior process with `args`, in the environment described by
+    /// `run_env`. Returns an error message if the process could not be started.
+    pub fn new(target: &str, args: &[String], run_env: &RunEnvironment) -> Result<Inferior, String> {
         use std::process::Command;
         use std::os::unix::process::CommandExt;
 
+        // A relative target would otherwise be looked up from `set cwd`, or in $PATH
+        let target = std::fs::canonicalize(target).map_err(|err| format!("{}: {}", target, err))?;
         let mut cmd = Command::new(target);
+        cmd.args(args).env_clear().envs(run_env.environment.iter().map(|(name, value)| (name, value)));
+        if let Some(cwd) = &run_env.cwd {
+            cmd.current_dir(cwd);
+        }
+        let [stdin, stdout, stderr] = run_env.open_streams()?;
+        if let Some(stdin) = stdin {
+            cmd.stdin(stdin);
+        }
+        if let Some(stdout) = stdout {
+            cmd.stdout(stdout);
+        }
+        if let Some(stderr) = stderr {
+            cmd.stderr(stderr);
+        }
+        let new_session = run_env.tty.is_some();
 
         unsafe {
-            cmd.args(args)
-            .pre_exec(|| child_traceme());
+            cmd.pre_exec(move || {
+                if new_session {
+                    child_set_tty()?;
+                }
+                child_traceme()
+            });
         }
-        
-        let child = cmd.spawn().ok()?;
-        let inferior = Inferior { child };
 
-        match inferior.wait(Some(WaitPidFlag::WSTOPPED)).ok()? {
-            Status::Stopped(_, _) => Some(inferior),
-            _ => None,
+        let child = cmd.spawn().map_err(|err| err.to_string())?;
+        let inferior = Inferior::from_pid(Pid::from_raw(child.id() as i32));
+
+        match inferior.wait(Some(WaitPidFlag::WSTOPPED)).map_err(|err| err.to_string())? {
+            Status::Stopped(_, _) => {
+                ptrace::setoptions(inferior.pid(), OPTIONS).map_err(|err| err.to_string())?;
+                Ok(inferior)
+            }
+            _ => Err("the program exited during startup".to_string()),
         }
+    }
 
+    fn from_pid(pid: Pid) -> Inferior {
+        Inferior {
+            pid,
+            syscall_log: None,
+            catch_syscalls: Vec::new(),
+            trace_syscalls: false,
+            current_syscall: Cell::new(None),
+            page_cache: RefCell::new(HashMap::new()),
+            pending_signal: Cell::new(None),
+        }
     }
 
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
     }
 
     /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
     /// after the waitpid call.
+    /// System call stops are handled here, and resumed from unless the call is caught.
     pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
-            WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
-            WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
-            WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
-                Status::Stopped(signal, regs.rip as usize)
-            },
-            other => panic!("waitpid returned unexpected status: {:?}", other),
-        })
+        loop {
+            return Ok(match waitpid(self.pid(), options)? {
+                WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
+                WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
+                WaitStatus::Stopped(_pid, signal) => {
+                    let regs = ptrace::getregs(self.pid())?;
+                    Status::Stopped(signal, regs.rip as usize)
+                },
+                WaitStatus::PtraceSyscall(_pid) => {
+                    if let Some(status) = self.syscall_stop()? {
+                        return Ok(status);
+                    }
+                    self.resume(ptrace::syscall, None)?;
+                    continue;
+                },
+                WaitStatus::PtraceEvent(_pid, signal, _event) => {
+                    let regs = ptrace::getregs(self.pid())?;
+                    Status::Stopped(signal, regs.rip as usize)
+                },
+                // Only reported with options this debugger does not use (WNOHANG, WCONTINUED)
+                WaitStatus::Continued(_) | WaitStatus::StillAlive => return Err(nix::Error::EINVAL),
+            });
+        }
     }
 
-    pub fn wakeup_wait(&self, bp: &HashMap<usize, crate::debugger::BreakPoint>) -> Result<Status, nix::Error> {
-        let mut regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
+    /// Starts recording or replaying the inferior's system calls. Must be called before it has
+    /// run any code.
+    pub fn set_syscall_log(&mut self, log: Rc<RefCell<SyscallLog>>) -> Result<(), nix::Error> {
+        self.disable_vdso()?;
+        self.syscall_log = Some(log);
+        Ok(())
+    }
 
-        // for SIGTRAP (breakpoint)
-        if let Some(bp) = bp.get(&(rip - 1)) {
-            if let Ok(_) = self.write_byte(rip - 1, bp.origin_byte) {
-                regs.rip = (rip - 1) as u64;
-                ptrace::setregs(self.pid(), regs)?;
-                ptrace::step(self.pid(), None)?;
+    pub fn syscall_log(&self) -> Option<&Rc<RefCell<SyscallLog>>> {
+        self.syscall_log.as_ref()
+    }
 
-                match self.wait(None)? {
-                    Status::Exited(ecode) => return Ok(Status::Exited(ecode)),
-                    Status::Signaled(signal) => return Ok(Status::Signaled(signal)),
-                    Status::Stopped(_, _) => { self.write_byte(rip - 1, 0xcc).unwrap(); }
+    /// Hides the vDSO from the program by renaming its entry in the auxiliary vector on the
+    /// stack, so that the C library makes real system calls for `time`, `clock_gettime`, etc.
+    fn disable_vdso(&self) -> Result<(), nix::Error> {
+        let read = |addr: usize| ptrace::read(self.pid(), addr as ptrace::AddressType).map(|w| w as u64);
+        // The stack holds argc, then argv and envp each followed by NULL, then the auxv
+        let mut addr = ptrace::getregs(self.pid())?.rsp as usize;
+        addr += (read(addr)? as usize + 2) * size_of::<u64>();
+        while read(addr)? != 0 {
+            addr += size_of::<u64>();
+        }
+        addr += size_of::<u64>();
+        loop {
+            match read(addr)? {
+                0 => return Ok(()),
+                nix::libc::AT_SYSINFO_EHDR => {
+                    self.write_memory(addr, &nix::libc::AT_IGNORE.to_le_bytes())?;
                 }
+                _ => {}
             }
+            addr += 2 * size_of::<u64>();
         }
+    }
+
+    /// Sets the system calls to stop at, one list per catchpoint (empty for all of them), and
+    /// whether to print every system call.
+    pub fn set_syscall_tracing(&mut self, catchpoints: &[Vec<u64>], trace: bool) {
+        self.catch_syscalls = catchpoints.to_vec();
+        self.trace_syscalls = trace;
+    }
 
-        ptrace::cont(self.pid(), None)?;
+    fn traces_syscalls(&self) -> bool {
+        self.syscall_log.is_some() || !self.catch_syscalls.is_empty() || self.trace_syscalls
+    }
+
+    /// Handles a stop at the entry or exit of a system call: passes it to the log, prints it if
+    /// tracing, and returns the status to report if it is caught.
+    fn syscall_stop(&self) -> Result<Option<Status>, nix::Error> {
+        let info = self.syscall_info()?;
+        let (number, returning) = match info.op {
+            nix::libc::PTRACE_SYSCALL_INFO_ENTRY => {
+                let entry = unsafe { info.u.entry };
+                self.current_syscall.set(Some((entry.nr, entry.args)));
+                if let Some(log) = self.syscall_log.as_ref() {
+                    log.borrow_mut().on_entry(self, entry.nr, entry.args)?;
+                }
+                // These never return
+                if self.trace_syscalls && matches!(syscalls::name(entry.nr), Some("exit" | "exit_group")) {
+                    println!("{} = ?", syscalls::format_call(self, entry.nr, &entry.args, None));
+                }
+                (entry.nr, false)
+            }
+            nix::libc::PTRACE_SYSCALL_INFO_EXIT => {
+                let exit = unsafe { info.u.exit };
+                let (number, args) = match self.current_syscall.take() {
+                    Some(current) => current,
+                    None => return Ok(None),
+                };
+                if let Some(log) = self.syscall_log.as_ref() {
+                    log.borrow_mut().on_exit(self, exit.sval)?;
+                }
+                if self.trace_syscalls {
+                    // The result may have been replaced by a replayed one
+                    let result = ptrace::getregs(self.pid())?.rax as i64;
+                    println!("{}", syscalls::format_call(self, number, &args, Some(result)));
+                }
+                (number, true)
+            }
+            _ => return Ok(None),
+        };
+        let caught = self
+            .catch_syscalls
+            .iter()
+            .any(|numbers| numbers.is_empty() || numbers.contains(&number));
+        let rip = info.instruction_pointer as usize;
+        Ok(Some(Status::Syscall(number, returning, rip)).filter(|_| caught))
+    }
+
+    fn syscall_info(&self) -> Result<nix::libc::ptrace_syscall_info, nix::Error> {
+        let mut info = std::mem::MaybeUninit::<nix::libc::ptrace_syscall_info>::zeroed();
+        let res = unsafe {
+            nix::libc::ptrace(
+                nix::libc::PTRACE_GET_SYSCALL_INFO,
+                self.pid().as_raw(),
+                size_of::<nix::libc::ptrace_syscall_info>(),
+                info.as_mut_ptr(),
+            )
+        };
+        nix::errno::Errno::result(res)?;
+        Ok(unsafe { info.assume_init() })
+    }
+
+    /// Resumes the inferior with the ptrace `request` (cont, step or syscall), delivering
+    /// `signal` to it. Its memory may change from here on, so cached pages are dropped.
+    fn resume(
+        &self,
+        request: fn(Pid, Option<signal::Signal>) -> nix::Result<()>,
+        signal: Option<signal::Signal>,
+    ) -> nix::Result<()> {
+        self.page_cache.borrow_mut().clear();
+        request(self.pid(), signal)
+    }
+
+    /// Makes the inferior receive `signal` when it is next resumed, or not if None.
+    pub fn set_pending_signal(&self, signal: Option<signal::Signal>) {
+        self.pending_signal.set(signal);
+    }
+
+    /// Resumes the inferior, stopping at system calls if they are traced.
+    fn cont(&self) -> Result<(), nix::Error> {
+        if self.traces_syscalls() {
+            self.resume(ptrace::syscall, self.pending_signal.take())
+        } else {
+            self.resume(ptrace::cont, self.pending_signal.take())
+        }
+    }
+
+    /// Resumes the inferior until it stops again. If it is sitting on a breakpoint, the original
+    /// instruction is executed first. When the inferior stops on one of `bp`, the instruction
+    /// pointer is rewound to the breakpoint address.
+    pub fn wakeup_wait(&self, bp: &HashMap<usize, crate::debugger::BreakPoint>) -> Result<Status, nix::Error> {
+        match self.step_over_breakpoint(bp)? {
+            None | Some(Status::Stopped(signal::Signal::SIGTRAP, _)) => {}
+            Some(status) => return Ok(status),
+        }
+        self.cont()?;
+        self.wait_breakpoint(bp)
+    }
+
+    /// Resumes the inferior like `wakeup_wait`, but stops it with SIGSTOP if it is still running
+    /// at `deadline`, so that the profiler can sample where it is.
+    pub fn wakeup_wait_until(
+        &self,
+        bp: &HashMap<usize, crate::debugger::BreakPoint>,
+        deadline: Instant,
+    ) -> Result<Status, nix::Error> {
+        match self.step_over_breakpoint(bp)? {
+            None | Some(Status::Stopped(signal::Signal::SIGTRAP, _)) => {}
+            Some(status) => return Ok(status),
+        }
+        self.cont()?;
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        // Only stop the inferior if it is still running, rather than leave a SIGSTOP pending
+        // after a breakpoint
+        if !self.has_changed_state()? {
+            signal::kill(self.pid(), signal::Signal::SIGSTOP)?;
+        }
+        self.wait_breakpoint(bp)
+    }
+
+    /// Returns whether the inferior has stopped or exited since it was resumed, without waiting
+    /// for it.
+    fn has_changed_state(&self) -> Result<bool, nix::Error> {
+        let mut info: nix::libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let flags = nix::libc::WEXITED | nix::libc::WSTOPPED | nix::libc::WNOHANG | nix::libc::WNOWAIT;
+        let result = unsafe {
+            nix::libc::waitid(nix::libc::P_PID, self.pid().as_raw() as nix::libc::id_t, &mut info, flags)
+        };
+        nix::errno::Errno::result(result)?;
+        Ok(unsafe { info.si_pid() } != 0)
+    }
+
+    /// Executes a single instruction, stepping over a breakpoint at the current address if
+    /// needed.
+    pub fn step_instruction(&self, bp: &HashMap<usize, crate::debugger::BreakPoint>) -> Result<Status, nix::Error> {
+        if let Some(status) = self.step_over_breakpoint(bp)? {
+            return Ok(status);
+        }
+        let rip = ptrace::getregs(self.pid())?.rip as usize;
+        // Single-stepping would run a system call without stopping at its entry and exit
+        let stops = if !self.traces_syscalls() {
+            0
+        } else if self.syscall_info()?.op == nix::libc::PTRACE_SYSCALL_INFO_ENTRY {
+            1
+        } else if self.read_memory(rip, SYSCALL.len())? == SYSCALL {
+            2
+        } else {
+            0
+        };
+        if stops > 0 {
+            for _ in 0..stops {
+                self.resume(ptrace::syscall, self.pending_signal.take())?;
+                match waitpid(self.pid(), None)? {
+                    WaitStatus::PtraceSyscall(_) => {
+                        if let Some(status) = self.syscall_stop()? {
+                            return Ok(status);
+                        }
+                    }
+                    WaitStatus::Exited(_, exit_code) => return Ok(Status::Exited(exit_code)),
+                    WaitStatus::Signaled(_, signal, _) => return Ok(Status::Signaled(signal)),
+                    WaitStatus::Stopped(_, signal) => return Ok(Status::Stopped(signal, rip)),
+                    _ => {}
+                }
+            }
+            let rip = ptrace::getregs(self.pid())?.rip as usize;
+            return Ok(Status::Stopped(signal::Signal::SIGTRAP, rip));
+        }
+        self.resume(ptrace::step, self.pending_signal.take())?;
         self.wait(None)
     }
 
+    /// If the inferior is stopped on a breakpoint, temporarily restores the original byte,
+    /// executes that one instruction and returns the resulting status. Returns None if there is
+    /// no breakpoint at the current address.
+    fn step_over_breakpoint(&self, bp: &HashMap<usize, crate::debugger::BreakPoint>) -> Result<Option<Status>, nix::Error> {
+        let rip = ptrace::getregs(self.pid())?.rip as usize;
+        let bp = match bp.get(&rip) {
+            Some(bp) => bp,
+            None => return Ok(None),
+        };
+        self.write_byte(rip, bp.origin_byte)?;
+        self.resume(ptrace::step, self.pending_signal.take())?;
+        let status = self.wait(None)?;
+        if let Status::Stopped(_, _) = status {
+            self.write_byte(rip, 0xcc)?;
+        }
+        Ok(Some(status))
+    }
+
+    fn wait_breakpoint(&self, bp: &HashMap<usize, crate::debugger::BreakPoint>) -> Result<Status, nix::Error> {
+        match self.wait(None)? {
+            Status::Stopped(signal::Signal::SIGTRAP, rip) if bp.contains_key(&(rip - 1)) => {
+                let mut regs = ptrace::getregs(self.pid())?;
+                regs.rip = (rip - 1) as u64;
+                ptrace::setregs(self.pid(), regs)?;
+                Ok(Status::Stopped(signal::Signal::SIGTRAP, rip - 1))
+            }
+            status => Ok(status),
+        }
+    }
+
     pub fn kill(&mut self) -> Result<(), nix::Error> {
-        ptrace::kill(self.pid())
+        ptrace::kill(self.pid())?;
+        self.wait(None)?;
+        Ok(())
     }
 
-    pub fn backtrace(&self, debug_data: &Option<DwarfData>) -> Result<(), nix::Error> {
+    /// Forks the stopped inferior by making it execute a `fork` system call in place of the
+    /// current instruction. Returns the child, which is traced and stopped at the same point with
+    /// the same registers and memory.
+    pub fn checkpoint(&self) -> Result<Inferior, nix::Error> {
         let regs = ptrace::getregs(self.pid())?;
-        let mut rip = regs.rip as usize;
-        let mut rbp = regs.rbp as usize;
-        let debug_ref = debug_data.as_ref().unwrap();
+        let rip = regs.rip as usize;
+        let saved = self.read_memory(rip, SYSCALL.len())?;
+        self.write_memory(rip, &SYSCALL)?;
+        let mut fork_regs = regs;
+        fork_regs.rax = nix::libc::SYS_fork as u64;
+        // Don't let the kernel restart a system call the inferior was stopped in
+        fork_regs.orig_rax = u64::MAX;
+        ptrace::setregs(self.pid(), fork_regs)?;
+        ptrace::setoptions(self.pid(), OPTIONS | ptrace::Options::PTRACE_O_TRACEFORK)?;
 
+        let child = self.finish_fork();
+        // Put the inferior back the way it was, whether or not the fork worked
+        ptrace::setoptions(self.pid(), OPTIONS)?;
+        self.write_memory(rip, &saved)?;
+        ptrace::setregs(self.pid(), regs)?;
 
+        let mut child = Inferior::from_pid(child?);
+        // The child goes on recording or replaying from the same log when it is run
+        child.syscall_log = self.syscall_log.clone();
+        // The child's memory is a copy of the inferior's, syscall instruction included
+        ptrace::setoptions(child.pid(), OPTIONS)?;
+        child.write_memory(rip, &saved)?;
+        ptrace::setregs(child.pid(), regs)?;
+        Ok(child)
+    }
+
+    /// Executes the `fork` set up by `checkpoint` and returns the pid of the child, once it has
+    /// stopped.
+    fn finish_fork(&self) -> Result<Pid, nix::Error> {
         loop {
-            let line = debug_ref
-            .get_line_from_addr(rip)
-            .expect("failed to get line info");
+            self.resume(ptrace::step, None)?;
+            match waitpid(self.pid(), None)? {
+                WaitStatus::PtraceEvent(_, _, event) if event == ptrace::Event::PTRACE_EVENT_FORK as i32 => break,
+                // A pending signal, e.g. SIGCHLD from a copy of a checkpoint exiting, is
+                // discarded before the system call runs
+                WaitStatus::Stopped(_, signal) if signal != signal::Signal::SIGTRAP => continue,
+                _ => return Err(nix::Error::ECHILD),
+            }
+        }
+        let child = Pid::from_raw(ptrace::getevent(self.pid())? as i32);
+        // Finish the system call in the parent
+        self.resume(ptrace::step, None)?;
+        waitpid(self.pid(), None)?;
+        // The child is traced too, and starts out stopped by SIGSTOP
+        match waitpid(child, None)? {
+            WaitStatus::Stopped(_, _) => Ok(child),
+            _ => Err(nix::Error::ECHILD),
+        }
+    }
+
+    /// Calls the function at `addr` with `args`, following the x86-64 System V calling
+    /// convention, and runs the inferior until it returns to `return_addr`, where a breakpoint is
+    /// placed for the duration of the call. If `return_size` is non-zero, the function returns a
+    /// struct of that size in memory, which is set aside on the stack. The registers are restored
+    /// afterwards, unless the inferior is gone.
+    pub fn call_function(
+        &self,
+        addr: usize,
+        args: &[CallArg],
+        return_size: usize,
+        return_addr: usize,
+    ) -> Result<CallOutcome, nix::Error> {
+        let regs = ptrace::getregs(self.pid())?;
+        let fpregs = self.get_fpregs()?;
+
+        // Stay clear of the red zone below rsp, which the current function may be using
+        let return_buffer = (regs.rsp as usize - RED_ZONE - return_size) & !0xf;
+        let mut integers = Vec::new();
+        if return_size > 0 {
+            integers.push(return_buffer as u64);
+        }
+        let mut sse = Vec::new();
+        let mut stack = Vec::new();
+        for arg in args {
+            match *arg {
+                CallArg::Integer(value) if integers.len() < 6 => integers.push(value),
+                CallArg::Sse(value) if sse.len() < 8 => sse.push(value),
+                CallArg::Integer(value) | CallArg::Sse(value) => stack.push(value),
+            }
+        }
+        // The stack must be 16-byte aligned at the call, i.e. right before the return address is
+        // pushed
+        let mut sp = (return_buffer - stack.len() * size_of::<u64>()) & !0xf;
+        for (i, value) in stack.iter().enumerate() {
+            self.write_memory(sp + i * size_of::<u64>(), &value.to_le_bytes())?;
+        }
+        sp -= size_of::<u64>();
+        self.write_memory(sp, &(return_addr as u64).to_le_bytes())?;
+
+        let mut call_regs = regs;
+        let registers = [
+            &mut call_regs.rdi,
+            &mut call_regs.rsi,
+            &mut call_regs.rdx,
+            &mut call_regs.rcx,
+            &mut call_regs.r8,
+            &mut call_regs.r9,
+        ];
+        for (register, value) in registers.into_iter().zip(&integers) {
+            *register = *value;
+        }
+        let mut call_fpregs = fpregs;
+        for (i, value) in sse.iter().enumerate() {
+            call_fpregs.xmm_space[4 * i] = *value as u32;
+            call_fpregs.xmm_space[4 * i + 1] = (*value >> 32) as u32;
+        }
+        // Variadic functions expect the number of vector registers used in al
+        call_regs.rax = sse.len() as u64;
+        call_regs.rip = addr as u64;
+        call_regs.rsp = sp as u64;
+        // Don't let the kernel restart a system call the inferior was stopped in
+        call_regs.orig_rax = u64::MAX;
+
+        let saved = self.write_byte(return_addr, 0xcc)?;
+        ptrace::setregs(self.pid(), call_regs)?;
+        self.set_fpregs(&call_fpregs)?;
+        self.resume(ptrace::cont, None)?;
+        let outcome = match self.wait(None)? {
+            Status::Stopped(signal::Signal::SIGTRAP, rip)
+                // A recursive call reaching the return address doesn't count
+                if rip == return_addr + 1
+                    && ptrace::getregs(self.pid())?.rsp as usize == sp + size_of::<u64>() =>
+            {
+                let result_regs = ptrace::getregs(self.pid())?;
+                let result_fpregs = self.get_fpregs()?;
+                let xmm = |i: usize| {
+                    result_fpregs.xmm_space[4 * i] as u64
+                        | (result_fpregs.xmm_space[4 * i + 1] as u64) << 32
+                };
+                CallOutcome::Returned(CallReturn {
+                    integer: [result_regs.rax, result_regs.rdx],
+                    sse: [xmm(0), xmm(1)],
+                    memory: self.read_memory(return_buffer, return_size)?,
+                })
+            }
+            status @ (Status::Exited(_) | Status::Signaled(_)) => {
+                return Ok(CallOutcome::Interrupted(status))
+            }
+            status => CallOutcome::Interrupted(status),
+        };
+        self.write_byte(return_addr, saved)?;
+        ptrace::setregs(self.pid(), regs)?;
+        self.set_fpregs(&fpregs)?;
+        Ok(outcome)
+    }
+
+    /// Returns the lines of a backtrace, one per frame including inlined ones.
+    pub fn backtrace(
+        &self,
+        debug_data: &Option<DwarfData>,
+        libraries: &[SharedLibrary],
+    ) -> Result<Vec<String>, nix::Error> {
+        let mut lines = Vec::new();
+        for (i, pc) in self.stack_frames(debug_data, libraries)?.into_iter().enumerate() {
+            // Return addresses point after the call, which may already be on the next line
+            let lookup = if i == 0 { pc } else { pc - 1 };
+            let object = shared_libraries::find_object(lookup, debug_data, libraries);
+            let (debug_ref, base) = match object {
+                Some(object) => object,
+                None => {
+                    lines.push(format!("{:#x} in ??", pc));
+                    continue;
+                }
+            };
+            let frames = debug_ref.get_frames_from_addr(lookup - base);
+            if frames.is_empty() {
+                // Library code usually has no line info, only the symbol table
+                let library = libraries.iter().find(|lib| lib.contains(lookup));
+                match (debug_ref.get_symbol_at(lookup - base), library) {
+                    (Some(sym), Some(lib)) => lines.push(format!("{} (from {})", sym.describe(pc - base), lib.name)),
+                    (Some(sym), None) => lines.push(format!("{} ({:#x})", sym.describe(pc - base), pc)),
+                    (None, _) => lines.push(format!("{:#x} in ??", pc)),
+                }
+            }
+            // Functions inlined at pc are shown as frames of their own
+            for (func, line) in frames {
+                match line {
+                    Some(line) => lines.push(format!("{} ({}:{})", func, line.file, line.number)),
+                    None => lines.push(format!("{} ({:#x})", func, pc)),
+                }
+            }
+        }
+
+        Ok(lines)
+    }
+
+    /// Returns the pc of every physical frame on the stack, innermost first, up to `main`. Uses
+    /// the call frame information when there is some for a pc, and the frame pointer chain
+    /// otherwise.
+    pub fn stack_frames(
+        &self,
+        debug_data: &Option<DwarfData>,
+        libraries: &[SharedLibrary],
+    ) -> Result<Vec<usize>, nix::Error> {
+        let regs = ptrace::getregs(self.pid())?;
+        let mut pc = regs.rip as usize;
+        let mut rsp = regs.rsp as usize;
+        let mut rbp = regs.rbp as usize;
+        let mut frames = Vec::new();
 
-            let func = debug_ref
-            .get_function_from_addr(rip)
-            .expect("failed to get func info");
+        while frames.len() < 256 {
+            frames.push(pc);
+            let lookup = if frames.len() == 1 { pc } else { pc - 1 };
+            let object = shared_libraries::find_object(lookup, debug_data, libraries);
+            let outermost = object.is_some_and(|(dwarf, base)| {
+                let name = match dwarf.get_function_at(lookup - base) {
+                    Some(func) => Some(func.name.as_str()),
+                    None => dwarf.get_symbol_at(lookup - base).map(|sym| sym.name.as_str()),
+                };
+                // When stopped before main, the entry point is the outermost frame
+                (base == 0 && name == Some("main")) || name == Some("_start")
+            });
+            if outermost {
+                break;
+            }
 
-            println!("{} ({}:{})", func, line.file, line.number);
+            let row = object.and_then(|(dwarf, base)| dwarf.unwind_row(lookup - base));
+            let (cfa, saved_rbp) = match row {
+                Some(row) => {
+                    let base = match row.cfa_register {
+                        6 => rbp,
+                        7 => rsp,
+                        register if frames.len() == 1 => {
+                            match crate::registers::get_dwarf_register(&regs, None, register) {
+                                Some(value) => value as usize,
+                                None => break,
+                            }
+                        }
+                        _ => break,
+                    };
+                    let cfa = (base as i64 + row.cfa_offset) as usize;
+                    let saved_rbp = row
+                        .saved_registers
+                        .iter()
+                        .find(|(register, _)| *register == 6)
+                        .map(|(_, offset)| (cfa as i64 + offset) as usize);
+                    (cfa, saved_rbp)
+                }
+                None => (rbp + 16, Some(rbp)),
+            };
 
-            if func.contains("main") {
+            let return_addr = match cfa.checked_sub(8).and_then(|addr| self.read_word(addr).ok()) {
+                Some(addr) => addr as usize,
+                None => break,
+            };
+            if let Some(saved_rbp) = saved_rbp {
+                rbp = match self.read_word(saved_rbp) {
+                    Ok(value) => value as usize,
+                    Err(_) => break,
+                };
+            }
+            if return_addr == 0 {
                 break;
             }
+            pc = return_addr;
+            rsp = cfa;
+        }
+
+        Ok(frames)
+    }
+
+    pub fn get_regs(&self) -> Result<nix::libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(self.pid())
+    }
+
+    pub fn get_fpregs(&self) -> Result<nix::libc::user_fpregs_struct, nix::Error> {
+        let mut fpregs = std::mem::MaybeUninit::<nix::libc::user_fpregs_struct>::uninit();
+        let res = unsafe {
+            nix::libc::ptrace(
+                nix::libc::PTRACE_GETFPREGS,
+                self.pid().as_raw(),
+                std::ptr::null_mut::<std::ffi::c_void>(),
+                fpregs.as_mut_ptr(),
+            )
+        };
+        nix::errno::Errno::result(res)?;
+        Ok(unsafe { fpregs.assume_init() })
+    }
 
-            rip = ptrace::read(self.pid(), (rbp + 8) as ptrace::AddressType)? as usize;
-            rbp = ptrace::read(self.pid(), rbp as ptrace::AddressType)? as usize;
+    pub fn set_fpregs(&self, fpregs: &nix::libc::user_fpregs_struct) -> Result<(), nix::Error> {
+        let res = unsafe {
+            nix::libc::ptrace(
+                nix::libc::PTRACE_SETFPREGS,
+                self.pid().as_raw(),
+                std::ptr::null_mut::<std::ffi::c_void>(),
+                fpregs as *const nix::libc::user_fpregs_struct,
+            )
+        };
+        nix::errno::Errno::result(res)?;
+        Ok(())
+    }
+
+    /// Reads `len` bytes of the inferior's memory starting at `addr`. Memory is read a page at a
+    /// time and cached until the inferior is resumed.
+    pub fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let end = addr.checked_add(len).ok_or(nix::Error::EFAULT)?;
+        let mut cache = self.page_cache.borrow_mut();
+        if cache.len() > MAX_CACHED_PAGES {
+            cache.clear();
         }
+        let mut bytes = Vec::with_capacity(len);
+        let mut page = addr & !(PAGE_SIZE - 1);
+        while page < end {
+            let data = match cache.entry(page) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(self.read_page(page)?),
+            };
+            bytes.extend_from_slice(&data[addr.max(page) - page..end.min(page + PAGE_SIZE) - page]);
+            page += PAGE_SIZE;
+        }
+        Ok(bytes)
+    }
 
+    /// Reads the NUL-terminated string at `addr`, up to `limit` bytes. Returns its bytes, without
+    /// the terminator, and whether the terminator was found within `limit` bytes.
+    pub fn read_c_string(&self, addr: usize, limit: usize) -> Result<(Vec<u8>, bool), nix::Error> {
+        let mut bytes = Vec::new();
+        while bytes.len() <= limit {
+            let chunk = self.read_memory(addr + bytes.len(), size_of::<u64>())?;
+            if let Some(end) = chunk.iter().position(|&b| b == 0) {
+                bytes.extend_from_slice(&chunk[..end]);
+                let complete = bytes.len() <= limit;
+                bytes.truncate(limit);
+                return Ok((bytes, complete));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        bytes.truncate(limit);
+        Ok((bytes, false))
+    }
+
+    fn read_word(&self, addr: usize) -> Result<u64, nix::Error> {
+        let bytes = self.read_memory(addr, size_of::<u64>())?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Reads the page at `page` with process_vm_readv. Pages the inferior can't read itself
+    /// (e.g. mapped without PROT_READ) are read through /proc/<pid>/mem, and as a last resort one
+    /// word at a time with ptrace.
+    fn read_page(&self, page: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut data = vec![0u8; PAGE_SIZE];
+        let local = nix::libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut std::ffi::c_void,
+            iov_len: PAGE_SIZE,
+        };
+        let remote = nix::libc::iovec {
+            iov_base: page as *mut std::ffi::c_void,
+            iov_len: PAGE_SIZE,
+        };
+        let read = unsafe { nix::libc::process_vm_readv(self.pid().as_raw(), &local, 1, &remote, 1, 0) };
+        if read == PAGE_SIZE as isize {
+            return Ok(data);
+        }
+
+        let mem = std::fs::File::open(format!("/proc/{}/mem", self.pid()));
+        if let Ok(mem) = mem {
+            use std::os::unix::fs::FileExt;
+            if mem.read_exact_at(&mut data, page as u64).is_ok() {
+                return Ok(data);
+            }
+        }
+
+        for (i, chunk) in data.chunks_mut(size_of::<usize>()).enumerate() {
+            let word_addr = page + i * size_of::<usize>();
+            let word = ptrace::read(self.pid(), word_addr as ptrace::AddressType)? as u64;
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        Ok(data)
+    }
+
+    /// Writes `bytes` to the inferior's memory starting at `addr`, a word at a time. Words that
+    /// are only partially covered are read first, so that their other bytes are preserved.
+    pub fn write_memory(&self, addr: usize, bytes: &[u8]) -> Result<(), nix::Error> {
+        let end = addr + bytes.len();
+        let mut word_addr = align_addr_to_word(addr);
+        while word_addr < end {
+            let mut word = [0u8; size_of::<usize>()];
+            if word_addr < addr || word_addr + word.len() > end {
+                let orig = ptrace::read(self.pid(), word_addr as ptrace::AddressType)? as u64;
+                word = orig.to_le_bytes();
+            }
+            for (i, byte) in word.iter_mut().enumerate() {
+                if (addr..end).contains(&(word_addr + i)) {
+                    *byte = bytes[word_addr + i - addr];
+                }
+            }
+            unsafe {
+                ptrace::write(
+                    self.pid(),
+                    word_addr as ptrace::AddressType,
+                    u64::from_le_bytes(word) as *mut std::ffi::c_void,
+                )?
+            };
+            let page = word_addr & !(PAGE_SIZE - 1);
+            if let Some(data) = self.page_cache.borrow_mut().get_mut(&page) {
+                let offset = word_addr - page;
+                data[offset..offset + word.len()].copy_from_slice(&word);
+            }
+            word_addr += size_of::<usize>();
+        }
         Ok(())
     }
 
+    /// Writes a single byte and returns the one it replaced.
     pub fn write_byte(&self, addr: usize, val: u8) -> Result<u8, nix::Error> {
-        let aligned_addr = align_addr_to_word(addr);
-        let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> 8 * byte_offset) & 0xff;
-        let masked_word = word & !(0xff << 8 * byte_offset);
-        let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
-        unsafe { 
-            ptrace::write(
-                    self.pid(),
-                    aligned_addr as ptrace::AddressType,
-                    updated_word as *mut std::ffi::c_void,
-                )? 
-        };
-        Ok(orig_byte as u8)
+        let orig_byte = self.read_memory(addr, 1)?[0];
+        self.write_memory(addr, &[val])?;
+        Ok(orig_byte)
     }
 
 }
 
+/// The `syscall` instruction.
+const SYSCALL: [u8; 2] = [0x0f, 0x05];
+
+const PAGE_SIZE: usize = 4096;
+
+/// Pages cached at most, so that dumping large amounts of memory doesn't keep it all around.
+const MAX_CACHED_PAGES: usize = 1024;
+
+/// Size of the area below rsp that functions may use without adjusting rsp.
+const RED_ZONE: usize = 128;
+
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_run_args() {
+        let words: Vec<String> = "a <in.txt b > out.txt 2>&1".split(' ').map(str::to_string).collect();
+        let (args, redirections) = parse_run_args(&words).unwrap();
+        assert_eq!(args, vec!["a", "b"]);
+        assert_eq!(redirections, vec![
+            Redirection::File { fd: 0, path: "in.txt".to_string(), append: false },
+            Redirection::File { fd: 1, path: "out.txt".to_string(), append: false },
+            Redirection::Duplicate { fd: 2, target: 1 },
+        ]);
+        let words = vec!["2>>".to_string(), "log".to_string()];
+        assert_eq!(parse_run_args(&words).unwrap().1[0].to_string(), "2>> log");
+        assert!(parse_run_args(&["3>x".to_string()]).is_err());
+        assert!(parse_run_args(&[">".to_string()]).is_err());
+    }
+}
@@ -1,17 +1,42 @@
+mod completion;
+mod coverage;
 mod debugger;
 mod debugger_command;
 mod inferior;
 mod dwarf_data;
 mod gimli_wrapper;
+mod expr;
+mod procfs;
+mod profiler;
+mod registers;
+mod shared_libraries;
+mod record;
+mod syscalls;
+mod tui;
+mod settings;
 
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
 use std::env;
 
+/// Samples per second taken by `deet profile` unless `--hz` is given.
+const DEFAULT_PROFILE_HZ: u32 = 100;
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    // Only taken before the command and target, so that it is never one of the program's arguments
+    let index_cache = args.get(1).is_none_or(|arg| arg != "--no-index-cache");
+    if !index_cache {
+        args.remove(1);
+    }
+    if matches!(args.get(1).map(String::as_str), Some("profile") | Some("coverage")) {
+        return run_tool(&args, index_cache);
+    }
+    let tui = args.iter().position(|arg| arg == "--tui").map(|i| args.remove(i)).is_some();
     if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
+        println!("Usage: {} [--no-index-cache] [--tui] <target program>", args[0]);
+        println!("       {} [--no-index-cache] profile [--hz N] [-o FILE] <target program> [args...]", args[0]);
+        println!("       {} [--no-index-cache] coverage [-o FILE] <target program> [args...]", args[0]);
         std::process::exit(1);
     }
     let target = &args[1];
@@ -20,5 +45,50 @@ fn main() {
     // processes)
     unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
 
-    Debugger::new(target).run();
+    let mut debugger = Debugger::new(target, index_cache);
+    if tui {
+        debugger.queue_command("layout src");
+    }
+    debugger.run();
+}
+
+/// Runs `deet profile [--hz N] [-o FILE] <target program> [args...]` or
+/// `deet coverage [-o FILE] <target program> [args...]`, which run the program once to the end.
+fn run_tool(args: &[String], index_cache: bool) {
+    let profile = args[1] == "profile";
+    let usage = || -> ! {
+        let options = if profile { "[--hz N] [-o FILE]" } else { "[-o FILE]" };
+        println!("Usage: {} {} {} <target program> [args...]", args[0], args[1], options);
+        std::process::exit(1);
+    };
+    let mut hz = DEFAULT_PROFILE_HZ;
+    let mut output_path = None;
+    let mut rest = &args[2..];
+    loop {
+        match rest {
+            [option, value, ..] if option == "--hz" && profile => {
+                hz = value.parse().ok().filter(|&hz| hz > 0).unwrap_or_else(|| usage());
+            }
+            [option, value, ..] if option == "-o" => output_path = Some(value.clone()),
+            _ => break,
+        }
+        rest = &rest[2..];
+    }
+    let (target, target_args) = match rest.split_first() {
+        Some(split) => split,
+        None => usage(),
+    };
+    // Written in the current directory, as <program>.folded or <program>.info, by default
+    let output_path = output_path.unwrap_or_else(|| {
+        let name = std::path::Path::new(target).file_name().unwrap_or_default();
+        format!("{}.{}", name.to_string_lossy(), if profile { "folded" } else { "info" })
+    });
+
+    unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
+    let mut debugger = Debugger::new(target, index_cache);
+    if profile {
+        debugger.profile(target_args, hz, &output_path);
+    } else {
+        debugger.coverage(target_args, &output_path);
+    }
 }
@@ -0,0 +1,194 @@
+//! The inferior as the kernel shows it under `/proc/<pid>`: its command line and working
+//! directory, memory mappings, open files and auxiliary vector.
+
+use crate::syscalls;
+use nix::libc;
+use nix::unistd::Pid;
+use object::{Object, ObjectSection, ObjectSegment};
+use std::collections::HashMap;
+use std::fs;
+
+/// A line of `/proc/<pid>/maps`.
+pub struct Mapping {
+    pub start: usize,
+    pub end: usize,
+    pub perms: String,
+    pub offset: usize, // Offset in the mapped file
+    pub path: String, // The mapped file, a name like `[heap]`, or empty for anonymous memory
+}
+
+/// An open file descriptor, with its cursor and the flags it was opened with from
+/// `/proc/<pid>/fdinfo`.
+pub struct OpenFile {
+    pub fd: i32,
+    pub target: String,
+    pub pos: Option<u64>,
+    pub flags: Option<u32>,
+}
+
+/// How `info auxv` shows the value of an entry.
+pub enum AuxvFormat {
+    Hex,
+    Dec,
+    Str, // Address of a string in the inferior
+}
+
+/// Names, descriptions and formats of the auxiliary vector entries, as in gdb.
+const AUXV_ENTRIES: &[(u64, &str, &str, AuxvFormat)] = &[
+    (0, "AT_NULL", "End of vector", AuxvFormat::Hex),
+    (3, "AT_PHDR", "Program headers for program", AuxvFormat::Hex),
+    (4, "AT_PHENT", "Size of program header entry", AuxvFormat::Dec),
+    (5, "AT_PHNUM", "Number of program headers", AuxvFormat::Dec),
+    (6, "AT_PAGESZ", "System page size", AuxvFormat::Dec),
+    (7, "AT_BASE", "Base address of interpreter", AuxvFormat::Hex),
+    (8, "AT_FLAGS", "Flags", AuxvFormat::Hex),
+    (9, "AT_ENTRY", "Entry point of program", AuxvFormat::Hex),
+    (11, "AT_UID", "Real user ID", AuxvFormat::Dec),
+    (12, "AT_EUID", "Effective user ID", AuxvFormat::Dec),
+    (13, "AT_GID", "Real group ID", AuxvFormat::Dec),
+    (14, "AT_EGID", "Effective group ID", AuxvFormat::Dec),
+    (15, "AT_PLATFORM", "String identifying platform", AuxvFormat::Str),
+    (16, "AT_HWCAP", "Machine-dependent CPU capability hints", AuxvFormat::Hex),
+    (17, "AT_CLKTCK", "Frequency of times()", AuxvFormat::Dec),
+    (23, "AT_SECURE", "Boolean, was exec setuid-like?", AuxvFormat::Dec),
+    (24, "AT_BASE_PLATFORM", "String identifying base platform", AuxvFormat::Str),
+    (25, "AT_RANDOM", "Address of 16 random bytes", AuxvFormat::Hex),
+    (26, "AT_HWCAP2", "Extension of AT_HWCAP", AuxvFormat::Hex),
+    (27, "AT_RSEQ_FEATURE_SIZE", "rseq supported feature size", AuxvFormat::Dec),
+    (28, "AT_RSEQ_ALIGN", "rseq allocation alignment", AuxvFormat::Dec),
+    (31, "AT_EXECFN", "File name of executable", AuxvFormat::Str),
+    (33, "AT_SYSINFO_EHDR", "System-supplied DSO's ELF header", AuxvFormat::Hex),
+    (51, "AT_MINSIGSTKSZ", "Minimal stack size for signal delivery", AuxvFormat::Hex),
+];
+
+/// O_LARGEFILE as the kernel reports it, on every file opened by a 64-bit process.
+const O_LARGEFILE: u32 = 0o100000;
+
+/// Returns the arguments the inferior was started with.
+pub fn read_cmdline(pid: Pid) -> Option<Vec<String>> {
+    let data = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    Some(
+        data.split(|&b| b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).to_string())
+            .collect(),
+    )
+}
+
+/// Returns where the symbolic link `/proc/<pid>/<name>` (e.g. `cwd` or `exe`) points.
+pub fn read_link(pid: Pid, name: &str) -> Option<String> {
+    let target = fs::read_link(format!("/proc/{}/{}", pid, name)).ok()?;
+    Some(target.to_string_lossy().to_string())
+}
+
+pub fn read_maps(pid: Pid) -> Vec<Mapping> {
+    let text = fs::read_to_string(format!("/proc/{}/maps", pid)).unwrap_or_default();
+    text.lines().filter_map(parse_mapping).collect()
+}
+
+/// Parses a line of `/proc/<pid>/maps`, such as
+/// `00400000-00401000 r--p 00000000 fd:01 1234       /tmp/calls`.
+fn parse_mapping(line: &str) -> Option<Mapping> {
+    let mut fields = line.splitn(6, ' ');
+    let (start, end) = fields.next()?.split_once('-')?;
+    let perms = fields.next()?.to_string();
+    let offset = fields.next()?;
+    // Device and inode
+    fields.next()?;
+    fields.next()?;
+    Some(Mapping {
+        start: usize::from_str_radix(start, 16).ok()?,
+        end: usize::from_str_radix(end, 16).ok()?,
+        perms,
+        offset: usize::from_str_radix(offset, 16).ok()?,
+        // The path is padded into a column
+        path: fields.next().unwrap_or_default().trim_start().to_string(),
+    })
+}
+
+/// Returns the ELF sections of the object mapped from `path` that start in each of its
+/// `mappings`, by the start address of the mapping.
+pub fn mapped_sections(path: &str, mappings: &[&Mapping]) -> HashMap<usize, Vec<String>> {
+    let mut sections: HashMap<usize, Vec<String>> = HashMap::new();
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(_) => return sections,
+    };
+    let object = match object::File::parse(&*data) {
+        Ok(object) => object,
+        Err(_) => return sections,
+    };
+    // The mapping of the start of the file tells how far the object was moved from the
+    // addresses it was linked at
+    let first_segment = object.segments().find(|segment| segment.file_range().0 == 0);
+    let first_mapping = mappings.iter().filter(|mapping| mapping.offset == 0).map(|mapping| mapping.start).min();
+    let bias = match (first_segment, first_mapping) {
+        (Some(segment), Some(start)) => start.wrapping_sub(segment.address() as usize),
+        _ => return sections,
+    };
+    for section in object.sections() {
+        let name = section.name().unwrap_or_default();
+        if section.address() == 0 || section.size() == 0 || name.is_empty() {
+            continue;
+        }
+        let addr = (section.address() as usize).wrapping_add(bias);
+        if let Some(mapping) = mappings.iter().find(|mapping| mapping.start <= addr && addr < mapping.end) {
+            sections.entry(mapping.start).or_default().push(name.to_string());
+        }
+    }
+    sections
+}
+
+/// Returns the open file descriptors of the inferior, in order.
+pub fn read_fds(pid: Pid) -> Vec<OpenFile> {
+    let mut files: Vec<OpenFile> = fs::read_dir(format!("/proc/{}/fd", pid))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let fd: i32 = entry.file_name().to_str()?.parse().ok()?;
+            let target = fs::read_link(entry.path()).ok()?.to_string_lossy().to_string();
+            let info = fs::read_to_string(format!("/proc/{}/fdinfo/{}", pid, fd)).unwrap_or_default();
+            let field = |name: &str| {
+                info.lines().find_map(|line| line.strip_prefix(name)?.strip_prefix(':')).map(str::trim)
+            };
+            Some(OpenFile {
+                fd,
+                target,
+                pos: field("pos").and_then(|pos| pos.parse().ok()),
+                flags: field("flags").and_then(|flags| u32::from_str_radix(flags, 8).ok()),
+            })
+        })
+        .collect();
+    files.sort_by_key(|file| file.fd);
+    files
+}
+
+/// Describes the flags of an open file, e.g. `O_WRONLY|O_APPEND|O_CLOEXEC`, leaving out
+/// O_LARGEFILE which says nothing about it.
+pub fn describe_open_flags(flags: u32) -> String {
+    syscalls::open_flags((flags & !O_LARGEFILE) as libc::c_int)
+}
+
+/// Returns the name, description and format of an auxiliary vector entry.
+pub fn auxv_entry(key: u64) -> (&'static str, &'static str, &'static AuxvFormat) {
+    match AUXV_ENTRIES.iter().find(|(entry_key, _, _, _)| *entry_key == key) {
+        Some((_, name, description, format)) => (name, description, format),
+        None => ("???", "", &AuxvFormat::Hex),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_mapping() {
+        let mapping = parse_mapping("00400000-00401000 r-xp 00001000 fd:01 1234       /tmp/my calls").unwrap();
+        assert_eq!((mapping.start, mapping.end, mapping.offset), (0x400000, 0x401000, 0x1000));
+        assert_eq!((mapping.perms.as_str(), mapping.path.as_str()), ("r-xp", "/tmp/my calls"));
+        assert_eq!(parse_mapping("7ffd1000-7ffd2000 rw-p 00000000 00:00 0 ").unwrap().path, "");
+        assert_eq!(describe_open_flags(0o2100002), "O_RDWR|O_CLOEXEC");
+        assert_eq!(describe_open_flags(0o102001), "O_WRONLY|O_APPEND");
+    }
+}
@@ -0,0 +1,86 @@
+//! Aggregation of the stacks sampled by `deet profile` into a flat profile, and into folded
+//! stacks (`main;parse;read_token 42`) as read by flame graph tools.
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Default)]
+pub struct Profile {
+    samples: usize,
+    // Number of samples of each stack, outermost function first
+    stacks: HashMap<Vec<String>, usize>,
+}
+
+impl Profile {
+    /// Adds a sample of the stack `functions`, innermost first as in a backtrace.
+    pub fn add(&mut self, mut functions: Vec<String>) {
+        functions.reverse();
+        *self.stacks.entry(functions).or_default() += 1;
+        self.samples += 1;
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Returns the lines of the flat profile: for each function, the samples it was running in
+    /// itself and the samples it was on the stack in, by decreasing self time.
+    pub fn flat(&self) -> Vec<String> {
+        let mut counts: HashMap<&str, (usize, usize)> = HashMap::new();
+        for (stack, &count) in &self.stacks {
+            if let Some(innermost) = stack.last() {
+                counts.entry(innermost).or_default().0 += count;
+            }
+            // Recursive functions are counted once per sample
+            let distinct: HashSet<&str> = stack.iter().map(String::as_str).collect();
+            for function in distinct {
+                counts.entry(function).or_default().1 += count;
+            }
+        }
+        let mut counts: Vec<(&str, (usize, usize))> = counts.into_iter().collect();
+        counts.sort_by(|(name1, (self1, total1)), (name2, (self2, total2))| {
+            self2.cmp(self1).then(total2.cmp(total1)).then(name1.cmp(name2))
+        });
+        let percent = |count: usize| 100.0 * count as f64 / self.samples.max(1) as f64;
+        let mut lines = vec![format!("{:>7} {:>8} {:>7} {:>8}  function", "%self", "self", "%total", "total")];
+        for (name, (self_count, total)) in counts {
+            lines.push(format!(
+                "{:>7.2} {:>8} {:>7.2} {:>8}  {}",
+                percent(self_count), self_count, percent(total), total, name
+            ));
+        }
+        lines
+    }
+
+    /// Returns the folded stacks, one line per distinct stack with its number of samples.
+    pub fn folded(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.stacks.iter()
+            .map(|(stack, count)| format!("{} {}", stack.join(";"), count))
+            .collect();
+        lines.sort();
+        lines
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stack(functions: &[&str]) -> Vec<String> {
+        functions.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut profile = Profile::default();
+        profile.add(stack(&["fib", "fib", "main"]));
+        profile.add(stack(&["fib", "fib", "main"]));
+        profile.add(stack(&["main"]));
+        profile.add(stack(&["write", "report", "main"]));
+        assert_eq!(profile.samples(), 4);
+        assert_eq!(profile.folded(), vec!["main 1", "main;fib;fib 2", "main;report;write 1"]);
+        let flat = profile.flat();
+        assert_eq!(flat[1], "  50.00        2   50.00        2  fib");
+        assert_eq!(flat[2], "  25.00        1  100.00        4  main");
+        assert_eq!(flat.len(), 5);
+    }
+}
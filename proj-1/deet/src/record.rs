@@ -0,0 +1,177 @@
+//! Recording of the system calls made by the inferior, and replaying them on a later run. Calls
+//! whose results come from outside the program (input, time, random numbers, pids) are not
+//! executed on replay: their recorded results are written back instead, so that the program takes
+//! the same path as in the recorded run.
+
+use crate::inferior::Inferior;
+use nix::libc;
+
+#[derive(Clone)]
+pub struct SyscallRecord {
+    pub number: u64,
+    pub args: [u64; 6],
+    pub result: i64,
+    /// Memory written by the call, as (argument holding the address, bytes).
+    pub outputs: Vec<(usize, Vec<u8>)>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Record,
+    Replay,
+}
+
+pub struct SyscallLog {
+    pub mode: Mode,
+    /// Arguments the program was run with.
+    pub args: Vec<String>,
+    pub records: Vec<SyscallRecord>,
+    /// Index of the next record to replay.
+    position: usize,
+    /// The call the inferior is in: its number and arguments, and whether it is being emulated.
+    current: Option<(u64, [u64; 6], bool)>,
+}
+
+/// How far a log had got when a checkpoint was taken, to rewind it to when the checkpoint is
+/// restarted.
+#[derive(Clone, Copy)]
+pub struct LogMark {
+    records: usize,
+    position: usize,
+    current: Option<(u64, [u64; 6], bool)>,
+}
+
+impl SyscallLog {
+    pub fn record(args: &[String]) -> SyscallLog {
+        SyscallLog {
+            mode: Mode::Record,
+            args: args.to_vec(),
+            records: Vec::new(),
+            position: 0,
+            current: None,
+        }
+    }
+
+    /// Returns a log that replays this recording from the start.
+    pub fn replay(&self) -> SyscallLog {
+        SyscallLog {
+            mode: Mode::Replay,
+            args: self.args.clone(),
+            records: self.records.clone(),
+            position: 0,
+            current: None,
+        }
+    }
+
+    pub fn mark(&self) -> LogMark {
+        LogMark { records: self.records.len(), position: self.position, current: self.current }
+    }
+
+    /// Goes back to `mark`, forgetting the calls recorded since: the restarted program makes
+    /// them again.
+    pub fn rewind(&mut self, mark: LogMark) {
+        if self.mode == Mode::Record {
+            self.records.truncate(mark.records);
+        }
+        self.position = mark.position;
+        self.current = mark.current;
+    }
+
+    /// Handles the inferior entering system call `number`. When replaying a call that is
+    /// emulated, the call is turned into an invalid one so that the kernel skips it.
+    pub fn on_entry(&mut self, inferior: &Inferior, number: u64, args: [u64; 6]) -> Result<(), nix::Error> {
+        let mut emulate = false;
+        if self.mode == Mode::Replay {
+            match self.records.get(self.position) {
+                Some(record) if record.number == number => emulate = emulated(number),
+                Some(record) => {
+                    println!(
+                        "Replay diverged at system call {}: expected {} but the program made {}; continuing without replay.",
+                        self.position, record.number, number
+                    );
+                    self.position = self.records.len();
+                }
+                None => {}
+            }
+            self.position += 1;
+            if emulate {
+                let mut regs = inferior.get_regs()?;
+                regs.orig_rax = u64::MAX;
+                nix::sys::ptrace::setregs(inferior.pid(), regs)?;
+            }
+        }
+        self.current = Some((number, args, emulate));
+        Ok(())
+    }
+
+    /// Handles the inferior returning from the current system call with `result`: records it,
+    /// along with the memory it wrote, or replaces it with the recorded one.
+    pub fn on_exit(&mut self, inferior: &Inferior, result: i64) -> Result<(), nix::Error> {
+        let (number, args, emulate) = match self.current.take() {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+        match self.mode {
+            Mode::Record => {
+                let outputs = outputs(number, &args, result)
+                    .into_iter()
+                    .filter_map(|(arg, len)| {
+                        let bytes = inferior.read_memory(args[arg] as usize, len).ok()?;
+                        Some((arg, bytes))
+                    })
+                    .collect();
+                self.records.push(SyscallRecord { number, args, result, outputs });
+            }
+            Mode::Replay if emulate => {
+                let record = &self.records[self.position - 1];
+                for (arg, bytes) in &record.outputs {
+                    inferior.write_memory(args[*arg] as usize, bytes)?;
+                }
+                let mut regs = inferior.get_regs()?;
+                regs.rax = record.result as u64;
+                nix::sys::ptrace::setregs(inferior.pid(), regs)?;
+            }
+            Mode::Replay => {}
+        }
+        Ok(())
+    }
+}
+
+/// Returns whether the result of system call `number` depends on the world outside the program,
+/// so that it should not be executed again on replay.
+fn emulated(number: u64) -> bool {
+    matches!(
+        number as libc::c_long,
+        libc::SYS_read
+            | libc::SYS_pread64
+            | libc::SYS_lseek
+            | libc::SYS_recvfrom
+            | libc::SYS_poll
+            | libc::SYS_getrandom
+            | libc::SYS_time
+            | libc::SYS_gettimeofday
+            | libc::SYS_clock_gettime
+            | libc::SYS_nanosleep
+            | libc::SYS_clock_nanosleep
+            | libc::SYS_getpid
+            | libc::SYS_getppid
+            | libc::SYS_gettid
+    )
+}
+
+/// Returns the memory system call `number` wrote to, as (argument holding the address, length).
+fn outputs(number: u64, args: &[u64; 6], result: i64) -> Vec<(usize, usize)> {
+    let nonnull = |arg: usize, len: usize| Some((arg, len)).filter(|_| args[arg] != 0);
+    let outputs = match number as libc::c_long {
+        libc::SYS_read | libc::SYS_pread64 | libc::SYS_recvfrom if result > 0 => {
+            vec![nonnull(1, result as usize)]
+        }
+        libc::SYS_getrandom if result > 0 => vec![nonnull(0, result as usize)],
+        libc::SYS_poll => vec![nonnull(0, args[1] as usize * 8)],
+        libc::SYS_time => vec![nonnull(0, 8)],
+        libc::SYS_gettimeofday => vec![nonnull(0, 16), nonnull(1, 8)],
+        libc::SYS_clock_gettime => vec![nonnull(1, 16)],
+        _ => Vec::new(),
+    };
+    outputs.into_iter().flatten().collect()
+}
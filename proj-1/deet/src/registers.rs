@@ -0,0 +1,79 @@
+//! Access to the x86-64 general purpose registers of a stopped inferior by name.
+
+use nix::libc::{user_fpregs_struct, user_regs_struct};
+
+/// Returns the value of the register called `name` (without the leading `$`). `pc`, `sp` and
+/// `fp` are accepted as aliases for `rip`, `rsp` and `rbp`.
+pub fn get_register(regs: &user_regs_struct, name: &str) -> Option<u64> {
+    let mut regs = *regs;
+    register_mut(&mut regs, name).copied()
+}
+
+/// Returns a mutable reference to the register called `name`, for writing to it.
+pub fn register_mut<'a>(regs: &'a mut user_regs_struct, name: &str) -> Option<&'a mut u64> {
+    Some(match name {
+        "rax" => &mut regs.rax,
+        "rbx" => &mut regs.rbx,
+        "rcx" => &mut regs.rcx,
+        "rdx" => &mut regs.rdx,
+        "rsi" => &mut regs.rsi,
+        "rdi" => &mut regs.rdi,
+        "rbp" | "fp" => &mut regs.rbp,
+        "rsp" | "sp" => &mut regs.rsp,
+        "r8" => &mut regs.r8,
+        "r9" => &mut regs.r9,
+        "r10" => &mut regs.r10,
+        "r11" => &mut regs.r11,
+        "r12" => &mut regs.r12,
+        "r13" => &mut regs.r13,
+        "r14" => &mut regs.r14,
+        "r15" => &mut regs.r15,
+        "rip" | "pc" => &mut regs.rip,
+        "eflags" => &mut regs.eflags,
+        "cs" => &mut regs.cs,
+        "ss" => &mut regs.ss,
+        "ds" => &mut regs.ds,
+        "es" => &mut regs.es,
+        "fs" => &mut regs.fs,
+        "gs" => &mut regs.gs,
+        "fs_base" => &mut regs.fs_base,
+        "gs_base" => &mut regs.gs_base,
+        "orig_rax" => &mut regs.orig_rax,
+        _ => return None,
+    })
+}
+
+/// Returns the (low 64 bits of the) register with the given DWARF register number, as used by
+/// location expressions and call frame information.
+pub fn get_dwarf_register(
+    regs: &user_regs_struct,
+    fpregs: Option<&user_fpregs_struct>,
+    register: u16,
+) -> Option<u64> {
+    Some(match register {
+        0 => regs.rax,
+        1 => regs.rdx,
+        2 => regs.rcx,
+        3 => regs.rbx,
+        4 => regs.rsi,
+        5 => regs.rdi,
+        6 => regs.rbp,
+        7 => regs.rsp,
+        8 => regs.r8,
+        9 => regs.r9,
+        10 => regs.r10,
+        11 => regs.r11,
+        12 => regs.r12,
+        13 => regs.r13,
+        14 => regs.r14,
+        15 => regs.r15,
+        16 => regs.rip,
+        17..=32 => {
+            // xmm0-xmm15, four 32-bit words each
+            let xmm = &fpregs?.xmm_space[(register as usize - 17) * 4..];
+            xmm[0] as u64 | (xmm[1] as u64) << 32
+        }
+        49 => regs.eflags,
+        _ => return None,
+    })
+}
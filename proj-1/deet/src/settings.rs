@@ -0,0 +1,249 @@
+//! Settings changed with `set`/`show` and `handle`, the pager for long output, and the init files
+//! whose commands run at startup: `~/.deetrc`, then `./.deetrc` if its directory is in the
+//! auto-load safe path.
+
+use crate::expr::FormatOptions;
+use crate::tui;
+use nix::sys::signal::Signal;
+use std::collections::HashMap;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What to do when the inferior receives a signal: stop and give control back to the user, print
+/// a message, and deliver the signal to the program when it is resumed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignalAction {
+    pub stop: bool,
+    pub print: bool,
+    pub pass: bool,
+}
+
+pub struct Settings {
+    pub prompt: String,
+    pub format: FormatOptions,
+    pub pagination: bool,
+    pub height: usize, // Lines per page
+    pub auto_list: bool, // Show the source line whenever the program stops
+    pub reverse: bool, // Take the checkpoints reverse-continue needs while the program runs
+    pub safe_path: Vec<PathBuf>, // Directories whose .deetrc may run
+    signals: HashMap<Signal, SignalAction>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            prompt: "(deet) ".to_string(),
+            format: FormatOptions::default(),
+            pagination: true,
+            height: tui::terminal_size().map_or(24, |(rows, _)| rows),
+            auto_list: false,
+            reverse: false,
+            safe_path: Vec::new(),
+            signals: HashMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Sets option `name`, e.g. `print` with the value `pretty on`. Returns an error message for
+    /// unknown options and bad values.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "prompt" => {
+                // A quoted prompt is taken as is, otherwise it is followed by a space
+                self.prompt = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                    Some(quoted) => quoted.to_string(),
+                    None => format!("{} ", value),
+                };
+            }
+            "print" => match value.split_once(' ') {
+                Some(("pretty", on)) => self.format.pretty = parse_bool(on)?,
+                _ => return Err(format!("Undefined set print command: \"{}\".", value)),
+            },
+            "output-radix" => {
+                self.format.hex = match value {
+                    "10" => false,
+                    "16" => true,
+                    _ => return Err(format!("Unsupported output radix \"{}\"; use 10 or 16.", value)),
+                }
+            }
+            "pagination" => self.pagination = parse_bool(value)?,
+            "height" => {
+                self.height = value
+                    .parse()
+                    .map_err(|_| format!("Invalid number \"{}\".", value))?
+            }
+            "auto-list" => self.auto_list = parse_bool(value)?,
+            "reverse" => self.reverse = parse_bool(value)?,
+            "auto-load" => match value.split_once(' ') {
+                // A colon-separated list, like $PATH
+                Some(("safe-path", dirs)) => self.safe_path = dirs.split(':').map(PathBuf::from).collect(),
+                _ => return Err(format!("Undefined set auto-load command: \"{}\".", value)),
+            },
+            _ => return Err(format!("No option named \"{}\".", name)),
+        }
+        Ok(())
+    }
+
+    /// Describes the current value of option `name`.
+    pub fn show(&self, name: &str) -> Option<String> {
+        let on_off = |on: bool| if on { "on" } else { "off" };
+        Some(match name {
+            "prompt" => format!("Debugger's prompt is \"{}\".", self.prompt),
+            "print pretty" => format!("Pretty formatting of structures is {}.", on_off(self.format.pretty)),
+            "output-radix" => format!(
+                "Default output radix for printing of values is {}.",
+                if self.format.hex { 16 } else { 10 }
+            ),
+            "pagination" => format!("State of pagination is {}.", on_off(self.pagination)),
+            "height" => format!("Number of lines per page is {}.", self.height),
+            "auto-list" => format!("Listing the source line at each stop is {}.", on_off(self.auto_list)),
+            "reverse" => format!("Recording checkpoints for reverse execution is {}.", on_off(self.reverse)),
+            "auto-load safe-path" => {
+                let dirs: Vec<String> = self.safe_path.iter().map(|dir| dir.display().to_string()).collect();
+                format!("List of directories from which it is safe to auto-load files is \"{}\".", dirs.join(":"))
+            }
+            _ => return None,
+        })
+    }
+
+    /// Whether the file at `path`, which must be canonical, is under one of the directories of
+    /// the auto-load safe path. Files in the current directory may come from anyone, e.g. with a
+    /// cloned repository, so they only run where the user has said it is safe.
+    pub fn is_safe_to_load(&self, path: &Path) -> bool {
+        self.safe_path.iter()
+            .filter_map(|dir| dir.canonicalize().ok())
+            .any(|dir| path.starts_with(dir))
+    }
+
+    pub fn signal_action(&self, signal: Signal) -> SignalAction {
+        if let Some(action) = self.signals.get(&signal) {
+            return *action;
+        }
+        match signal {
+            // Signals programs commonly use as part of normal operation
+            Signal::SIGALRM | Signal::SIGURG | Signal::SIGCHLD | Signal::SIGWINCH | Signal::SIGIO
+            | Signal::SIGVTALRM | Signal::SIGPROF => SignalAction { stop: false, print: false, pass: true },
+            // Ctrl+C is meant for the debugger
+            Signal::SIGINT | Signal::SIGTRAP => SignalAction { stop: true, print: true, pass: false },
+            _ => SignalAction { stop: true, print: true, pass: true },
+        }
+    }
+
+    /// Changes how `signal` is handled according to keywords like `nostop` or `pass`.
+    pub fn handle(&mut self, signal: Signal, keywords: &[String]) -> Result<(), String> {
+        if signal == Signal::SIGTRAP {
+            return Err("SIGTRAP is used by the debugger and cannot be changed.".to_string());
+        }
+        let mut action = self.signal_action(signal);
+        for keyword in keywords {
+            match keyword.as_str() {
+                "stop" => {
+                    action.stop = true;
+                    action.print = true;
+                }
+                "nostop" => action.stop = false,
+                "print" => action.print = true,
+                "noprint" => {
+                    action.print = false;
+                    action.stop = false;
+                }
+                "pass" | "noignore" => action.pass = true,
+                "nopass" | "ignore" => action.pass = false,
+                _ => return Err(format!("Unrecognized or ambiguous flag word: \"{}\".", keyword)),
+            }
+        }
+        self.signals.insert(signal, action);
+        Ok(())
+    }
+}
+
+/// Parses a signal given as `SIGUSR1`, `USR1` or a number.
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    if let Ok(number) = name.parse::<i32>() {
+        return Signal::try_from(number).ok();
+    }
+    let upper = name.to_uppercase();
+    let full = if upper.starts_with("SIG") { upper } else { format!("SIG{}", upper) };
+    Signal::from_str(&full).ok()
+}
+
+/// The header of the table printed by `handle` and `info signals`.
+pub fn signal_header() -> String {
+    format!("{:<14}{:<8}{:<8}{:<18}Description", "Signal", "Stop", "Print", "Pass to program")
+}
+
+pub fn format_signal(signal: Signal, action: SignalAction) -> String {
+    let yes_no = |b: bool| if b { "Yes" } else { "No" };
+    let description = unsafe { std::ffi::CStr::from_ptr(nix::libc::strsignal(signal as i32)) };
+    format!(
+        "{:<14}{:<8}{:<8}{:<18}{}",
+        signal.as_str(),
+        yes_no(action.stop),
+        yes_no(action.print),
+        yes_no(action.pass),
+        description.to_string_lossy()
+    )
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "on" | "1" | "yes" | "enable" => Ok(true),
+        "off" | "0" | "no" | "disable" => Ok(false),
+        _ => Err(format!("\"on\" or \"off\" expected, got \"{}\".", value)),
+    }
+}
+
+/// Returns `~/.deetrc`, if it exists.
+pub fn home_init_file() -> Option<PathBuf> {
+    let path = PathBuf::from(std::env::var("HOME").ok()?).join(".deetrc");
+    Some(path).filter(|path| path.is_file())
+}
+
+/// Returns `.deetrc` in the current directory, if it exists and is not `~/.deetrc`.
+pub fn local_init_file() -> Option<PathBuf> {
+    let path = PathBuf::from(".deetrc").canonicalize().ok()?;
+    let home = home_init_file().and_then(|home| home.canonicalize().ok());
+    Some(path).filter(|path| home.as_ref() != Some(path))
+}
+
+/// Prints long output a page at a time, waiting for the user to press return between pages, when
+/// pagination is on and the debugger is used interactively.
+pub struct Pager {
+    height: usize,
+    lines: usize,
+    quit: bool,
+}
+
+impl Pager {
+    pub fn new(settings: &Settings) -> Pager {
+        let interactive = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+        Pager {
+            height: if settings.pagination && interactive { settings.height } else { 0 },
+            lines: 0,
+            quit: false,
+        }
+    }
+
+    /// Prints `line`, unless the user has asked to stop. Returns false once they have.
+    pub fn println(&mut self, line: &str) -> bool {
+        if self.quit {
+            return false;
+        }
+        if self.height > 1 && self.lines == self.height - 1 {
+            print!("--Type <RET> for more, q to quit--");
+            let _ = std::io::stdout().flush();
+            let mut answer = String::new();
+            let _ = std::io::stdin().lock().read_line(&mut answer);
+            if answer.trim() == "q" {
+                self.quit = true;
+                return false;
+            }
+            self.lines = 0;
+        }
+        println!("{}", line);
+        self.lines += 1;
+        true
+    }
+}
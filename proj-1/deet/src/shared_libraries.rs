@@ -0,0 +1,161 @@
+//! Tracking of the shared objects loaded into the inferior. The dynamic linker publishes the list
+//! of loaded objects in its rendezvous structure (`struct r_debug`), which the `DT_DEBUG` entry
+//! of the executable's dynamic section points to, and calls `_dl_debug_state` every time the list
+//! changes so that debuggers can put a breakpoint there.
+
+use crate::dwarf_data::DwarfData;
+use crate::inferior::Inferior;
+use object::{Object, ObjectSection, ObjectSegment};
+use std::fs;
+use std::mem::size_of;
+
+const DT_NULL: u64 = 0;
+const DT_DEBUG: u64 = 21;
+const AT_BASE: u64 = 7;
+const AT_ENTRY: u64 = 9;
+/// `r_debug.r_state` when the list of loaded objects is consistent.
+const RT_CONSISTENT: u64 = 0;
+
+pub struct SharedLibrary {
+    pub name: String,
+    /// Difference between the addresses the library was loaded at and those in its file.
+    pub base: usize,
+    /// Runtime address range covered by the library's loadable segments.
+    pub start: usize,
+    pub end: usize,
+    pub debug_data: Option<DwarfData>,
+}
+
+impl SharedLibrary {
+    /// Reads the symbols and debug info of the shared object at `path`, loaded at `base`.
+    /// Returns None if the file can't be read, e.g. for the vDSO.
+    pub fn load(path: &str, base: usize, debug_file_directories: &[String], index_cache: bool) -> Option<SharedLibrary> {
+        let data = fs::read(path).ok()?;
+        let object = object::File::parse(&*data).ok()?;
+        let segments: Vec<(u64, u64)> = object
+            .segments()
+            .map(|s| (s.address(), s.address() + s.size()))
+            .collect();
+        let start = segments.iter().map(|&(start, _)| start).min()? as usize;
+        let end = segments.iter().map(|&(_, end)| end).max()? as usize;
+        Some(SharedLibrary {
+            name: path.to_string(),
+            base,
+            start: base + start,
+            end: base + end,
+            debug_data: DwarfData::from_file(path, debug_file_directories, index_cache)
+                .or_else(|_| DwarfData::from_symbol_table(path))
+                .ok(),
+        })
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+/// Returns the debug info of the object containing `addr`, along with the base it was loaded at.
+/// Addresses outside of every shared library belong to the executable, which is not relocated.
+pub fn find_object<'a>(
+    addr: usize,
+    debug_data: &'a Option<DwarfData>,
+    libraries: &'a [SharedLibrary],
+) -> Option<(&'a DwarfData, usize)> {
+    match libraries.iter().find(|lib| lib.contains(addr)) {
+        Some(lib) => Some((lib.debug_data.as_ref()?, lib.base)),
+        None => Some((debug_data.as_ref()?, 0)),
+    }
+}
+
+/// Returns the path of the dynamic linker requested by the executable, if it is dynamically
+/// linked.
+pub fn interpreter(target: &str) -> Option<String> {
+    let data = fs::read(target).ok()?;
+    let object = object::File::parse(&*data).ok()?;
+    let interp = object.section_by_name(".interp")?.data().ok()?.to_vec();
+    let end = interp.iter().position(|&b| b == 0).unwrap_or(interp.len());
+    String::from_utf8(interp[..end].to_vec()).ok()
+}
+
+/// Returns the address the dynamic linker was loaded at, from the inferior's auxiliary vector.
+pub fn interpreter_base(inferior: &Inferior) -> Option<usize> {
+    read_auxv(inferior)
+        .into_iter()
+        .find(|&(key, _)| key == AT_BASE)
+        .map(|(_, value)| value as usize)
+        .filter(|&base| base != 0)
+}
+
+/// Returns how far the executable was moved from the addresses it was linked at, which is zero
+/// unless it is position-independent: the difference between the entry point the kernel
+/// reports in the auxiliary vector and the one in the file.
+pub fn executable_bias(inferior: &Inferior, target: &str) -> usize {
+    let entry = read_auxv(inferior).into_iter().find(|&(key, _)| key == AT_ENTRY).map(|(_, value)| value);
+    let data = fs::read(target).unwrap_or_default();
+    match (entry, object::File::parse(&*data).ok()) {
+        (Some(entry), Some(object)) => entry.wrapping_sub(object.entry()) as usize,
+        _ => 0,
+    }
+}
+
+/// Reads the (key, value) pairs of the inferior's auxiliary vector.
+pub fn read_auxv(inferior: &Inferior) -> Vec<(u64, u64)> {
+    let data = fs::read(format!("/proc/{}/auxv", inferior.pid())).unwrap_or_default();
+    data.chunks_exact(2 * size_of::<u64>())
+        .map(|entry| {
+            (
+                u64::from_le_bytes(entry[..8].try_into().unwrap()),
+                u64::from_le_bytes(entry[8..].try_into().unwrap()),
+            )
+        })
+        .take_while(|&(key, _)| key != 0)
+        .collect()
+}
+
+/// Returns the (name, base) of every object in the dynamic linker's list, or None if the list is
+/// not available or is being modified.
+pub fn read_link_map(inferior: &Inferior, target: &str) -> Option<Vec<(String, usize)>> {
+    let r_debug = r_debug_addr(inferior, target)?;
+    if read_word(inferior, r_debug + 24)? & 0xffff_ffff != RT_CONSISTENT {
+        return None;
+    }
+
+    let mut objects = Vec::new();
+    let mut link_map = read_word(inferior, r_debug + 8)? as usize;
+    while link_map != 0 && objects.len() < 4096 {
+        let base = read_word(inferior, link_map)? as usize;
+        let name = read_string(inferior, read_word(inferior, link_map + 8)? as usize)?;
+        objects.push((name, base));
+        link_map = read_word(inferior, link_map + 24)? as usize;
+    }
+    Some(objects)
+}
+
+/// Finds `struct r_debug` through the `DT_DEBUG` entry of the executable's dynamic section, which
+/// the dynamic linker fills in during startup.
+fn r_debug_addr(inferior: &Inferior, target: &str) -> Option<usize> {
+    let data = fs::read(target).ok()?;
+    let object = object::File::parse(&*data).ok()?;
+    let dynamic = object.section_by_name(".dynamic")?;
+    let mut addr = (dynamic.address() as usize).wrapping_add(executable_bias(inferior, target));
+    let end = addr + dynamic.size() as usize;
+    while addr < end {
+        match read_word(inferior, addr)? {
+            DT_NULL => return None,
+            DT_DEBUG => return Some(read_word(inferior, addr + 8)? as usize).filter(|&a| a != 0),
+            _ => addr += 16,
+        }
+    }
+    None
+}
+
+fn read_word(inferior: &Inferior, addr: usize) -> Option<u64> {
+    let bytes = inferior.read_memory(addr, size_of::<u64>()).ok()?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Reads the NUL-terminated string at `addr` in the inferior.
+pub fn read_string(inferior: &Inferior, addr: usize) -> Option<String> {
+    let (bytes, _) = inferior.read_c_string(addr, 4096).ok()?;
+    String::from_utf8(bytes).ok()
+}
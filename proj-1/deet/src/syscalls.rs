@@ -0,0 +1,303 @@
+//! Names and argument types of the x86-64 Linux system calls, for `catch syscall` and
+//! `trace syscalls`.
+
+use crate::expr::quote_bytes;
+use crate::inferior::Inferior;
+use nix::libc;
+
+/// System call names, indexed by number.
+static NAMES: [&str; 451] = [
+    "read", "write", "open", "close", "stat", "fstat", "lstat", "poll", "lseek", "mmap", "mprotect",
+    "munmap", "brk", "rt_sigaction", "rt_sigprocmask", "rt_sigreturn", "ioctl", "pread64",
+    "pwrite64", "readv", "writev", "access", "pipe", "select", "sched_yield", "mremap", "msync",
+    "mincore", "madvise", "shmget", "shmat", "shmctl", "dup", "dup2", "pause", "nanosleep",
+    "getitimer", "alarm", "setitimer", "getpid", "sendfile", "socket", "connect", "accept",
+    "sendto", "recvfrom", "sendmsg", "recvmsg", "shutdown", "bind", "listen", "getsockname",
+    "getpeername", "socketpair", "setsockopt", "getsockopt", "clone", "fork", "vfork", "execve",
+    "exit", "wait4", "kill", "uname", "semget", "semop", "semctl", "shmdt", "msgget", "msgsnd",
+    "msgrcv", "msgctl", "fcntl", "flock", "fsync", "fdatasync", "truncate", "ftruncate", "getdents",
+    "getcwd", "chdir", "fchdir", "rename", "mkdir", "rmdir", "creat", "link", "unlink", "symlink",
+    "readlink", "chmod", "fchmod", "chown", "fchown", "lchown", "umask", "gettimeofday",
+    "getrlimit", "getrusage", "sysinfo", "times", "ptrace", "getuid", "syslog", "getgid", "setuid",
+    "setgid", "geteuid", "getegid", "setpgid", "getppid", "getpgrp", "setsid", "setreuid",
+    "setregid", "getgroups", "setgroups", "setresuid", "getresuid", "setresgid", "getresgid",
+    "getpgid", "setfsuid", "setfsgid", "getsid", "capget", "capset", "rt_sigpending",
+    "rt_sigtimedwait", "rt_sigqueueinfo", "rt_sigsuspend", "sigaltstack", "utime", "mknod",
+    "uselib", "personality", "ustat", "statfs", "fstatfs", "sysfs", "getpriority", "setpriority",
+    "sched_setparam", "sched_getparam", "sched_setscheduler", "sched_getscheduler",
+    "sched_get_priority_max", "sched_get_priority_min", "sched_rr_get_interval", "mlock", "munlock",
+    "mlockall", "munlockall", "vhangup", "modify_ldt", "pivot_root", "_sysctl", "prctl",
+    "arch_prctl", "adjtimex", "setrlimit", "chroot", "sync", "acct", "settimeofday", "mount",
+    "umount2", "swapon", "swapoff", "reboot", "sethostname", "setdomainname", "iopl", "ioperm",
+    "create_module", "init_module", "delete_module", "get_kernel_syms", "query_module", "quotactl",
+    "nfsservctl", "getpmsg", "putpmsg", "afs_syscall", "tuxcall", "security", "gettid", "readahead",
+    "setxattr", "lsetxattr", "fsetxattr", "getxattr", "lgetxattr", "fgetxattr", "listxattr",
+    "llistxattr", "flistxattr", "removexattr", "lremovexattr", "fremovexattr", "tkill", "time",
+    "futex", "sched_setaffinity", "sched_getaffinity", "set_thread_area", "io_setup", "io_destroy",
+    "io_getevents", "io_submit", "io_cancel", "get_thread_area", "lookup_dcookie", "epoll_create",
+    "epoll_ctl_old", "epoll_wait_old", "remap_file_pages", "getdents64", "set_tid_address",
+    "restart_syscall", "semtimedop", "fadvise64", "timer_create", "timer_settime", "timer_gettime",
+    "timer_getoverrun", "timer_delete", "clock_settime", "clock_gettime", "clock_getres",
+    "clock_nanosleep", "exit_group", "epoll_wait", "epoll_ctl", "tgkill", "utimes", "vserver",
+    "mbind", "set_mempolicy", "get_mempolicy", "mq_open", "mq_unlink", "mq_timedsend",
+    "mq_timedreceive", "mq_notify", "mq_getsetattr", "kexec_load", "waitid", "add_key",
+    "request_key", "keyctl", "ioprio_set", "ioprio_get", "inotify_init", "inotify_add_watch",
+    "inotify_rm_watch", "migrate_pages", "openat", "mkdirat", "mknodat", "fchownat", "futimesat",
+    "newfstatat", "unlinkat", "renameat", "linkat", "symlinkat", "readlinkat", "fchmodat",
+    "faccessat", "pselect6", "ppoll", "unshare", "set_robust_list", "get_robust_list", "splice",
+    "tee", "sync_file_range", "vmsplice", "move_pages", "utimensat", "epoll_pwait", "signalfd",
+    "timerfd_create", "eventfd", "fallocate", "timerfd_settime", "timerfd_gettime", "accept4",
+    "signalfd4", "eventfd2", "epoll_create1", "dup3", "pipe2", "inotify_init1", "preadv", "pwritev",
+    "rt_tgsigqueueinfo", "perf_event_open", "recvmmsg", "fanotify_init", "fanotify_mark",
+    "prlimit64", "name_to_handle_at", "open_by_handle_at", "clock_adjtime", "syncfs", "sendmmsg",
+    "setns", "getcpu", "process_vm_readv", "process_vm_writev", "kcmp", "finit_module",
+    "sched_setattr", "sched_getattr", "renameat2", "seccomp", "getrandom", "memfd_create",
+    "kexec_file_load", "bpf", "execveat", "userfaultfd", "membarrier", "mlock2", "copy_file_range",
+    "preadv2", "pwritev2", "pkey_mprotect", "pkey_alloc", "pkey_free", "statx", "io_pgetevents",
+    "rseq", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
+    "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
+    "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
+    "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "pidfd_send_signal",
+    "io_uring_setup", "io_uring_enter", "io_uring_register", "open_tree", "move_mount", "fsopen",
+    "fsconfig", "fsmount", "fspick", "pidfd_open", "clone3", "close_range", "openat2",
+    "pidfd_getfd", "faccessat2", "process_madvise", "epoll_pwait2", "mount_setattr", "quotactl_fd",
+    "landlock_create_ruleset", "landlock_add_rule", "landlock_restrict_self", "memfd_secret",
+    "process_mrelease", "futex_waitv", "set_mempolicy_home_node",
+];
+
+/// Argument types of the common system calls, one character per argument: `d` signed, `u`
+/// unsigned, `x` hexadecimal, `f` file descriptor, `s` string, `b` buffer (its length is the next
+/// argument, or the result for calls that fill it), `o` open flags, `m` file mode.
+const SIGNATURES: &[(&str, &str)] = &[
+    ("read", "fbu"),
+    ("write", "fbu"),
+    ("open", "som"),
+    ("close", "f"),
+    ("stat", "sx"),
+    ("fstat", "fx"),
+    ("lstat", "sx"),
+    ("poll", "xud"),
+    ("lseek", "fdd"),
+    ("mmap", "xuxxfd"),
+    ("mprotect", "xux"),
+    ("munmap", "xu"),
+    ("brk", "x"),
+    ("rt_sigaction", "dxxu"),
+    ("rt_sigprocmask", "dxxu"),
+    ("ioctl", "fxx"),
+    ("pread64", "fbud"),
+    ("pwrite64", "fbud"),
+    ("readv", "fxd"),
+    ("writev", "fxd"),
+    ("access", "sd"),
+    ("pipe", "x"),
+    ("dup", "f"),
+    ("dup2", "ff"),
+    ("nanosleep", "xx"),
+    ("socket", "ddd"),
+    ("connect", "fxu"),
+    ("accept", "fxx"),
+    ("sendto", "fbudxu"),
+    ("recvfrom", "fbudxx"),
+    ("bind", "fxu"),
+    ("listen", "fd"),
+    ("execve", "sxx"),
+    ("exit", "d"),
+    ("wait4", "dxdx"),
+    ("kill", "dd"),
+    ("fcntl", "fdx"),
+    ("fsync", "f"),
+    ("ftruncate", "fd"),
+    ("getcwd", "xu"),
+    ("chdir", "s"),
+    ("rename", "ss"),
+    ("mkdir", "sm"),
+    ("rmdir", "s"),
+    ("creat", "sm"),
+    ("unlink", "s"),
+    ("readlink", "sxu"),
+    ("chmod", "sm"),
+    ("arch_prctl", "dx"),
+    ("time", "x"),
+    ("set_tid_address", "x"),
+    ("clock_gettime", "dx"),
+    ("clock_nanosleep", "ddxx"),
+    ("exit_group", "d"),
+    ("openat", "fsom"),
+    ("mkdirat", "fsm"),
+    ("newfstatat", "fsxd"),
+    ("unlinkat", "fsd"),
+    ("renameat", "fsfs"),
+    ("readlinkat", "fsxu"),
+    ("faccessat", "fsd"),
+    ("pipe2", "xd"),
+    ("dup3", "ffd"),
+    ("prlimit64", "ddxx"),
+    ("getrandom", "bud"),
+    ("statx", "fsdux"),
+    ("faccessat2", "fsdd"),
+];
+
+/// Flags of open(2) besides the access mode. Flags that include others come first, so that
+/// O_SYNC is not also shown as O_DSYNC.
+const OPEN_FLAGS: &[(libc::c_int, &str)] = &[
+    (libc::O_CREAT, "O_CREAT"),
+    (libc::O_EXCL, "O_EXCL"),
+    (libc::O_NOCTTY, "O_NOCTTY"),
+    (libc::O_TRUNC, "O_TRUNC"),
+    (libc::O_APPEND, "O_APPEND"),
+    (libc::O_NONBLOCK, "O_NONBLOCK"),
+    (libc::O_SYNC, "O_SYNC"),
+    (libc::O_DSYNC, "O_DSYNC"),
+    (libc::O_DIRECT, "O_DIRECT"),
+    (libc::O_TMPFILE, "O_TMPFILE"),
+    (libc::O_DIRECTORY, "O_DIRECTORY"),
+    (libc::O_NOFOLLOW, "O_NOFOLLOW"),
+    (libc::O_NOATIME, "O_NOATIME"),
+    (libc::O_CLOEXEC, "O_CLOEXEC"),
+    (libc::O_PATH, "O_PATH"),
+];
+
+/// Number of bytes of strings and buffers shown.
+const MAX_SHOWN: usize = 32;
+
+pub fn name(number: u64) -> Option<&'static str> {
+    NAMES.get(number as usize).copied().filter(|name| !name.is_empty())
+}
+
+/// Returns the number of the system call called `name`, or written as a number.
+pub fn number(name: &str) -> Option<u64> {
+    match name.parse::<u64>() {
+        Ok(number) => Some(number),
+        Err(_) => NAMES.iter().position(|n| *n == name).map(|number| number as u64),
+    }
+}
+
+/// Returns `name` or `syscall_N` for system call `number`.
+pub fn display_name(number: u64) -> String {
+    match name(number) {
+        Some(name) => name.to_string(),
+        None => format!("syscall_{}", number),
+    }
+}
+
+/// Formats a system call the way strace does, e.g. `openat(AT_FDCWD, "/etc/passwd", O_RDONLY)`.
+/// `result` is the value returned, if the call has returned.
+pub fn format_call(inferior: &Inferior, number: u64, args: &[u64; 6], result: Option<i64>) -> String {
+    let signature = name(number)
+        .and_then(|name| SIGNATURES.iter().find(|(n, _)| *n == name))
+        .map(|(_, signature)| *signature)
+        .unwrap_or("xxxxxx");
+    let args: Vec<String> = signature
+        .chars()
+        .enumerate()
+        .map(|(i, kind)| {
+            let arg = args[i];
+            match kind {
+                'd' => (arg as i64).to_string(),
+                'u' => arg.to_string(),
+                'f' if arg as i32 == libc::AT_FDCWD => "AT_FDCWD".to_string(),
+                'f' => (arg as i32).to_string(),
+                's' => read_string(inferior, arg),
+                // Buffers a call fills are only meaningful once it has returned
+                'b' => {
+                    let filled = matches!(name(number), Some("read" | "pread64" | "recvfrom" | "getrandom"));
+                    let len = match (filled, result) {
+                        (true, Some(result)) if result >= 0 => Some(result as usize),
+                        (true, _) => None,
+                        (false, _) => args.get(i + 1).map(|&len| len as usize),
+                    };
+                    match len {
+                        Some(len) => read_buffer(inferior, arg, len),
+                        None => format!("{:#x}", arg),
+                    }
+                }
+                'o' => open_flags(arg as libc::c_int),
+                'm' => format!("0{:o}", arg),
+                _ => format!("{:#x}", arg),
+            }
+        })
+        .collect();
+    let result = match result {
+        Some(result) => format!(" = {}", format_result(number, result)),
+        None => String::new(),
+    };
+    format!("{}({}){}", display_name(number), args.join(", "), result)
+}
+
+fn format_result(number: u64, result: i64) -> String {
+    if (-4095..0).contains(&result) {
+        let errno = nix::errno::Errno::from_i32(-result as i32);
+        return format!("-1 {:?} ({})", errno, errno.desc());
+    }
+    match name(number) {
+        Some("mmap" | "brk") => format!("{:#x}", result),
+        _ => result.to_string(),
+    }
+}
+
+/// Describes the flags of open(2), e.g. `O_WRONLY|O_CREAT|O_TRUNC`.
+pub fn open_flags(flags: libc::c_int) -> String {
+    let mut names = vec![match flags & libc::O_ACCMODE {
+        libc::O_RDONLY => "O_RDONLY".to_string(),
+        libc::O_WRONLY => "O_WRONLY".to_string(),
+        libc::O_RDWR => "O_RDWR".to_string(),
+        mode => mode.to_string(),
+    }];
+    let mut rest = flags & !libc::O_ACCMODE;
+    for (flag, name) in OPEN_FLAGS {
+        if rest & flag == *flag {
+            names.push(name.to_string());
+            rest &= !flag;
+        }
+    }
+    if rest != 0 {
+        names.push(format!("{:#o}", rest));
+    }
+    names.join("|")
+}
+
+fn read_string(inferior: &Inferior, addr: u64) -> String {
+    if addr == 0 {
+        return "NULL".to_string();
+    }
+    match inferior.read_c_string(addr as usize, MAX_SHOWN) {
+        Ok((bytes, true)) => quote_bytes(&bytes),
+        Ok((bytes, false)) => format!("{}...", quote_bytes(&bytes)),
+        Err(_) => format!("{:#x}", addr),
+    }
+}
+
+fn read_buffer(inferior: &Inferior, addr: u64, len: usize) -> String {
+    match inferior.read_memory(addr as usize, len.min(MAX_SHOWN)) {
+        Ok(bytes) if len > MAX_SHOWN => format!("{}...", quote_bytes(&bytes)),
+        Ok(bytes) => quote_bytes(&bytes),
+        Err(_) => format!("{:#x}", addr),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn syscall_numbers() {
+        assert_eq!(number("openat"), Some(257));
+        assert_eq!(number("60"), Some(60));
+        assert_eq!(number("no_such_call"), None);
+        assert_eq!(name(0), Some("read"));
+        assert_eq!(name(400), None);
+        assert_eq!(display_name(400), "syscall_400");
+    }
+
+    #[test]
+    fn decode_open_flags() {
+        assert_eq!(open_flags(libc::O_RDONLY), "O_RDONLY");
+        assert_eq!(
+            open_flags(libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC),
+            "O_WRONLY|O_CREAT|O_TRUNC|O_CLOEXEC"
+        );
+        assert_eq!(open_flags(libc::O_RDWR | libc::O_SYNC), "O_RDWR|O_SYNC");
+        assert_eq!(open_flags(libc::O_RDWR | libc::O_TMPFILE), "O_RDWR|O_TMPFILE");
+    }
+}
@@ -0,0 +1,163 @@
+//! The split-screen text interface (`deet --tui`, `layout src`): a source pane following the
+//! current line, a register pane and a stack pane, drawn with ANSI escape sequences above a
+//! scrolling region at the bottom of the terminal where commands are typed and print their output.
+
+use nix::libc::{self, user_regs_struct};
+use std::cell::Cell;
+use std::io::Write;
+
+/// What the panes show, captured when the inferior stops.
+#[derive(Default)]
+pub struct View {
+    pub file: Option<String>,
+    pub line: Option<usize>, // Current line in `file`, if the inferior is stopped there
+    pub breakpoint_lines: Vec<usize>, // Lines of `file` with a breakpoint
+    pub location: Option<String>, // Where the inferior is stopped, e.g. `add (calls.c:10)`
+    pub registers: Option<user_regs_struct>,
+    pub stack: Vec<String>,
+}
+
+pub struct Tui {
+    size: Cell<(usize, usize)>, // Rows and columns the screen was laid out for
+}
+
+/// Lines at the bottom of the screen for the command line and command output.
+fn command_rows(rows: usize) -> usize {
+    (rows / 4).max(4)
+}
+
+impl Tui {
+    /// Clears the screen and lays out the panes.
+    pub fn new() -> Tui {
+        let tui = Tui { size: Cell::new((0, 0)) };
+        tui.layout();
+        tui
+    }
+
+    fn layout(&self) {
+        self.size.set(pane_size());
+        let (rows, _) = self.size.get();
+        // Commands scroll in the region below the panes
+        print!("\x1b[2J\x1b[{};{}r\x1b[{};1H", rows - command_rows(rows) + 1, rows, rows);
+        let _ = std::io::stdout().flush();
+    }
+
+    pub fn draw(&self, view: &View) {
+        if pane_size() != self.size.get() {
+            self.layout();
+        }
+        let (rows, cols) = self.size.get();
+        let pane_rows = rows - command_rows(rows);
+        let source_rows = pane_rows * 3 / 5;
+        let left_cols = cols / 2;
+
+        let mut lines = vec![title(view.file.as_deref().unwrap_or("[ No Source Available ]"), cols)];
+        lines.extend(source_lines(view, source_rows - 1, cols));
+        let registers = register_lines(view, pane_rows - source_rows - 1, left_cols);
+        lines.push(format!("{}{}", title("Registers", left_cols), title("Stack", cols - left_cols)));
+        for (i, register_line) in registers.iter().enumerate() {
+            let frame = view.stack.get(i).map_or(String::new(), |frame| format!("#{} {}", i, frame));
+            lines.push(format!("{}{}", fit(register_line, left_cols), fit(&frame, cols - left_cols)));
+        }
+
+        // Save and restore the cursor, which is on the command line
+        let mut out = String::from("\x1b7");
+        for (row, line) in lines.iter().enumerate().take(pane_rows) {
+            out.push_str(&format!("\x1b[{};1H{}\x1b[K", row + 1, line));
+        }
+        out.push_str("\x1b8");
+        print!("{}", out);
+        let _ = std::io::stdout().flush();
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        // Give the whole screen back to the command line
+        print!("\x1b[r\x1b[2J\x1b[{};1H", self.size.get().0);
+        let _ = std::io::stdout().flush();
+    }
+}
+
+/// Returns `rows` lines of the source file around the current line, each with a gutter marking
+/// breakpoints (`B`) and the current line (`>`).
+fn source_lines(view: &View, rows: usize, cols: usize) -> Vec<String> {
+    let text = view.file.as_ref().and_then(|file| std::fs::read_to_string(file).ok()).unwrap_or_default();
+    let source: Vec<&str> = text.lines().collect();
+    let center = view.line.unwrap_or(1);
+    let first = center.saturating_sub(rows / 2).max(1);
+    (first..first + rows)
+        .map(|number| {
+            let text = match source.get(number - 1) {
+                Some(text) => text.replace('\t', "    "),
+                None => return fit("", cols),
+            };
+            let breakpoint = if view.breakpoint_lines.contains(&number) { 'B' } else { ' ' };
+            let current = view.line == Some(number);
+            let line = fit(&format!("{}{}{:>4}  {}", breakpoint, if current { '>' } else { ' ' }, number, text), cols);
+            if current {
+                format!("\x1b[1m{}\x1b[0m", line)
+            } else {
+                line
+            }
+        })
+        .collect()
+}
+
+/// Returns `rows` lines describing the current location and registers, laid out in as many
+/// columns as fit in `cols`.
+fn register_lines(view: &View, rows: usize, cols: usize) -> Vec<String> {
+    let mut lines = vec![String::new(); rows];
+    let regs = match view.registers {
+        Some(regs) => regs,
+        None => {
+            if let Some(line) = lines.first_mut() {
+                *line = "The program is not being run.".to_string();
+            }
+            return lines;
+        }
+    };
+    if let Some(line) = lines.first_mut() {
+        *line = view.location.clone().unwrap_or_default();
+    }
+    let registers = [
+        ("rax", regs.rax), ("rbx", regs.rbx), ("rcx", regs.rcx), ("rdx", regs.rdx),
+        ("rsi", regs.rsi), ("rdi", regs.rdi), ("rbp", regs.rbp), ("rsp", regs.rsp),
+        ("r8", regs.r8), ("r9", regs.r9), ("r10", regs.r10), ("r11", regs.r11),
+        ("r12", regs.r12), ("r13", regs.r13), ("r14", regs.r14), ("r15", regs.r15),
+        ("rip", regs.rip), ("eflags", regs.eflags),
+    ];
+    const WIDTH: usize = 26;
+    let columns = (cols / WIDTH).max(1);
+    for (i, (name, value)) in registers.iter().enumerate() {
+        let row = 1 + i / columns;
+        if row >= rows {
+            break;
+        }
+        lines[row].push_str(&fit(&format!("{:<7}{:#018x}", name, value), WIDTH));
+    }
+    lines
+}
+
+/// A pane title, in reverse video across `cols` columns.
+fn title(name: &str, cols: usize) -> String {
+    format!("\x1b[7m{}\x1b[0m", fit(&format!(" {}", name), cols))
+}
+
+/// Truncates or pads `text` to exactly `cols` characters.
+fn fit(text: &str, cols: usize) -> String {
+    format!("{:<width$.width$}", text, width = cols)
+}
+
+/// Returns the rows and columns of the terminal on standard output, if it is one.
+pub fn terminal_size() -> Option<(usize, usize)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    let (rows, cols) = (size.ws_row as usize, size.ws_col as usize);
+    Some((rows, cols)).filter(|_| ok && rows > 0 && cols > 0)
+}
+
+/// The size the panes are laid out for, falling back to 24x80 on a missing or tiny terminal.
+fn pane_size() -> (usize, usize) {
+    terminal_size().filter(|&(rows, _)| rows >= 12).unwrap_or((24, 80))
+}